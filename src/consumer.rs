@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Consumer API of the controller, for drivers needing more than the reset framework
+//!
+//! The reset framework only asserts, deasserts, resets and reads a line. Drivers
+//! of the blocks that need counted holds, exclusive use, pins, batches or
+//! asynchronous resets look the controller up from one of their own `resets`
+//! specifiers with `Controller::get` and use it under their consumer name.
+//!
+//! Every call reading or changing a line runs as an op, like the framework ops:
+//! it waits for the controller to be registered (EAGAIN), powers its domain,
+//! refuses re-entrancy, honors the gate, pins, injected errors and the op
+//! timeout, and is recorded in `op_log` and the heartbeat.
+
+use kernel::{
+    bindings,
+    c_str,
+    clk::{Clk, EnabledClk},
+    device::RawDevice,
+    error,
+    prelude::*,
+    str::CStr,
+    sync::Arc,
+};
+
+use core::time::Duration;
+
+use crate::{dt, BstMap, BstResetDevice, LinePin, OpKind, ResetDeviceData};
+
+pub use crate::{BulkError, Phase, PhaseHook, PostReset, SharedPolicy};
+
+// Name the controller's platform driver registers under, to tell its devices
+// from those of other reset controllers
+const DRIVER_NAME: &CStr = c_str!("reset_bsta1000b");
+
+/// A reset controller as seen by one named consumer.
+///
+/// Ids are those of the consumer's `resets` specifiers, as `get` returns them,
+/// holds, acquisitions and pins belong to the consumer name. The controller
+/// data stays alive as long as the handle, once the controller is removed
+/// every op fails with EAGAIN.
+#[derive(Clone)]
+pub struct Controller {
+    data: Arc<ResetDeviceData>,
+    consumer: &'static CStr,
+}
+
+/// A line pinned by a consumer, see `Controller::pin`. Dropping it unpins the line.
+pub struct Pinned<'a>(LinePin<'a>);
+
+impl Pinned<'_> {
+    /// Unpin the line.
+    pub fn unpin(self) {
+        self.0.unpin()
+    }
+}
+
+impl Controller {
+    /// Look up the controller of the `index`th `resets` specifier of `dev`,
+    /// returning it with the id the specifier translates to. EPROBE_DEFER until
+    /// the controller has probed, ENODEV when another driver serves the specifier.
+    pub fn get(dev: &impl RawDevice, consumer: &'static CStr, index: u32) -> Result<(Self, u64)> {
+        let np = dt::of_node(dev);
+        if np.is_null() {
+            return Err(error::code::ENOENT);
+        }
+        let spec = dt::phandle_args(np, c_str!("resets"), c_str!("#reset-cells"), index)?;
+        let data = bound_to(spec.np);
+        // SAFETY: A successful parse holds a reference on `spec.np`.
+        unsafe { bindings::of_node_put(spec.np) };
+        let data = data?;
+        let cells = (spec.args_count.max(0) as usize).min(spec.args.len());
+        let rst_id = data.xlate(&spec.args[..cells])?;
+        Ok((Controller { data, consumer }, rst_id))
+    }
+
+    // A handle on controller data the driver already holds, e.g. in the selftest
+    #[cfg_attr(not(feature = "selftest"), allow(dead_code))]
+    pub(crate) fn new(data: Arc<ResetDeviceData>, consumer: &'static CStr) -> Self {
+        Controller { data, consumer }
+    }
+
+    /// Hold a line asserted. Holds are counted per consumer, the line is
+    /// released with the last hold of every consumer.
+    pub fn assert(&self, rst_id: u64) -> Result {
+        let consumer = self.consumer;
+        self.data.op_for(OpKind::Assert, Some(consumer), rst_id, |data, id| data.assert_for(consumer, id))
+    }
+
+    /// Release one hold of a line. Without a hold the deassert is unbalanced:
+    /// warned about once, or EINVAL in strict mode.
+    pub fn deassert(&self, rst_id: u64) -> Result {
+        let consumer = self.consumer;
+        self.data.op_for(OpKind::Deassert, Some(consumer), rst_id, |data, id| data.deassert_for(consumer, id))
+    }
+
+    /// Reset a line, following its `bst,shared-reset-policy` while held.
+    pub fn reset(&self, rst_id: u64) -> Result {
+        self.reset_shared(rst_id, None)
+    }
+
+    /// Reset a line, following `policy` rather than the line's while held.
+    pub fn reset_with_policy(&self, rst_id: u64, policy: SharedPolicy) -> Result {
+        self.reset_shared(rst_id, Some(policy))
+    }
+
+    fn reset_shared(&self, rst_id: u64, policy: Option<SharedPolicy>) -> Result {
+        let consumer = self.consumer;
+        let reset = |data: &BstMap, id| data.reset_for(consumer, id, policy);
+        self.data.op_for(OpKind::Reset, Some(consumer), rst_id, reset)
+    }
+
+    /// Whether a line is held in reset, EBUSY while a `reset()` of it runs.
+    pub fn status(&self, rst_id: u64) -> Result<bool> {
+        self.data.op_for(OpKind::Status, Some(self.consumer), rst_id, BstMap::reported_status)
+    }
+
+    /// Lines the consumer holds asserted, for leak checks at unbind.
+    pub fn held(&self) -> Result<Vec<usize>> {
+        self.data.lines_held_by(self.consumer)
+    }
+
+    /// Acquire a line exclusively and keep it asserted or deasserted until
+    /// `release`. EBUSY while other consumers hold or have acquired it.
+    pub fn acquire(&self, rst_id: u64, asserted: bool) -> Result {
+        let consumer = self.consumer;
+        let acquire = |data: &BstMap, id| data.acquire_exclusive(consumer, id, asserted);
+        self.data.op_for(OpKind::Acquire, Some(consumer), rst_id, acquire)
+    }
+
+    /// Release a line acquired with `acquire`, restoring the state it had.
+    pub fn release(&self, rst_id: u64) -> Result {
+        let consumer = self.consumer;
+        let release = |data: &BstMap, id| data.release_exclusive(consumer, id);
+        self.data.op_for(OpKind::Release, Some(consumer), rst_id, release)
+    }
+
+    /// Pin a line: until unpinned, changes of it by anyone but this consumer fail with EBUSY.
+    pub fn pin(&self, rst_id: u64) -> Result<Pinned<'_>> {
+        let data: &BstMap = &self.data;
+        data.pin(self.consumer, data.resolve(rst_id)).map(Pinned)
+    }
+
+    /// Flip a plain level line whatever its state, for test harnesses.
+    /// Returns whether the line is now held in reset.
+    pub fn toggle(&self, rst_id: u64) -> Result<bool> {
+        self.data.op_for(OpKind::Toggle, Some(self.consumer), rst_id, BstMap::toggle_line)
+    }
+
+    /// Drive several plain level lines to the given states at once, with one
+    /// update per register. Every id is checked before anything is written.
+    pub fn set_states(&self, states: &[(u64, bool)]) -> Result {
+        let mut ops = Vec::try_with_capacity(states.len())?;
+        for &(rst_id, asserted) in states {
+            ops.try_push((if asserted { OpKind::Assert } else { OpKind::Deassert }, rst_id))?;
+        }
+        self.data.op_batch(Some(self.consumer), &ops, |data, ids| {
+            let mut resolved = Vec::try_with_capacity(ids.len())?;
+            for (&id, &(_, asserted)) in ids.iter().zip(states) {
+                resolved.try_push((id, asserted))?;
+            }
+            data.set_states(&resolved)
+        })
+    }
+
+    /// Deassert lines in order. With `rollback`, a failure part way puts the
+    /// lines released so far back into reset.
+    pub fn deassert_bulk(&self, ids: &[u64], rollback: bool) -> core::result::Result<(), BulkError> {
+        self.data.deassert_bulk(Some(self.consumer), ids, rollback)
+    }
+
+    /// Enable a block's clock, let it settle, then release the block from reset.
+    pub fn enable_clock_then_deassert(&self, clk: Clk, rst_id: u64) -> Result<EnabledClk> {
+        let deassert = |data: &BstMap, id| data.enable_clock_then_deassert(clk, id);
+        self.data.op_for(OpKind::Deassert, Some(self.consumer), rst_id, deassert)
+    }
+
+    /// Enable a block's clock, release it from reset and wait for its ready bit.
+    /// A failure leaves the block in reset with its clock disabled.
+    pub fn bring_up(&self, clk: Clk, rst_id: u64) -> Result<EnabledClk> {
+        self.data.op_for(OpKind::Deassert, Some(self.consumer), rst_id, |data, id| data.bring_up(clk, id))
+    }
+
+    /// Drive a line into or out of reset and wait until its status reads so,
+    /// ETIMEDOUT after `timeout`.
+    pub fn drive_to(&self, rst_id: u64, asserted: bool, timeout: Duration) -> Result {
+        let kind = if asserted { OpKind::Assert } else { OpKind::Deassert };
+        self.data.op_for(kind, Some(self.consumer), rst_id, |data, id| data.drive_to(id, asserted, timeout))
+    }
+
+    /// Start the reset of a self-clearing line without waiting for it, see `reset_pending`.
+    pub fn start_async_reset(&self, rst_id: u64) -> Result {
+        self.data.op_for(OpKind::Assert, Some(self.consumer), rst_id, BstMap::start_async_reset)
+    }
+
+    /// Run the full `reset()` of a line on the system workqueue, see `reset_pending`.
+    pub fn async_reset(&self, rst_id: u64) -> Result {
+        self.data.async_reset(self.data.resolve(rst_id))
+    }
+
+    /// Whether a reset started by `start_async_reset` or `async_reset` is still in progress.
+    pub fn reset_pending(&self, rst_id: u64) -> Result<bool> {
+        self.data.op_for(OpKind::Status, Some(self.consumer), rst_id, BstMap::reset_pending)
+    }
+
+    /// Set the callback run after every successful `reset()` of a line, `None` removes it.
+    pub fn set_post_reset(&self, rst_id: u64, callback: Option<PostReset>) -> Result {
+        self.data.set_post_reset(self.data.resolve(rst_id), callback)
+    }
+
+    /// Set the hook run at `phase` of every `reset()` of the controller, `None` removes it.
+    pub fn set_phase_hook(&self, phase: Phase, hook: Option<PhaseHook>) {
+        self.data.set_phase_hook(phase, hook)
+    }
+
+    /// Tag every op of the controller with `txn` until `end_transaction`.
+    pub fn begin_transaction(&self, txn: u32) -> Result {
+        self.data.begin_transaction(txn)
+    }
+
+    /// Close the transaction opened with `txn`.
+    pub fn end_transaction(&self, txn: u32) -> Result {
+        self.data.end_transaction(txn)
+    }
+
+    /// Switch every line to the other reset map, from `bst,alternate-map`.
+    pub fn swap_map(&self) -> Result {
+        self.data.swap_map()
+    }
+
+    /// Whether an id names a line ops can reach.
+    pub fn is_supported(&self, rst_id: u64) -> bool {
+        self.data.is_supported(rst_id)
+    }
+}
+
+// Controller data of the device bound to `np`, with its device lock held so
+// it cannot unbind meanwhile
+fn bound_to(np: *mut bindings::device_node) -> Result<Arc<ResetDeviceData>> {
+    // SAFETY: `np` is a valid node.
+    let pdev = unsafe { bindings::of_find_device_by_node(np) };
+    if pdev.is_null() {
+        return Err(error::code::EPROBE_DEFER);
+    }
+    // SAFETY: `of_find_device_by_node` holds a reference on the device until it is put below.
+    let dev = unsafe { &mut (*pdev).dev };
+    // SAFETY: The lock keeps the driver bound while its data is read. Bound to
+    // this driver, the driver data is the `BstResetDevice` probe returned.
+    let data = unsafe {
+        bindings::device_lock(dev);
+        let driver = dev.driver;
+        let drvdata = bindings::dev_get_drvdata(dev) as *const BstResetDevice;
+        let data = if driver.is_null() || drvdata.is_null() {
+            Err(error::code::EPROBE_DEFER)
+        } else if CStr::from_char_ptr((*driver).name).as_bytes() != DRIVER_NAME.as_bytes() {
+            Err(error::code::ENODEV)
+        } else {
+            Ok((*drvdata).data.clone())
+        };
+        bindings::device_unlock(dev);
+        data
+    };
+    // SAFETY: Drops the reference taken by `of_find_device_by_node`.
+    unsafe { bindings::put_device(dev) };
+    data
+}
@@ -0,0 +1,593 @@
+// SPDX-License-Identifier: GPL-2.0
+//! debugfs interface of the reset controller
+
+use kernel::{
+    bindings,
+    error,
+    prelude::*,
+    str::CStr,
+};
+
+use core::{
+    ffi::{c_char, c_int, c_void},
+    fmt::{self, Write},
+    marker::PhantomData,
+    sync::atomic::Ordering,
+};
+
+use crate::{ktime_get_ns, same_consumer, Line, ResetDeviceData};
+#[cfg(feature = "mmio-trace")]
+use crate::{mmio_trace, MAX_RESET_REGISTERS};
+#[cfg(any(debug_assertions, feature = "replay"))]
+use crate::OpKind;
+#[cfg(feature = "replay")]
+use crate::{ReplayStep, MAX_REPLAY_STEPS};
+
+// A debugfs file backed by the controller data
+pub(crate) trait File {
+    // Render the file contents
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result;
+
+    // Handle a write, read-only files reject it
+    fn store(_data: &ResetDeviceData, _input: &[u8]) -> Result {
+        Err(error::code::EPERM)
+    }
+}
+
+// Output buffer of a debugfs read
+pub(crate) struct SeqFile(*mut bindings::seq_file);
+
+impl fmt::Write for SeqFile {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // SAFETY: `self.0` is the seq_file of the read in progress.
+        unsafe { bindings::seq_write(self.0, s.as_ptr() as *const c_void, s.len()) };
+        Ok(())
+    }
+}
+
+// Largest write accepted by a debugfs file
+const MAX_INPUT: usize = 64;
+
+// SAFETY: Called by the seq_file core with the `private` pointer installed by `open`.
+unsafe extern "C" fn show_callback<T: File>(m: *mut bindings::seq_file, _v: *mut c_void) -> c_int {
+    // SAFETY: `private` is the `ResetDeviceData` passed to `Dir::create_file`, which
+    // outlives the directory.
+    let data = unsafe { &*((*m).private as *const ResetDeviceData) };
+    match T::show(data, &mut SeqFile(m)) {
+        Ok(()) => 0,
+        Err(e) => e.to_kernel_errno(),
+    }
+}
+
+// SAFETY: Called by the VFS with a valid inode and file.
+unsafe extern "C" fn open_callback<T: File>(inode: *mut bindings::inode, file: *mut bindings::file) -> c_int {
+    // SAFETY: `i_private` holds the data pointer passed to `debugfs_create_file`.
+    unsafe { bindings::single_open(file, Some(show_callback::<T>), (*inode).i_private) }
+}
+
+// SAFETY: Called by the VFS with a valid file and a user buffer of `count` bytes.
+unsafe extern "C" fn write_callback<T: File>(
+    file: *mut bindings::file,
+    ubuf: *const c_char,
+    count: usize,
+    ppos: *mut bindings::loff_t,
+) -> isize {
+    let mut buf = [0u8; MAX_INPUT];
+    let mut pos: bindings::loff_t = 0;
+    // SAFETY: `buf` is a valid kernel buffer and `ubuf` a user buffer of `count` bytes.
+    let len = unsafe {
+        bindings::simple_write_to_buffer(
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len(),
+            &mut pos,
+            ubuf as *const c_void,
+            count,
+        )
+    };
+    if len < 0 {
+        return len;
+    }
+
+    // SAFETY: `i_private` holds the data pointer passed to `debugfs_create_file`.
+    let data = unsafe { &*((*(*file).f_inode).i_private as *const ResetDeviceData) };
+    match T::store(data, &buf[..len as usize]) {
+        Ok(()) => {
+            // SAFETY: `ppos` is the valid file position of this write.
+            unsafe { *ppos += count as bindings::loff_t };
+            count as isize
+        }
+        Err(e) => e.to_kernel_errno() as isize,
+    }
+}
+
+// file_operations with every callback unset
+const ZEROED_FOPS: bindings::file_operations =
+    // SAFETY: All-zero bytes are a valid `file_operations`: null pointers and `None` callbacks.
+    unsafe { core::mem::transmute([0u8; core::mem::size_of::<bindings::file_operations>()]) };
+
+struct Fops<T>(PhantomData<T>);
+
+impl<T: File> Fops<T> {
+    const OPS: bindings::file_operations = bindings::file_operations {
+        open: Some(open_callback::<T>),
+        read: Some(bindings::seq_read),
+        write: Some(write_callback::<T>),
+        llseek: Some(bindings::seq_lseek),
+        release: Some(bindings::single_release),
+        ..ZEROED_FOPS
+    };
+}
+
+// A debugfs directory, removed together with its files on drop
+pub(crate) struct Dir(*mut bindings::dentry);
+
+impl Dir {
+    // Create a top level directory, debugfs failures are not fatal so errors are not reported
+    pub(crate) fn new(name: &CStr) -> Self {
+        // SAFETY: `name` is NUL terminated and a null parent means the debugfs root.
+        Dir(unsafe { bindings::debugfs_create_dir(name.as_char_ptr(), core::ptr::null_mut()) })
+    }
+
+    // Create a file whose callbacks receive `data`, which must outlive the directory
+    pub(crate) fn create_file<T: File>(&self, name: &CStr, mode: u16, data: &ResetDeviceData) {
+        // SAFETY: `self.0` is a directory created by `new`, `name` is NUL terminated and
+        // the ops table is a constant that lives forever.
+        unsafe {
+            bindings::debugfs_create_file(
+                name.as_char_ptr(),
+                mode,
+                self.0,
+                data as *const ResetDeviceData as *mut c_void,
+                &Fops::<T>::OPS,
+            )
+        };
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        // SAFETY: `self.0` was returned by `debugfs_create_dir`, removal accepts error values.
+        unsafe { bindings::debugfs_remove(self.0) };
+    }
+}
+
+// SAFETY: The dentry pointer is only used to create and remove files, both thread safe in debugfs.
+unsafe impl Send for Dir {}
+// SAFETY: `Dir` has no interior mutability.
+unsafe impl Sync for Dir {}
+
+// debugfs `retries`: per-line write retry counters. Write "all" to clear every
+// line's counters or an id to clear just that line's.
+pub(crate) struct RetriesFile;
+
+impl File for RetriesFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        writeln!(out, "id attempted succeeded")?;
+        for (rst_id, _) in data.defined() {
+            let line = match data.line(rst_id) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            let stats = &data.state[rst_id];
+            writeln!(
+                out,
+                "{} {} {}",
+                line,
+                stats.retries_attempted.load(Ordering::Relaxed),
+                stats.retries_succeeded.load(Ordering::Relaxed),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let states = match input.trim() {
+            "all" => &data.state[..],
+            id => {
+                let rst_id: usize = id.parse().map_err(|_| error::code::EINVAL)?;
+                let state = data.state.get(rst_id).ok_or(error::code::EINVAL)?;
+                core::slice::from_ref(state)
+            }
+        };
+        // The counters are updated with `reg_lock` held, so no op lands half way
+        data.with_reg_lock(|| {
+            for state in states {
+                state.retries_attempted.store(0, Ordering::Relaxed);
+                state.retries_succeeded.store(0, Ordering::Relaxed);
+            }
+        });
+        Ok(())
+    }
+}
+
+// debugfs `lines`: every defined line with its window, register offset, bit,
+// flags by name and the ops it accepts
+pub(crate) struct LinesFile;
+
+impl File for LinesFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let _power = data.power()?;
+        for (rst_id, _) in data.defined() {
+            if let Ok(line) = data.line(rst_id) {
+                // `line` only succeeds for lines in a present window
+                let window = line.cfg.window.unwrap_or(0);
+                let offset = line.addr() - data.window_base(window).unwrap_or(0);
+                write!(out, "{} {}:{:#x} {} ", line, window, offset, line.cfg.bit_idx)?;
+                writeln!(out, "{} {}", line.flags(), data.line_ops(line))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// debugfs `status.jsonl`: one JSON object per defined line in a present window,
+// for scripts. The keys and values are stable:
+// {"id":12,"name":"gpu","status":"asserted","flags":"INVERTED|SELF_CLEARING"}
+// with `name` null for unnamed lines, `status` "asserted", "deasserted" or
+// "unknown" when it cannot be read, and `flags` as in `lines`.
+pub(crate) struct StatusJsonFile;
+
+impl File for StatusJsonFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let _power = data.power()?;
+        for (rst_id, _) in data.defined() {
+            let line = match data.line(rst_id) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            write!(out, "{{\"id\":{},\"name\":", rst_id)?;
+            match line.cfg.name {
+                Some(name) => write_json_str(out, name.as_bytes())?,
+                None => write!(out, "null")?,
+            }
+            let status = match data.line_status(rst_id) {
+                Ok(true) => "asserted",
+                Ok(false) => "deasserted",
+                Err(_) => "unknown",
+            };
+            writeln!(out, ",\"status\":\"{}\",\"flags\":\"{}\"}}", status, line.flags())?;
+        }
+        Ok(())
+    }
+}
+
+// Write bytes as a JSON string, escaping what JSON requires
+fn write_json_str(out: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    out.write_char('"')?;
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => write!(out, "\\{}", b as char)?,
+            0x20..=0x7e => out.write_char(b as char)?,
+            _ => write!(out, "\\u{:04x}", b)?,
+        }
+    }
+    out.write_char('"')
+}
+
+// debugfs `snapshot`: the whole configuration at once, see `BstMap::snapshot`
+pub(crate) struct SnapshotFile;
+
+impl File for SnapshotFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let _power = data.power()?;
+        data.snapshot(out)
+    }
+}
+
+// debugfs `last_reset`: time since the last successful `reset()` of each line that was
+// ever reset, as "id ms"
+pub(crate) struct LastResetFile;
+
+impl File for LastResetFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let now = ktime_get_ns() as u64;
+        for (rst_id, state) in data.state.iter().enumerate() {
+            let last = state.last_reset_ns.load(Ordering::Relaxed);
+            if last != 0 {
+                writeln!(out, "{} {}", rst_id, now.saturating_sub(last) / 1_000_000)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// debugfs `map.csv`: the whole map for analysis tools, one line per defined
+// line as "id,name,window,phys_addr,bit,width,flags" after a header row. Names
+// come from `reset-names` and are quoted when needed, see `write_csv_field`.
+pub(crate) struct MapCsvFile;
+
+impl File for MapCsvFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        writeln!(out, "id,name,window,phys_addr,bit,width,flags")?;
+        for (rst_id, map) in data.defined() {
+            let cfg = &data.lines()[rst_id];
+            let (window, phys_addr) = match (cfg.window, data.phys_addr(cfg)) {
+                (Some(window), Some(phys_addr)) => (window, phys_addr),
+                _ => continue,
+            };
+            // Built by hand as lazy lines not mapped yet have no `Line`, `addr` is unused
+            let line = Line { id: rst_id, map, cfg, addr: cfg.addr };
+            write!(out, "{},", rst_id)?;
+            if let Some(name) = cfg.name {
+                write_csv_field(out, name.as_bytes())?;
+            }
+            write!(out, ",{},{:#x},{},", window, phys_addr, cfg.bit_idx)?;
+            writeln!(out, "{},{}", cfg.ctrl_width.bits(), line.flags())?;
+        }
+        Ok(())
+    }
+}
+
+// Write bytes as a CSV field, as RFC 4180 has it: quoted when they hold a
+// separator, a quote or a line break, with quotes doubled. Bytes that are not
+// printable ASCII are written as `\xNN`, like `CStr` displays them.
+pub(crate) fn write_csv_field(out: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    let quoted = bytes.iter().any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r'));
+    if quoted {
+        out.write_char('"')?;
+    }
+    for &b in bytes {
+        match b {
+            b'"' => out.write_str("\"\"")?,
+            0x20..=0x7e | b'\n' | b'\r' => out.write_char(b as char)?,
+            _ => write!(out, "\\x{:02x}", b)?,
+        }
+    }
+    if quoted {
+        out.write_char('"')?;
+    }
+    Ok(())
+}
+
+// debugfs `holders`: lines held through the consumer-tracked API, one consumer per row
+pub(crate) struct HoldersFile;
+
+impl File for HoldersFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let mut consumers: Vec<&'static CStr> = Vec::new();
+        for hold in data.holds.lock().iter() {
+            if !consumers.iter().any(|consumer| same_consumer(consumer, hold.consumer)) {
+                consumers.try_push(hold.consumer)?;
+            }
+        }
+        for consumer in consumers {
+            write!(out, "{}:", consumer)?;
+            for id in data.lines_held_by(consumer)? {
+                write!(out, " {}", id)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+// debugfs `trace_op`: write "assert <id>" or "deassert <id>", then read the
+// register value before and after the change
+#[cfg(debug_assertions)]
+pub(crate) struct TraceOpFile;
+
+#[cfg(debug_assertions)]
+impl File for TraceOpFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let trace = &data.last_trace;
+        let id = trace.id.load(Ordering::Relaxed);
+        if id != 0 {
+            writeln!(
+                out,
+                "{} {:#010x} {:#010x}",
+                id - 1,
+                trace.before.load(Ordering::Relaxed),
+                trace.after.load(Ordering::Relaxed),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let mut words = input.split_whitespace();
+        let asserted = match words.next() {
+            Some("assert") => true,
+            Some("deassert") => false,
+            _ => return Err(error::code::EINVAL),
+        };
+        let rst_id: u32 = words.next().and_then(|id| id.parse().ok()).ok_or(error::code::EINVAL)?;
+        let kind = if asserted { OpKind::Assert } else { OpKind::Deassert };
+        // Through `op` like any other write, so the gate, the pins and the op
+        // log see a traced write too
+        let (before, after) = data.op(kind, rst_id.into(), |data, id| data.drive_traced(id, asserted))?;
+        let trace = &data.last_trace;
+        trace.before.store(before, Ordering::Relaxed);
+        trace.after.store(after, Ordering::Relaxed);
+        trace.id.store(rst_id + 1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+// debugfs `replay`: write "<assert|deassert|reset> <id> <delay_ms>" to append a
+// step, "run" to run the loaded steps in order or "clear" to drop them. Reads
+// list the steps as "index op id delay_ms result", result "-" before a run.
+#[cfg(feature = "replay")]
+pub(crate) struct ReplayFile;
+
+#[cfg(feature = "replay")]
+impl File for ReplayFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        for (index, step) in data.replay.lock().iter().enumerate() {
+            write!(out, "{} {} {} {} ", index, step.kind.name(), step.id, step.delay_ms)?;
+            match step.result {
+                Some(result) => writeln!(out, "{}", result)?,
+                None => writeln!(out, "-")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let mut words = input.split_whitespace();
+        let kind = match words.next() {
+            Some("run") => return data.replay(),
+            Some("clear") => {
+                data.replay.lock().clear();
+                return Ok(());
+            }
+            Some("assert") => OpKind::Assert,
+            Some("deassert") => OpKind::Deassert,
+            Some("reset") => OpKind::Reset,
+            _ => return Err(error::code::EINVAL),
+        };
+        let mut next = || words.next().and_then(|word| word.parse().ok()).ok_or(error::code::EINVAL);
+        let (id, delay_ms): (u32, u32) = (next()?, next()?);
+        if !data.is_supported(id.into()) {
+            fail!(data.dev, EINVAL, "Replay step names unsupported reset ID {}\n", id);
+        }
+        let mut steps = data.replay.lock();
+        if steps.len() == MAX_REPLAY_STEPS {
+            return Err(error::code::ENOSPC);
+        }
+        steps.try_push(ReplayStep { kind, id: id.into(), delay_ms, result: None })?;
+        Ok(())
+    }
+}
+
+// debugfs `op_log`: most recent ops, oldest first, as "time_ns id op result"
+pub(crate) struct OpLogFile;
+
+impl File for OpLogFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let log = data.op_log.lock_irqdisable();
+        for record in log.iter() {
+            writeln!(
+                out,
+                "{} {} {} {} {}",
+                record.time_ns,
+                record.id,
+                record.kind.name(),
+                record.result,
+                record.txn
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// debugfs `mmio_trace`: the last register accesses within this instance's
+// windows, oldest first, as "seq r|w width phys_addr value"
+#[cfg(feature = "mmio-trace")]
+pub(crate) struct MmioTraceFile;
+
+#[cfg(feature = "mmio-trace")]
+impl File for MmioTraceFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        mmio_trace::for_each(|access| {
+            // The ring is shared by every instance, skip the other instances' registers
+            let phys_addr = (0..MAX_RESET_REGISTERS).find_map(|window| {
+                let offset = access.addr.checked_sub(data.window_base(window)?)?;
+                if offset >= data.window_size[window] {
+                    return None;
+                }
+                Some(data.phys_base[window]? + offset as u64)
+            });
+            let phys_addr = match phys_addr {
+                Some(phys_addr) => phys_addr,
+                None => return Ok(()),
+            };
+            let dir = if access.dir == mmio_trace::Dir::Write { "w" } else { "r" };
+            writeln!(out, "{} {} {} {:#x} {:#x}", access.seq, dir, access.bits, phys_addr, access.val)?;
+            Ok(())
+        })
+    }
+}
+
+// debugfs `inject_error`: write "<id> <EIO|ETIMEDOUT|EPERM>" to make the next op
+// on that line fail with that errno, read the armed errors back
+#[cfg(feature = "error-injection")]
+pub(crate) struct InjectErrorFile;
+
+#[cfg(feature = "error-injection")]
+impl File for InjectErrorFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        for (rst_id, state) in data.state.iter().enumerate() {
+            let errno = state.injected.load(Ordering::Relaxed);
+            if errno != 0 {
+                writeln!(out, "{} {}", rst_id, errno)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let mut words = input.split_whitespace();
+        let rst_id: usize = words.next().and_then(|id| id.parse().ok()).ok_or(error::code::EINVAL)?;
+        let errno = match words.next() {
+            Some("EIO") => error::code::EIO,
+            Some("ETIMEDOUT") => error::code::ETIMEDOUT,
+            Some("EPERM") => error::code::EPERM,
+            _ => return Err(error::code::EINVAL),
+        };
+        let state = data.state.get(rst_id).ok_or(error::code::EINVAL)?;
+        state.injected.store(errno.to_kernel_errno(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+// debugfs `recover`: write anything to reset the controller itself and restore its setup
+pub(crate) struct RecoverFile;
+
+impl File for RecoverFile {
+    fn show(_data: &ResetDeviceData, _out: &mut SeqFile) -> Result {
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, _input: &[u8]) -> Result {
+        data.recover()
+    }
+}
+
+// debugfs `registers`: physical base of every register window
+pub(crate) struct RegistersFile;
+
+impl File for RegistersFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        for index in 0..data.phys_base.len() {
+            if let Ok(base) = data.register_phys_base(index) {
+                writeln!(out, "{} {:#x}", index, base)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// debugfs `reset_source`: read the latched reset-source bits, write "clear" to
+// read and clear them, or "clear <bit>" to clear only that bit
+pub(crate) struct ResetSourceFile;
+
+impl File for ResetSourceFile {
+    fn show(data: &ResetDeviceData, out: &mut SeqFile) -> Result {
+        let _power = data.power()?;
+        writeln!(out, "{:#010x}", data.read_reset_source(false)?)?;
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let mut words = input.split_whitespace();
+        if words.next() != Some("clear") {
+            return Err(error::code::EINVAL);
+        }
+        let mask = match words.next() {
+            Some(bit) => match bit.parse::<u32>() {
+                Ok(bit) if bit < 32 => 1 << bit,
+                _ => return Err(error::code::EINVAL),
+            },
+            None => u32::MAX,
+        };
+        let _power = data.power()?;
+        let latched = data.clear_reset_source(mask)?;
+        dev_info!(data.dev, "Cleared reset source {:#010x}\n", latched & mask);
+        Ok(())
+    }
+}
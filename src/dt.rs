@@ -0,0 +1,836 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Firmware property helpers for the reset controller node, and the parsers of
+//! the properties describing the lines and the board
+//!
+//! Properties come from the device tree node, or from the `_DSD` properties of
+//! the ACPI companion on ACPI systems, which have no device tree node.
+
+use kernel::{
+    bindings,
+    c_str,
+    device::RawDevice,
+    error,
+    platform,
+    prelude::*,
+    str::CStr,
+};
+
+use core::sync::atomic::AtomicBool;
+
+use bst_reset_rust::BstResetManager;
+
+use crate::{
+    defined_lines, HoldTimes, IdAlias, LineConfig, MaxAssert, PostWrite, Quirks, RegBit, ReleaseStage, SetClr,
+    SharedPolicy, StatusReg, Width, LINE_ASSERT_ONLY, LINE_COMPLETION_IRQ, LINE_DEASSERT_ONLY,
+    LINE_EDGE_TRIGGERED, LINE_INVERTED, LINE_PROTECTED, LINE_RESTRICTED, LINE_SELF_CLEARING,
+    LINE_STATUS_INVERTED, MAX_RESET_REGISTERS,
+};
+
+// Get the device tree node backing the device, null if probed without DT
+pub(crate) fn of_node(dev: &impl RawDevice) -> *mut bindings::device_node {
+    // SAFETY: `raw_device` returns a pointer that is valid for the lifetime of `dev`.
+    unsafe { (*dev.raw_device()).of_node }
+}
+
+// Child node of the device tree node by name, with a reference held, null if absent
+pub(crate) fn child(dev: &impl RawDevice, name: &CStr) -> *mut bindings::device_node {
+    let np = of_node(dev);
+    if np.is_null() {
+        return np;
+    }
+    // SAFETY: `np` is a valid node and `name` is NUL terminated.
+    unsafe { bindings::of_get_child_by_name(np, name.as_char_ptr()) }
+}
+
+// First argument of the first phandle in a `list` property of `np`, e.g. the
+// reset id of `resets = <&rstc 12>`
+pub(crate) fn phandle_arg(np: *mut bindings::device_node, list: &CStr, cells: &CStr) -> Result<u32> {
+    let args = phandle_args(np, list, cells, 0)?;
+    // SAFETY: A successful parse holds a reference on `args.np`.
+    unsafe { bindings::of_node_put(args.np) };
+    if args.args_count < 1 {
+        return Err(error::code::EINVAL);
+    }
+    Ok(args.args[0])
+}
+
+// The `index`th phandle in a `list` property of `np` with its arguments. The
+// caller puts the reference held on the node it points at.
+pub(crate) fn phandle_args(
+    np: *mut bindings::device_node,
+    list: &CStr,
+    cells: &CStr,
+    index: u32,
+) -> Result<bindings::of_phandle_args> {
+    // SAFETY: All-zero bytes are a valid `of_phandle_args`.
+    let mut args: bindings::of_phandle_args = unsafe { core::mem::zeroed() };
+    // SAFETY: `np` is a valid node, the names are NUL terminated and `args` is writable.
+    let ret = unsafe {
+        let (list, cells) = (list.as_char_ptr(), cells.as_char_ptr());
+        bindings::of_parse_phandle_with_args(np, list, cells, index as i32, &mut args)
+    };
+    if ret < 0 {
+        return Err(error::Error::from_kernel_errno(ret));
+    }
+    Ok(args)
+}
+
+// Whether the properties come from ACPI `_DSD` rather than a device tree node
+pub(crate) fn is_acpi(dev: &impl RawDevice) -> bool {
+    // SAFETY: `raw_device` returns a pointer that is valid for the lifetime of `dev`.
+    of_node(dev).is_null() && !unsafe { (*dev.raw_device()).fwnode }.is_null()
+}
+
+// Refuse prerequisites that form a cycle, the lines on it could never be
+// deasserted. A depth-first walk from every line: reaching a line that is still
+// on the walk's path closes a cycle.
+pub(crate) fn check_prerequisite_cycles(pdev: &platform::Device, lines: &[LineConfig]) -> Result {
+    const UNVISITED: u8 = 0;
+    const ON_PATH: u8 = 1;
+    const DONE: u8 = 2;
+    let mut state = Vec::try_with_capacity(lines.len())?;
+    for _ in lines {
+        state.try_push(UNVISITED)?;
+    }
+    // Lines of the walk's path, each with the index of its next prerequisite
+    let mut path: Vec<(usize, usize)> = Vec::new();
+    for start in 0..lines.len() {
+        if state[start] != UNVISITED {
+            continue;
+        }
+        state[start] = ON_PATH;
+        path.try_push((start, 0))?;
+        while let Some(top) = path.last_mut() {
+            let (id, next) = *top;
+            let prereq = match lines[id].prerequisites.get(next) {
+                Some(&(prereq, _)) => prereq,
+                None => {
+                    state[id] = DONE;
+                    path.pop();
+                    continue;
+                }
+            };
+            top.1 += 1;
+            match state.get(prereq) {
+                Some(&UNVISITED) => {
+                    state[prereq] = ON_PATH;
+                    path.try_push((prereq, 0))?;
+                }
+                Some(&ON_PATH) => {
+                    fail!(pdev, EINVAL, "Reset ids {} and {} are on a prerequisite cycle\n", prereq, id);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Settings only ACPI variants describe, in `_DSD`
+pub(crate) struct AcpiConfig {
+    // Reset hold time from `bst,hold-time-ms`
+    pub(crate) hold_time_ms: Option<u64>,
+    // Mask of the register windows in `_CRS`, from `bst,num-windows`
+    pub(crate) windows: Option<u32>,
+}
+
+// Parse the ACPI-only properties with `read`, which reads a u32 property of the
+// device, `read_u32` at probe
+pub(crate) fn acpi_config(
+    pdev: &platform::Device,
+    read: impl Fn(&CStr) -> Option<u32>,
+) -> Result<AcpiConfig> {
+    let hold_time_ms = read(c_str!("bst,hold-time-ms")).map(u64::from);
+    let windows = match read(c_str!("bst,num-windows")) {
+        Some(count) if count == 0 || count as usize > MAX_RESET_REGISTERS => {
+            fail!(pdev, EINVAL, "Invalid bst,num-windows {}\n", count);
+        }
+        Some(count) => Some((1u32 << count) - 1),
+        None => None,
+    };
+    Ok(AcpiConfig { hold_time_ms, windows })
+}
+
+// Check whether a boolean property is present on the node
+pub(crate) fn read_bool(dev: &impl RawDevice, name: &CStr) -> bool {
+    let np = of_node(dev);
+    if np.is_null() {
+        // SAFETY: The device is valid and `name` is NUL terminated.
+        return unsafe { bindings::device_property_present(dev.raw_device(), name.as_char_ptr()) };
+    }
+    // SAFETY: `np` is a valid node and `name` is NUL terminated.
+    let prop = unsafe { bindings::of_find_property(np, name.as_char_ptr(), core::ptr::null_mut()) };
+    !prop.is_null()
+}
+
+// Read an optional list of u32 cells, an absent property yields an empty list
+pub(crate) fn read_u32_list(dev: &impl RawDevice, name: &CStr) -> Result<Vec<u32>> {
+    let mut list = Vec::new();
+    let np = of_node(dev);
+
+    let count = if np.is_null() {
+        // SAFETY: The device is valid, `name` is NUL terminated and a null buffer only counts.
+        unsafe {
+            bindings::device_property_read_u32_array(
+                dev.raw_device(),
+                name.as_char_ptr(),
+                core::ptr::null_mut(),
+                0,
+            )
+        }
+    } else {
+        // SAFETY: `np` is a valid node and `name` is NUL terminated.
+        unsafe { bindings::of_property_count_elems_of_size(np, name.as_char_ptr(), 4) }
+    };
+    if count <= 0 {
+        return Ok(list);
+    }
+
+    for _ in 0..count {
+        list.try_push(0u32)?;
+    }
+    let ret = if np.is_null() {
+        // SAFETY: `list` has room for exactly `count` cells.
+        unsafe {
+            bindings::device_property_read_u32_array(
+                dev.raw_device(),
+                name.as_char_ptr(),
+                list.as_mut_ptr(),
+                count as usize,
+            )
+        }
+    } else {
+        // SAFETY: `list` has room for exactly `count` cells.
+        unsafe {
+            bindings::of_property_read_variable_u32_array(
+                np,
+                name.as_char_ptr(),
+                list.as_mut_ptr(),
+                count as usize,
+                count as usize,
+            )
+        }
+    };
+    if ret < 0 {
+        return Err(error::Error::from_kernel_errno(ret));
+    }
+    Ok(list)
+}
+
+// Read an optional u32 property
+pub(crate) fn read_u32(dev: &impl RawDevice, name: &CStr) -> Option<u32> {
+    let np = of_node(dev);
+    let mut val = 0u32;
+
+    let ret = if np.is_null() {
+        // SAFETY: The device is valid, `name` is NUL terminated and `val` holds one cell.
+        unsafe { bindings::device_property_read_u32_array(dev.raw_device(), name.as_char_ptr(), &mut val, 1) }
+    } else {
+        // SAFETY: `np` is a valid node, `name` is NUL terminated and `val` holds one cell.
+        unsafe { bindings::of_property_read_variable_u32_array(np, name.as_char_ptr(), &mut val, 1, 1) }
+    };
+    if ret < 0 {
+        return None;
+    }
+    Some(val)
+}
+
+// Read an optional string list, the strings live as long as the firmware node
+pub(crate) fn read_string_list(dev: &impl RawDevice, name: &CStr) -> Result<Vec<&'static CStr>> {
+    let mut list = Vec::new();
+    let np = of_node(dev);
+    if np.is_null() {
+        return read_fwnode_string_list(dev, name);
+    }
+
+    // SAFETY: `np` is a valid node and `name` is NUL terminated, a null output only counts.
+    let count = unsafe {
+        bindings::of_property_read_string_helper(np, name.as_char_ptr(), core::ptr::null_mut(), 0, 0)
+    };
+    for index in 0..count.max(0) {
+        let mut out: *const core::ffi::c_char = core::ptr::null();
+        // SAFETY: `np` is a valid node, `name` is NUL terminated and `out` holds one string.
+        let ret = unsafe {
+            bindings::of_property_read_string_helper(np, name.as_char_ptr(), &mut out, 1, index)
+        };
+        if ret < 0 {
+            return Err(error::Error::from_kernel_errno(ret));
+        }
+        // SAFETY: The property value is a NUL terminated string owned by the node.
+        list.try_push(unsafe { CStr::from_char_ptr(out) })?;
+    }
+    Ok(list)
+}
+
+// String list of a device without a device tree node
+fn read_fwnode_string_list(dev: &impl RawDevice, name: &CStr) -> Result<Vec<&'static CStr>> {
+    let mut list = Vec::new();
+    // SAFETY: The device is valid, `name` is NUL terminated and a null buffer only counts.
+    let count = unsafe {
+        bindings::device_property_read_string_array(dev.raw_device(), name.as_char_ptr(), core::ptr::null_mut(), 0)
+    };
+    if count <= 0 {
+        return Ok(list);
+    }
+
+    let mut ptrs = Vec::try_with_capacity(count as usize)?;
+    for _ in 0..count {
+        ptrs.try_push(core::ptr::null::<core::ffi::c_char>())?;
+    }
+    // SAFETY: `ptrs` has room for exactly `count` strings.
+    let ret = unsafe {
+        bindings::device_property_read_string_array(
+            dev.raw_device(),
+            name.as_char_ptr(),
+            ptrs.as_mut_ptr(),
+            count as usize,
+        )
+    };
+    if ret < 0 {
+        return Err(error::Error::from_kernel_errno(ret));
+    }
+    for ptr in ptrs {
+        // SAFETY: The property value is a NUL terminated string owned by the firmware node.
+        list.try_push(unsafe { CStr::from_char_ptr(ptr) })?;
+    }
+    Ok(list)
+}
+
+// Read `bst,set-clr-windows` as (window, set offset, clear offset) tuples
+pub(crate) fn parse_set_clr(
+    pdev: &platform::Device,
+    bst_address: &[Option<*mut u8>],
+) -> Result<[Option<SetClr>; MAX_RESET_REGISTERS]> {
+    let mut set_clr = [None; MAX_RESET_REGISTERS];
+    let cells = read_u32_list(pdev, c_str!("bst,set-clr-windows"))?;
+    if cells.len() % 3 != 0 {
+        fail!(pdev, EINVAL, "bst,set-clr-windows must hold (window, set, clr) tuples\n");
+    }
+    for tuple in cells.chunks_exact(3) {
+        let window = tuple[0] as usize;
+        let valid = matches!(bst_address.get(window), Some(Some(_)))
+            && tuple[1] % 4 == 0
+            && tuple[2] % 4 == 0;
+        if !valid {
+            fail!(pdev, EINVAL, "Invalid SET/CLR description for window {}\n", window);
+        }
+        set_clr[window] = Some(SetClr { set_offset: tuple[1] as usize, clr_offset: tuple[2] as usize });
+    }
+    Ok(set_clr)
+}
+
+// Place the lines of a single-region controller from `bst,line-offsets`
+// (id, byte offset) pairs: their control register is the region base plus the
+// offset, which must leave room for a whole register within the region
+pub(crate) fn apply_line_offsets(
+    pdev: &platform::Device,
+    manager: &BstResetManager,
+    lines: &mut [LineConfig],
+    cells: &[u32],
+    base: usize,
+    size: usize,
+) -> Result {
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,line-offsets must hold (id, offset) pairs\n");
+    }
+    for pair in cells.chunks_exact(2) {
+        let (id, offset) = (pair[0] as usize, pair[1] as usize);
+        let cfg = match (manager.bsta1000b_map.get(id), lines.get_mut(id)) {
+            (Some(Some(_)), Some(cfg)) => cfg,
+            _ => fail!(pdev, EINVAL, "bst,line-offsets names undefined reset ID {}\n", id),
+        };
+        let bytes = (cfg.ctrl_width.bits() / 8) as usize;
+        if offset % bytes != 0 || offset.checked_add(bytes).map_or(true, |end| end > size) {
+            fail!(
+                pdev,
+                EINVAL,
+                "Reset ID {} offset {:#x} does not fit the {:#x} byte register region\n",
+                id,
+                offset,
+                size
+            );
+        }
+        cfg.addr = base + offset;
+        cfg.window = Some(0);
+    }
+    Ok(())
+}
+
+// Build the alternate map from `bst,alternate-map` (id, reg index, byte offset,
+// bit) tuples moving lines to another register bit. The register must be in a
+// window mapped at probe, lines not listed stay where the primary map has them.
+pub(crate) fn apply_alternate_map(
+    pdev: &platform::Device,
+    manager: &BstResetManager,
+    lines: &mut [LineConfig],
+    cells: &[u32],
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+) -> Result {
+    if cells.len() % 4 != 0 {
+        fail!(pdev, EINVAL, "bst,alternate-map must hold (id, reg, offset, bit) tuples\n");
+    }
+    for tuple in cells.chunks_exact(4) {
+        let (id, window, offset) = (tuple[0] as usize, tuple[1] as usize, tuple[2] as usize);
+        let cfg = match (manager.bsta1000b_map.get(id), lines.get_mut(id)) {
+            (Some(Some(_)), Some(cfg)) => cfg,
+            _ => fail!(pdev, EINVAL, "bst,alternate-map names undefined reset ID {}\n", id),
+        };
+        let width = cfg.ctrl_width.bits();
+        let bytes = (width / 8) as usize;
+        let base = match bst_address.get(window) {
+            Some(Some(base)) if offset % bytes == 0 && offset + bytes <= window_size[window] => *base,
+            _ => fail!(pdev, EINVAL, "Invalid alternate register for reset ID {}\n", id),
+        };
+        if tuple[3] >= width {
+            fail!(pdev, EINVAL, "Invalid alternate bit for reset ID {}\n", id);
+        }
+        cfg.addr = base as usize + offset;
+        cfg.window = Some(window);
+        cfg.bit_idx = tuple[3];
+    }
+    Ok(())
+}
+
+// Read the per-line configuration from the device tree
+pub(crate) fn parse_lines(
+    pdev: &platform::Device,
+    quirks: &Quirks,
+    nr_lines: usize,
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+) -> Result<Vec<LineConfig>> {
+    let mut lines = Vec::try_with_capacity(nr_lines)?;
+    for _ in 0..nr_lines {
+        lines.try_push(LineConfig::default())?;
+    }
+
+    let lists = [
+        (c_str!("bst,self-clearing-resets"), LINE_SELF_CLEARING),
+        (c_str!("bst,assert-only-resets"), LINE_ASSERT_ONLY),
+        (c_str!("bst,deassert-only-resets"), LINE_DEASSERT_ONLY),
+        (c_str!("bst,inverted-resets"), LINE_INVERTED),
+        (c_str!("bst,edge-triggered-resets"), LINE_EDGE_TRIGGERED),
+        (c_str!("bst,protected-resets"), LINE_PROTECTED),
+        (c_str!("bst,status-inverted-resets"), LINE_STATUS_INVERTED),
+        (c_str!("bst,completion-irq-resets"), LINE_COMPLETION_IRQ),
+    ];
+    for (name, flag) in lists {
+        for id in read_u32_list(pdev, name)? {
+            match lines.get_mut(id as usize) {
+                Some(cfg) => cfg.flags |= flag,
+                None => {
+                    fail!(pdev, EINVAL, "{}: reset id {} out of range\n", name, id);
+                }
+            }
+        }
+    }
+
+    for (id, cfg) in lines.iter().enumerate() {
+        if cfg.flags & LINE_EDGE_TRIGGERED != 0 && cfg.flags & LINE_SELF_CLEARING != 0 {
+            fail!(pdev, EINVAL, "Reset id {} cannot be both edge-triggered and self-clearing\n", id);
+        }
+        if cfg.flags & LINE_COMPLETION_IRQ != 0 && cfg.flags & LINE_SELF_CLEARING == 0 {
+            fail!(pdev, EINVAL, "Reset id {} signals completion but is not self-clearing\n", id);
+        }
+    }
+
+    if quirks.global_invert || read_bool(pdev, c_str!("bst,global-invert")) {
+        dev_info!(pdev, "Inverting reset polarity of all lines\n");
+        invert_all(&mut lines);
+    }
+
+    // Policies for resets of held lines as (id, policy) pairs
+    let cells = read_u32_list(pdev, c_str!("bst,shared-reset-policy"))?;
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,shared-reset-policy must hold (id, policy) pairs\n");
+    }
+    for pair in cells.chunks_exact(2) {
+        match (lines.get_mut(pair[0] as usize), SharedPolicy::from_dt(pair[1])) {
+            (Some(cfg), Ok(policy)) => cfg.shared_policy = policy,
+            _ => {
+                fail!(pdev, EINVAL, "Invalid bst,shared-reset-policy entry <{} {}>\n", pair[0], pair[1]);
+            }
+        }
+    }
+
+    // Ready bits as (id, register index, byte offset, bit) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,ready-bits"))?;
+    if cells.len() % 4 != 0 {
+        fail!(pdev, EINVAL, "bst,ready-bits must hold (id, reg, offset, bit) tuples\n");
+    }
+    for tuple in cells.chunks_exact(4) {
+        let id = tuple[0] as usize;
+        let ready = RegBit::from_dt(bst_address, window_size, tuple[1], tuple[2], tuple[3]).map_err(|e| {
+            dev_err!(pdev, "Invalid ready bit for reset id {}\n", id);
+            e
+        })?;
+        match lines.get_mut(id) {
+            Some(cfg) => cfg.ready = Some(ready),
+            None => {
+                fail!(pdev, EINVAL, "bst,ready-bits: reset id {} out of range\n", id);
+            }
+        }
+    }
+
+    // Names indexed by reset id, empty strings leave a line unnamed
+    let names = read_string_list(pdev, c_str!("reset-names"))?;
+    for (cfg, name) in lines.iter_mut().zip(names) {
+        if !name.is_empty() {
+            cfg.name = Some(name);
+        }
+    }
+
+    // Control register widths as (id, width in bits) pairs
+    let cells = read_u32_list(pdev, c_str!("bst,control-widths"))?;
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,control-widths must hold (id, width) pairs\n");
+    }
+    for pair in cells.chunks_exact(2) {
+        let id = pair[0] as usize;
+        match (lines.get_mut(id), Width::from_bits(pair[1])) {
+            (Some(cfg), Ok(width)) => cfg.ctrl_width = width,
+            _ => {
+                fail!(pdev, EINVAL, "Invalid control width for reset id {}\n", id);
+            }
+        }
+    }
+
+    // Status registers as (id, reg index, byte offset, bit, width in bits) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,status-regs"))?;
+    if cells.len() % 5 != 0 {
+        fail!(pdev, EINVAL, "bst,status-regs must hold (id, reg, offset, bit, width) tuples\n");
+    }
+    for tuple in cells.chunks_exact(5) {
+        let id = tuple[0] as usize;
+        let reg = parse_status_reg(bst_address, tuple[1], tuple[2], tuple[3], tuple[4]);
+        match (lines.get_mut(id), reg) {
+            (Some(cfg), Ok(reg)) => cfg.status_reg = Some(reg),
+            _ => {
+                fail!(pdev, EINVAL, "Invalid status register for reset id {}\n", id);
+            }
+        }
+    }
+
+    // Status mirrors as (id, reg index, byte offset, bit, width in bits) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,status-mirrors"))?;
+    if cells.len() % 5 != 0 {
+        fail!(pdev, EINVAL, "bst,status-mirrors must hold (id, reg, offset, bit, width) tuples\n");
+    }
+    for tuple in cells.chunks_exact(5) {
+        let id = tuple[0] as usize;
+        let reg = parse_status_reg(bst_address, tuple[1], tuple[2], tuple[3], tuple[4]);
+        match (lines.get_mut(id), reg) {
+            (Some(cfg), Ok(reg)) => cfg.status_mirror = Some(reg),
+            _ => {
+                fail!(pdev, EINVAL, "Invalid status mirror for reset id {}\n", id);
+            }
+        }
+    }
+
+    // Per-line hold times as (id, hold us, long hold us) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,line-hold-times"))?;
+    if cells.len() % 3 != 0 {
+        fail!(pdev, EINVAL, "bst,line-hold-times must hold (id, hold-us, long-hold-us) tuples\n");
+    }
+    for tuple in cells.chunks_exact(3) {
+        let id = tuple[0] as usize;
+        match lines.get_mut(id) {
+            Some(cfg) => cfg.hold_times = Some(HoldTimes { hold_us: tuple[1], long_hold_us: tuple[2] }),
+            None => fail!(pdev, EINVAL, "Invalid hold times for reset id {}\n", id),
+        }
+    }
+
+    // Maximum assert durations as (id, ms, action) tuples, action 0 warns and 1
+    // deasserts the line
+    let cells = read_u32_list(pdev, c_str!("bst,max-assert-ms"))?;
+    if cells.len() % 3 != 0 {
+        fail!(pdev, EINVAL, "bst,max-assert-ms must hold (id, ms, action) tuples\n");
+    }
+    for tuple in cells.chunks_exact(3) {
+        let id = tuple[0] as usize;
+        match lines.get_mut(id) {
+            Some(cfg) if tuple[1] > 0 && tuple[2] <= 1 => {
+                cfg.max_assert = Some(MaxAssert { ms: tuple[1], deassert: tuple[2] == 1 });
+            }
+            _ => fail!(pdev, EINVAL, "Invalid maximum assert duration for reset id {}\n", id),
+        }
+    }
+
+    // Deassert prerequisites as (id, prerequisite id, wait ms) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,deassert-dependencies"))?;
+    if cells.len() % 3 != 0 {
+        fail!(pdev, EINVAL, "bst,deassert-dependencies must hold (id, prerequisite, wait-ms) tuples\n");
+    }
+    for tuple in cells.chunks_exact(3) {
+        let (id, prereq) = (tuple[0] as usize, tuple[1] as usize);
+        if id == prereq || prereq >= nr_lines {
+            fail!(pdev, EINVAL, "Invalid deassert prerequisite {} of reset id {}\n", prereq, id);
+        }
+        match lines.get_mut(id) {
+            Some(cfg) => cfg.prerequisites.try_push((prereq, tuple[2]))?,
+            None => fail!(pdev, EINVAL, "Invalid deassert prerequisite {} of reset id {}\n", prereq, id),
+        }
+    }
+    check_prerequisite_cycles(pdev, &lines)?;
+
+    // Enable bits gating reset bits, as (id, register index, byte offset, bit) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,enable-bits"))?;
+    if cells.len() % 4 != 0 {
+        fail!(pdev, EINVAL, "bst,enable-bits must hold (id, reg, offset, bit) tuples\n");
+    }
+    for tuple in cells.chunks_exact(4) {
+        let id = tuple[0] as usize;
+        match (lines.get_mut(id), RegBit::from_dt(bst_address, window_size, tuple[1], tuple[2], tuple[3])) {
+            (Some(cfg), Ok(enable)) => cfg.enable = Some(enable),
+            _ => fail!(pdev, EINVAL, "Invalid enable bit for reset id {}\n", id),
+        }
+    }
+
+    // Companion registers written after a deassert, as (id, register index,
+    // byte offset, value) tuples
+    let cells = read_u32_list(pdev, c_str!("bst,post-deassert-writes"))?;
+    if cells.len() % 4 != 0 {
+        fail!(pdev, EINVAL, "bst,post-deassert-writes must hold (id, reg, offset, value) tuples\n");
+    }
+    for tuple in cells.chunks_exact(4) {
+        let id = tuple[0] as usize;
+        match (lines.get_mut(id), RegBit::from_dt(bst_address, window_size, tuple[1], tuple[2], 0)) {
+            (Some(cfg), Ok(reg)) => cfg.post_deassert = Some(PostWrite { addr: reg.addr, val: tuple[3] }),
+            _ => fail!(pdev, EINVAL, "Invalid post-deassert write for reset id {}\n", id),
+        }
+    }
+
+    // Release stages as (id, bit, delay in ms) tuples, in release order
+    let cells = read_u32_list(pdev, c_str!("bst,release-stages"))?;
+    if cells.len() % 3 != 0 {
+        fail!(pdev, EINVAL, "bst,release-stages must hold (id, bit, delay-ms) tuples\n");
+    }
+    for tuple in cells.chunks_exact(3) {
+        let id = tuple[0] as usize;
+        let valid = tuple[1] < 32
+            && matches!(lines.get(id), Some(cfg) if cfg.flags & LINE_RESTRICTED == 0);
+        if !valid {
+            fail!(pdev, EINVAL, "Invalid release stage for reset id {}\n", id);
+        }
+        lines[id].stages.try_push(ReleaseStage { bit_idx: tuple[1], delay_ms: tuple[2] })?;
+    }
+
+    // Coupled lines as (id, id) pairs, both must share the same restrictions
+    let cells = read_u32_list(pdev, c_str!("bst,coupled-resets"))?;
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,coupled-resets must hold (id, id) pairs\n");
+    }
+    for pair in cells.chunks_exact(2) {
+        let (a, b) = (pair[0] as usize, pair[1] as usize);
+        let valid = a != b
+            && a < nr_lines
+            && b < nr_lines
+            && lines[a].stages.is_empty()
+            && lines[b].stages.is_empty()
+            && lines[a].partner.is_none()
+            && lines[b].partner.is_none()
+            // Polarity may differ, every write applies each line's own
+            && (lines[a].flags ^ lines[b].flags) & !(LINE_INVERTED | LINE_STATUS_INVERTED) == 0
+            && lines[a].ctrl_width == lines[b].ctrl_width;
+        if !valid {
+            fail!(pdev, EINVAL, "Cannot couple reset ids {} and {}\n", a, b);
+        }
+        lines[a].partner = Some(b);
+        lines[b].partner = Some(a);
+    }
+    Ok(lines)
+}
+
+// Apply `bst,global-invert`: a globally inverted block flips every line, so a
+// per-line inversion on such a board brings that line back to the manager's polarity
+pub(crate) fn invert_all(lines: &mut [LineConfig]) {
+    for cfg in lines.iter_mut() {
+        cfg.flags ^= LINE_INVERTED;
+    }
+}
+
+// Whether diagnostics that only warn by default fail instead, for validation
+// builds: with the `strict` feature or `bst,strict`
+pub(crate) fn strict_mode(pdev: &platform::Device) -> bool {
+    cfg!(feature = "strict") || read_bool(pdev, c_str!("bst,strict"))
+}
+
+// Report ids mapped to the same register and bit, asserting one would silently
+// reset the other. Only a warning unless `bst,strict-map` or strict mode is set.
+pub(crate) fn check_overlaps(
+    pdev: &platform::Device,
+    manager: &BstResetManager,
+    lines: &[LineConfig],
+) -> Result {
+    let strict = read_bool(pdev, c_str!("bst,strict-map")) || strict_mode(pdev);
+    let bit_of = |id: usize| (lines[id].addr, lines[id].bit_idx);
+    let mut overlaps = false;
+    for (a, _) in defined_lines(manager) {
+        let bit = bit_of(a);
+        for (b, _) in defined_lines(manager).skip_while(|(b, _)| *b <= a) {
+            if bit_of(b) == bit {
+                dev_warn!(pdev, "Reset ids {} and {} map to the same register bit\n", a, b);
+                overlaps = true;
+            }
+        }
+    }
+    if overlaps && strict {
+        return Err(error::code::EINVAL);
+    }
+    Ok(())
+}
+
+// Check where every defined line ended up once placed, by the manager's map,
+// `bst,line-offsets` or `bst,alternate-map`: its control register must be
+// aligned to the width given by `bst,control-widths` and its bit and release
+// stages must fit that width
+pub(crate) fn check_layout(
+    pdev: &platform::Device,
+    manager: &BstResetManager,
+    lines: &[LineConfig],
+) -> Result {
+    for (id, _) in defined_lines(manager) {
+        let cfg = &lines[id];
+        let width = cfg.ctrl_width.bits();
+        if cfg.addr % (width / 8) as usize != 0 {
+            fail!(pdev, EINVAL, "Reset ID {} register {:#x} is not {}-bit aligned\n", id, cfg.addr, width);
+        }
+        let mut bits = core::iter::once(cfg.bit_idx).chain(cfg.stages.iter().map(|stage| stage.bit_idx));
+        if let Some(bit_idx) = bits.find(|bit_idx| *bit_idx >= width) {
+            fail!(pdev, EINVAL, "Reset ID {} bit {} does not fit a {}-bit register\n", id, bit_idx, width);
+        }
+    }
+    Ok(())
+}
+
+// Resolve a status bit, the offset must be aligned and the bit fit the width
+pub(crate) fn parse_status_reg(
+    bst_address: &[Option<*mut u8>],
+    reg: u32,
+    offset: u32,
+    bit_idx: u32,
+    width: u32,
+) -> Result<StatusReg> {
+    let width = Width::from_bits(width)?;
+    let base = match bst_address.get(reg as usize) {
+        Some(Some(base)) => *base as usize,
+        _ => return Err(error::code::EINVAL),
+    };
+    if offset % (width.bits() / 8) != 0 || bit_idx >= width.bits() {
+        return Err(error::code::EINVAL);
+    }
+    Ok(StatusReg { addr: base + offset as usize, bit_idx, width })
+}
+
+// Read the `bst,reset-id-aliases` table of (legacy id, id) pairs
+pub(crate) fn parse_aliases(pdev: &platform::Device, nr_lines: usize) -> Result<Vec<IdAlias>> {
+    let cells = read_u32_list(pdev, c_str!("bst,reset-id-aliases"))?;
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,reset-id-aliases must hold (legacy, id) pairs\n");
+    }
+
+    let mut aliases = Vec::try_with_capacity(cells.len() / 2)?;
+    for pair in cells.chunks_exact(2) {
+        if pair[1] as usize >= nr_lines {
+            fail!(pdev, EINVAL, "Alias {} targets out of range reset id {}\n", pair[0], pair[1]);
+        }
+        aliases.try_push(IdAlias { legacy: pair[0], id: pair[1], used: AtomicBool::new(false) })?;
+    }
+    Ok(aliases)
+}
+
+// Read `bst,safe-state-on-remove` as (id, state) pairs, state 1 asserted and 0 deasserted
+pub(crate) fn parse_safe_state(pdev: &platform::Device, nr_lines: usize) -> Result<Vec<(usize, bool)>> {
+    let cells = read_u32_list(pdev, c_str!("bst,safe-state-on-remove"))?;
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,safe-state-on-remove must hold (id, state) pairs\n");
+    }
+
+    let mut safe_state = Vec::try_with_capacity(cells.len() / 2)?;
+    for pair in cells.chunks_exact(2) {
+        if pair[0] as usize >= nr_lines || pair[1] > 1 {
+            fail!(pdev, EINVAL, "Invalid safe state {} for reset id {}\n", pair[1], pair[0]);
+        }
+        safe_state.try_push((pair[0] as usize, pair[1] == 1))?;
+    }
+    Ok(safe_state)
+}
+
+// Address of the reset-source register given by `bst,reset-source-reg` (reg index, byte offset)
+pub(crate) fn parse_reset_source(
+    pdev: &platform::Device,
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+) -> Result<Option<usize>> {
+    let cells = read_u32_list(pdev, c_str!("bst,reset-source-reg"))?;
+    if cells.is_empty() {
+        return Ok(None);
+    }
+    if cells.len() != 2 {
+        fail!(pdev, EINVAL, "bst,reset-source-reg must be (reg, offset)\n");
+    }
+    Ok(Some(RegBit::from_dt(bst_address, window_size, cells[0], cells[1], 0)?.addr))
+}
+
+// Self-reset bit of the controller given by `bst,self-reset` (reg index, byte offset, bit)
+pub(crate) fn parse_self_reset(
+    pdev: &platform::Device,
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+) -> Result<Option<RegBit>> {
+    let cells = read_u32_list(pdev, c_str!("bst,self-reset"))?;
+    if cells.is_empty() {
+        return Ok(None);
+    }
+    if cells.len() != 3 {
+        fail!(pdev, EINVAL, "bst,self-reset must be (reg, offset, bit)\n");
+    }
+    Ok(Some(RegBit::from_dt(bst_address, window_size, cells[0], cells[1], cells[2])?))
+}
+
+// Resource index of each present window. Without `reg-names`, `reg` lists the
+// present windows in `RstResId` order. With it, each entry names its window as
+// "rst<N>", N being the window's `RstResId`, so entries match their window
+// whatever their order, and a name matching no present window fails the probe.
+pub(crate) fn resource_order(
+    pdev: &platform::Device,
+    present: u32,
+) -> Result<[Option<u32>; MAX_RESET_REGISTERS]> {
+    let mut order = [None; MAX_RESET_REGISTERS];
+    let names = read_string_list(pdev, c_str!("reg-names"))?;
+    if names.is_empty() {
+        let mut res_index = 0;
+        for (window, slot) in order.iter_mut().enumerate() {
+            if present & (1 << window) != 0 {
+                *slot = Some(res_index);
+                res_index += 1;
+            }
+        }
+        return Ok(order);
+    }
+    if names.len() != present.count_ones() as usize {
+        fail!(pdev, EINVAL, "reg-names must name each of the present windows {:#x}\n", present);
+    }
+    let mut reordered = false;
+    for (res_index, name) in names.iter().enumerate() {
+        match window_of_name(name) {
+            Some(window) if present & (1 << window) != 0 && order[window].is_none() => {
+                reordered |= (present & ((1 << window) - 1)).count_ones() as usize != res_index;
+                order[window] = Some(res_index as u32);
+            }
+            _ => fail!(pdev, EINVAL, "reg-names entry {} names no present window\n", name),
+        }
+    }
+    if reordered {
+        dev_info!(pdev, "Matched out of order reg entries to their windows by reg-names\n");
+    }
+    Ok(order)
+}
+
+// Window named "rst<N>" in `reg-names`
+pub(crate) fn window_of_name(name: &CStr) -> Option<usize> {
+    let digits = core::str::from_utf8(name.as_bytes().strip_prefix(b"rst")?).ok()?;
+    let window = digits.parse().ok()?;
+    (window < MAX_RESET_REGISTERS).then(|| window)
+}
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Completion interrupt of controllers signalling the release of self-clearing lines
+//!
+//! The interrupt wakes the `reset()` waiting for such a line instead of polling
+//! its status. Resets are serialized by `reset_lock`, so at most one waits at a
+//! time and a single completion per controller is enough.
+//!
+//! The controller signals a release with a rising edge and has no register to
+//! acknowledge it, so the interrupt is requested edge triggered and the handler
+//! clears nothing. A level-triggered line would stay asserted and storm. The
+//! interrupt is not shared, every edge is the controller's and is handled,
+//! whether or not a reset still waits for it.
+
+use kernel::{
+    bindings,
+    device::RawDevice,
+    error,
+    prelude::*,
+    str::CStr,
+};
+
+use core::{
+    cell::UnsafeCell,
+    ffi::{c_int, c_void},
+    time::Duration,
+};
+
+use crate::BstMap;
+
+// A `struct completion` signalled by the interrupt handler
+pub(crate) struct Completion(UnsafeCell<bindings::completion>);
+
+impl Completion {
+    // A completion that must be initialized with `init` before it is used
+    pub(crate) fn new() -> Self {
+        // SAFETY: All-zero bytes are a valid `completion`, it is initialized by `init`.
+        Completion(UnsafeCell::new(unsafe { core::mem::zeroed() }))
+    }
+
+    // Set up the wait queue, what `init_completion` does, which is inline and
+    // bindgen cannot see
+    //
+    // # Safety
+    //
+    // The completion must not move anymore, e.g. because it is pinned.
+    pub(crate) unsafe fn init(&self) {
+        let completion = self.0.get();
+        // SAFETY: The completion does not move, see the function's safety requirements.
+        unsafe {
+            (*completion).done = 0;
+            bindings::__init_swait_queue_head(
+                core::ptr::addr_of_mut!((*completion).wait),
+                core::ptr::null(),
+                core::ptr::null_mut(),
+            );
+        }
+    }
+
+    // Forget a completion signalled before, what `reinit_completion` does
+    pub(crate) fn reinit(&self) {
+        // SAFETY: The completion was initialized by `init`, a racing `complete`
+        // is as benign as with `reinit_completion`.
+        unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!((*self.0.get()).done), 0) };
+    }
+
+    // Wake the waiter, or let the next wait return at once
+    pub(crate) fn complete(&self) {
+        // SAFETY: The completion was initialized by `init`.
+        unsafe { bindings::complete(self.0.get()) };
+    }
+
+    // Wait for `complete`, false once `timeout` has passed without it
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        // SAFETY: Pure conversion without preconditions.
+        let jiffies = unsafe { bindings::__msecs_to_jiffies(ms) };
+        // SAFETY: The completion was initialized by `init`.
+        unsafe { bindings::wait_for_completion_timeout(self.0.get(), jiffies) != 0 }
+    }
+}
+
+// SAFETY: `completion` is designed to be signalled and waited on from any context.
+unsafe impl Send for Completion {}
+// SAFETY: As above.
+unsafe impl Sync for Completion {}
+
+// The requested completion interrupt, freed on drop
+pub(crate) struct Irq {
+    irq: u32,
+    data: *const BstMap,
+}
+
+impl Irq {
+    // Request the first interrupt of the device for `data`, rising edge
+    // triggered, `data` must outlive the returned value
+    pub(crate) fn request(dev: &impl RawDevice, name: &CStr, data: &BstMap) -> Result<Self> {
+        // SAFETY: The device is valid, `dev_fwnode` covers DT and ACPI alike.
+        let irq = unsafe { bindings::fwnode_irq_get(bindings::dev_fwnode(dev.raw_device()), 0) };
+        if irq < 0 {
+            return Err(error::Error::from_kernel_errno(irq));
+        }
+        if irq == 0 {
+            return Err(error::code::EINVAL);
+        }
+        let data: *const BstMap = data;
+        // SAFETY: `handler` matches the calling convention of an IRQ handler, `name`
+        // lives as long as the device and `data` outlives the interrupt, which is
+        // freed on drop.
+        let ret = unsafe {
+            bindings::request_threaded_irq(
+                irq as u32,
+                Some(handler),
+                None,
+                bindings::IRQF_TRIGGER_RISING.into(),
+                name.as_char_ptr(),
+                data as *mut c_void,
+            )
+        };
+        if ret < 0 {
+            return Err(error::Error::from_kernel_errno(ret));
+        }
+        Ok(Irq { irq: irq as u32, data })
+    }
+}
+
+impl Drop for Irq {
+    fn drop(&mut self) {
+        // SAFETY: The interrupt was requested with `data` as its cookie by `request`,
+        // `free_irq` waits for a handler in progress.
+        unsafe { bindings::free_irq(self.irq, self.data as *mut c_void) };
+    }
+}
+
+// SAFETY: `data` is only used as the cookie of the interrupt and by the handler,
+// which only needs shared access.
+unsafe impl Send for Irq {}
+// SAFETY: As above.
+unsafe impl Sync for Irq {}
+
+// Handler of the completion interrupt. An edge no reset waits for is a late or
+// repeated release, handled all the same: the interrupt is the controller's alone.
+//
+// # Safety
+//
+// `dev_id` must point at a live `BstMap`, as the cookie passed by `Irq::request`.
+pub(crate) unsafe extern "C" fn handler(_irq: c_int, dev_id: *mut c_void) -> bindings::irqreturn_t {
+    // SAFETY: `dev_id` is the `BstMap` the interrupt was requested for, which
+    // outlives it.
+    let data = unsafe { &*(dev_id as *const BstMap) };
+    data.complete_reset_irq();
+    bindings::irqreturn_IRQ_HANDLED
+}
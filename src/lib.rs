@@ -5,6 +5,7 @@
 #![feature(allocator_api)]
 
 use kernel::{
+    acpi,
     bindings,
     delay::coarse_sleep,
     device,
@@ -14,96 +15,4067 @@ use kernel::{
     platform,  
     prelude::*,
     reset::{self, ResetRegistration},
-    sync::{Arc,ArcBorrow},
+    c_str,
+    clk::{Clk, EnabledClk},
+    device::RawDevice,
+    str::CStr,
+    sync::{Arc,ArcBorrow,Mutex,SpinLock},
+    task::Task,
 };
 
 use core::{
+    fmt::{self, Write},
     ops::DerefMut,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
     time::Duration,
 };
 
 use bst_reset_rust::{
     BstResetManager,
+    BstRstMap,
     RstResId,
     RST_HOLD_TIME,
     RESET_LONG_HOLD_TIME,
     ZERO_ASSERT_ONE_DEASSERT,
 };
 
-module_platform_driver! {
-    type: BstResetDriver,
-    name: "reset_bsta1000b",
-    license: "GPL v2",
-    initcall: "arch",
+// Log an error and return its errno in one step, so every failure is reported
+// where it is raised, e.g. `fail!(self.dev, EPERM, "Reset ID {} is assert-only\n", line)`
+macro_rules! fail {
+    ($dev:expr, $code:ident, $($arg:tt)*) => {{
+        dev_err!($dev, $($arg)*);
+        return Err(error::code::$code);
+    }};
 }
 
+// A lock of a `BstMap` before it is initialized in place by `BstMap::init_pinned`
+macro_rules! uninit_lock {
+    ($lock:ident, $value:expr) => {{
+        let value = $value;
+        // SAFETY: `BstMap::init_pinned` initializes every lock before the data is used.
+        unsafe { $lock::new(value) }
+    }};
+}
+
+// Declare the module at the given initcall level
+macro_rules! bst_reset_module {
+    ($initcall:tt) => {
+        module_platform_driver! {
+            type: BstResetDriver,
+            name: "reset_bsta1000b",
+            license: "GPL v2",
+            initcall: $initcall,
+            params: {
+                op_log_size: u32 {
+                    default: 32,
+                    permissions: 0,
+                    description: "Number of recent reset ops kept in debugfs op_log, 0 disables it",
+                },
+            },
+        }
+    };
+}
+
+// Declared after the macros above so the modules can use them
+pub mod consumer;
+mod debugfs;
+mod dt;
+mod irq;
+#[cfg(feature = "mmio-trace")]
+mod mmio_trace;
+#[cfg(feature = "selftest")]
+mod selftest;
+mod sysfs;
+mod work;
+
+// The initcall level is picked at build time, see the `initcall-*` features
+#[cfg(feature = "initcall-core")]
+bst_reset_module!("core");
+#[cfg(feature = "initcall-postcore")]
+bst_reset_module!("postcore");
+#[cfg(feature = "initcall-subsys")]
+bst_reset_module!("subsys");
+#[cfg(feature = "initcall-device")]
+bst_reset_module!("device");
+#[cfg(not(any(
+    feature = "initcall-core",
+    feature = "initcall-postcore",
+    feature = "initcall-subsys",
+    feature = "initcall-device",
+)))]
+bst_reset_module!("arch");
+
+#[cfg(any(
+    all(
+        feature = "initcall-core",
+        any(feature = "initcall-postcore", feature = "initcall-subsys", feature = "initcall-device")
+    ),
+    all(feature = "initcall-postcore", any(feature = "initcall-subsys", feature = "initcall-device")),
+    all(feature = "initcall-subsys", feature = "initcall-device"),
+))]
+compile_error!("only one initcall-* feature can be enabled");
+
 // Define the device ID table for module matching
 kernel::module_of_id_table!(BST_RESET_MOD_TABLE, BST_RESET_OF_MATCH_TABLE);
 // Define the ID array for device tree matching
-kernel::define_of_id_table! {BST_RESET_OF_MATCH_TABLE, (), [
-    (of::DeviceId::Compatible(b"bst,a1000b-rstc"), None),
+kernel::define_of_id_table! {BST_RESET_OF_MATCH_TABLE, Quirks, [
+    (of::DeviceId::Compatible(b"bst,a1000b-rstc"), Some(A1000B_QUIRKS)),
 ]}
 
-// Define the main driver structure
-struct BstResetDriver;
+// Define the device ID table for module matching on ACPI systems
+kernel::module_acpi_id_table!(BST_RESET_ACPI_MOD_TABLE, BST_RESET_ACPI_MATCH_TABLE);
+// Define the ID array for ACPI matching, by `_HID`
+kernel::define_acpi_id_table! {BST_RESET_ACPI_MATCH_TABLE, Quirks, [
+    (acpi::DeviceId::Hid(b"BSTR0001"), Some(A1000B_QUIRKS)),
+]}
+
+// Behavior of a controller variant, selected by the compatible that matched.
+// Known variants get it here rather than from DT booleans.
+struct Quirks {
+    // Variant name for logs
+    name: &'static str,
+    // Invert every line, on top of `bst,global-invert`
+    global_invert: bool,
+    // Reset hold time, `RST_HOLD_TIME` when `None`
+    hold_time_ms: Option<u64>,
+    // Read every control register back after writing it, so the write is not
+    // left posted on the bus when the op returns
+    flush_posted_writes: bool,
+    // Access to the control and status registers
+    regs: &'static dyn RegAccess,
+}
+
+const A1000B_QUIRKS: Quirks = Quirks {
+    name: "a1000b",
+    global_invert: false,
+    hold_time_ms: None,
+    flush_posted_writes: false,
+    regs: &Mmio,
+};
+
+// Define the main driver structure
+struct BstResetDriver;
+
+// SAFETY: `BstMap` holds a non-null pointer to GPIO registers, which is safe to be used from any thread.
+unsafe impl Send for BstMap {}
+// SAFETY: `BstMap` holds a non-null pointer to GPIO registers, references to which are safe to be used from any thread.
+unsafe impl Sync for BstMap {}
+
+// Driver-level line flags, layered on top of the manager's flags. Lines are
+// level triggered by default: held in reset for as long as they are asserted.
+//
+// - SELF_CLEARING: hardware releases the line after it is asserted, deassert
+//   is a no-op and `reset()` asserts then waits for the release
+// - ASSERT_ONLY: the line must never be released by us
+// - DEASSERT_ONLY: the line must never be put back into reset
+// - INVERTED: wired with the opposite polarity to what the manager describes
+// - EDGE_TRIGGERED: the block resets on a pulse on the line, assert performs
+//   the whole pulse and deassert is a no-op
+// - PROTECTED: resetting the line would take the board down, e.g. the CPU's
+//   own reset, every op driving it is refused
+// - STATUS_INVERTED: the status bit reads 1 when the line is out of reset,
+//   independently of the control polarity
+// - COMPLETION_IRQ: a self-clearing line whose release raises the completion
+//   interrupt, `reset()` waits for it instead of polling
+const LINE_SELF_CLEARING: u32 = 1 << 0;
+const LINE_ASSERT_ONLY: u32 = 1 << 1;
+const LINE_DEASSERT_ONLY: u32 = 1 << 2;
+const LINE_INVERTED: u32 = 1 << 3;
+const LINE_EDGE_TRIGGERED: u32 = 1 << 4;
+const LINE_PROTECTED: u32 = 1 << 5;
+const LINE_STATUS_INVERTED: u32 = 1 << 6;
+const LINE_COMPLETION_IRQ: u32 = 1 << 7;
+
+// Lines that cannot simply be driven to an arbitrary level
+const LINE_RESTRICTED: u32 = LINE_SELF_CLEARING
+    | LINE_ASSERT_ONLY
+    | LINE_DEASSERT_ONLY
+    | LINE_EDGE_TRIGGERED
+    | LINE_PROTECTED;
+
+// Ids of lines addressed directly by (register index, bit) with `#reset-cells = <2>`,
+// for bring-up before the built-in map describes the silicon
+const DIRECT_ID: u64 = 1 << 31;
+
+// Number of reset ids registered with the framework
+const NR_RESETS: usize = 50;
+
+// Upper bound on the `op_log_size` parameter
+const MAX_OP_LOG_SIZE: usize = 1024;
+
+// Upper bound on the register windows of any variant, sizes every per-window array
+const MAX_RESET_REGISTERS: usize = 5;
+// Windows described by the A1000B map
+const A1000B_REGISTERS: usize = RstResId::LSP1_RST_CTRL_REG as usize + 1;
+
+// `bst,present-windows` holds one bit per window
+const _: () = assert!(MAX_RESET_REGISTERS <= 31 && A1000B_REGISTERS <= MAX_RESET_REGISTERS);
+
+// A single bit in one of the mapped register windows
+#[derive(Clone, Copy)]
+struct RegBit {
+    addr: usize,
+    bit_idx: u32,
+}
+
+impl RegBit {
+    // Resolve a (register index, byte offset, bit) triple from DT. A register not
+    // lying whole within its window fails with EFAULT, as in `check_bounds`.
+    fn from_dt(
+        bst_address: &[Option<*mut u8>],
+        window_size: &[usize],
+        reg: u32,
+        offset: u32,
+        bit_idx: u32,
+    ) -> Result<Self> {
+        let base = match bst_address.get(reg as usize) {
+            Some(Some(base)) => *base as usize,
+            _ => return Err(error::code::EINVAL),
+        };
+        if offset % 4 != 0 || bit_idx >= 32 {
+            return Err(error::code::EINVAL);
+        }
+        if offset as usize + 4 > window_size.get(reg as usize).copied().unwrap_or(0) {
+            return Err(error::code::EFAULT);
+        }
+        Ok(RegBit { addr: base + offset as usize, bit_idx })
+    }
+
+    fn is_set(&self, regs: &dyn RegAccess) -> bool {
+        regs.read(self.addr, Width::W32) & (1 << self.bit_idx) != 0
+    }
+
+    fn set(&self, regs: &dyn RegAccess, on: bool) {
+        let reg_val = regs.read(self.addr, Width::W32);
+        if on {
+            regs.write(reg_val | (1 << self.bit_idx), self.addr, Width::W32);
+        } else {
+            regs.write(reg_val & !(1 << self.bit_idx), self.addr, Width::W32);
+        }
+    }
+}
+
+// Level shifter the reset lines are routed through on some boards. It has to
+// be enabled, through a GPIO and/or a register bit, before any line is driven.
+struct LevelShifter {
+    gpio: *mut bindings::gpio_desc,
+    bit: Option<RegBit>,
+    // Whether enabling succeeded, lines cannot be driven otherwise
+    enabled: bool,
+}
+
+impl LevelShifter {
+    // Look up and enable the level shifter, a failed enable is reported through `enabled`
+    fn probe(
+        pdev: &platform::Device,
+        bst_address: &[Option<*mut u8>],
+        window_size: &[usize],
+        regs: &dyn RegAccess,
+    ) -> Result<Self> {
+        let mut shifter = LevelShifter { gpio: core::ptr::null_mut(), bit: None, enabled: true };
+
+        // SAFETY: The device is valid and the consumer id is NUL terminated.
+        let gpio = unsafe {
+            bindings::devm_gpiod_get_optional(
+                pdev.raw_device(),
+                c_str!("level-shifter").as_char_ptr(),
+                bindings::gpiod_flags_GPIOD_OUT_LOW,
+            )
+        };
+        let errno = ptr_err(gpio as *const core::ffi::c_void);
+        if errno == error::code::EPROBE_DEFER.to_kernel_errno() {
+            return Err(error::code::EPROBE_DEFER);
+        } else if errno != 0 {
+            dev_err!(pdev, "Could not get level shifter GPIO: {}\n", errno);
+            shifter.enabled = false;
+        } else {
+            shifter.gpio = gpio;
+        }
+
+        let cells = dt::read_u32_list(pdev, c_str!("bst,level-shifter-enable"))?;
+        if !cells.is_empty() {
+            if cells.len() != 3 {
+                fail!(pdev, EINVAL, "bst,level-shifter-enable must be (reg, offset, bit)\n");
+            }
+            shifter.bit = Some(RegBit::from_dt(bst_address, window_size, cells[0], cells[1], cells[2])?);
+        }
+
+        if shifter.enabled {
+            shifter.set(regs, true);
+            if let Some(bit) = &shifter.bit {
+                if !bit.is_set(regs) {
+                    dev_err!(pdev, "Level shifter enable bit did not latch\n");
+                    shifter.enabled = false;
+                }
+            }
+        }
+        Ok(shifter)
+    }
+
+    // Enable or disable at probe, before anything else can drive the registers.
+    // Later on, `BstMap::set_level_shifter` takes `reg_lock` for the bit.
+    fn set(&self, regs: &dyn RegAccess, on: bool) {
+        self.set_gpio(on);
+        if let Some(bit) = &self.bit {
+            bit.set(regs, on);
+        }
+    }
+
+    fn set_gpio(&self, on: bool) {
+        if !self.gpio.is_null() {
+            // SAFETY: `gpio` was obtained from `devm_gpiod_get_optional` and is device managed.
+            unsafe { bindings::gpiod_set_value_cansleep(self.gpio, on as i32) };
+        }
+    }
+}
+
+// Power domain the controller sits in, kept powered while an op runs
+struct PowerDomain {
+    dev: *mut bindings::device,
+}
+
+impl PowerDomain {
+    // Attach the controller to its power domain and enable runtime PM on it,
+    // `None` when the node has no `power-domains`
+    fn probe(pdev: &platform::Device) -> Result<Option<Self>> {
+        if !dt::read_bool(pdev, c_str!("power-domains")) {
+            return Ok(None);
+        }
+        // Resuming the domain may sleep
+        if cfg!(feature = "no-sleep") {
+            fail!(pdev, EINVAL, "power-domains cannot be used with the no-sleep build\n");
+        }
+        let dev = pdev.raw_device();
+        // SAFETY: `dev` is a live device, attaching again is a no-op if the bus already did.
+        let ret = unsafe { bindings::dev_pm_domain_attach(dev, true) };
+        if ret < 0 {
+            dev_err!(pdev, "Could not attach power domain: {}\n", ret);
+            return Err(error::Error::from_kernel_errno(ret));
+        }
+        // SAFETY: `dev` is a live device, runtime PM is disabled again when it unbinds.
+        let ret = unsafe { bindings::devm_pm_runtime_enable(dev) };
+        if ret < 0 {
+            return Err(error::Error::from_kernel_errno(ret));
+        }
+        Ok(Some(PowerDomain { dev }))
+    }
+
+    // Whether the controller is runtime suspended, as `pm_runtime_suspended()`
+    fn suspended(&self) -> bool {
+        // SAFETY: `dev` is live while the controller is bound, the fields are only read.
+        let power = unsafe { &(*self.dev).power };
+        power.runtime_status == bindings::rpm_status_RPM_SUSPENDED && power.disable_depth == 0
+    }
+
+    // Power the domain up for the duration of the returned guard
+    fn get(&self) -> Result<PowerGuard<'_>> {
+        // SAFETY: `dev` is live while the controller is bound and has runtime PM enabled.
+        let ret = unsafe { bindings::__pm_runtime_resume(self.dev, bindings::RPM_GET_PUT as i32) };
+        let guard = PowerGuard(self);
+        if ret < 0 {
+            return Err(error::code::EIO);
+        }
+        Ok(guard)
+    }
+}
+
+// Usage count of the power domain, dropped when the op is done. The count is taken
+// even when resuming fails, so the guard exists on both paths.
+struct PowerGuard<'a>(&'a PowerDomain);
+
+impl Drop for PowerGuard<'_> {
+    fn drop(&mut self) {
+        let flags = bindings::RPM_GET_PUT | bindings::RPM_ASYNC;
+        // SAFETY: The usage count was taken by `PowerDomain::get`.
+        unsafe { bindings::__pm_runtime_idle(self.0.dev, flags as i32) };
+    }
+}
+
+// Errno encoded in an ERR_PTR, zero for valid and null pointers
+fn ptr_err(ptr: *const core::ffi::c_void) -> i32 {
+    let val = ptr as isize;
+    if (-(bindings::MAX_ERRNO as isize)..0).contains(&val) {
+        val as i32
+    } else {
+        0
+    }
+}
+
+// Driver-side configuration of a line, parsed from DT at probe
+#[derive(Default)]
+struct LineConfig {
+    flags: u32,
+    ready: Option<RegBit>,
+    // Line that must always be asserted and deasserted together with this one
+    partner: Option<usize>,
+    // Control register address, checked to fit a pointer at probe. Lines in a
+    // lazily mapped window hold an address relative to `lazy_token` instead.
+    addr: usize,
+    // Register window holding the control bit, `None` when that window is absent
+    window: Option<usize>,
+    // Control bit within the register, the map's unless the alternate map moves it
+    bit_idx: u32,
+    // Short name for diagnostics, e.g. "lsp1_uart"
+    name: Option<&'static CStr>,
+    // Extra bits in the line's register released one by one before the line itself
+    stages: Vec<ReleaseStage>,
+    // Access width of the control register
+    ctrl_width: Width,
+    // Register reporting the line status, when not the control register itself
+    status_reg: Option<StatusReg>,
+    // Redundant copy of the status bit, compared on every status read
+    status_mirror: Option<StatusReg>,
+    // What `reset()` does while consumers of the hold API hold the line
+    shared_policy: SharedPolicy,
+    // Per-line hold times from `bst,line-hold-times`
+    hold_times: Option<HoldTimes>,
+    // Longest the line may stay asserted, from `bst,max-assert-ms`
+    max_assert: Option<MaxAssert>,
+    // Companion register written after each deassert, from `bst,post-deassert-writes`
+    post_deassert: Option<PostWrite>,
+    // Bit gating the reset bit, set around every write of it, from `bst,enable-bits`
+    enable: Option<RegBit>,
+    // Lines that must be out of reset before this one is deasserted, each with
+    // how long to wait for it in ms, from `bst,deassert-dependencies`
+    prerequisites: Vec<(usize, u32)>,
+}
+
+// A 32-bit companion register and the value a block needs in it once released
+#[derive(Clone, Copy)]
+struct PostWrite {
+    addr: usize,
+    val: u32,
+}
+
+// Longest a line may stay asserted, and whether the assert watchdog releases it
+// once over it rather than only warning
+#[derive(Clone, Copy)]
+struct MaxAssert {
+    ms: u32,
+    deassert: bool,
+}
+
+/// What `reset()` of a line held through the hold API does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SharedPolicy {
+    /// Refuse with EBUSY.
+    Refuse,
+    /// Reset only when the caller is the sole holder, EBUSY otherwise.
+    SoleHolder,
+    /// Reset anyway, with a warning.
+    Force,
+}
+
+impl Default for SharedPolicy {
+    fn default() -> Self {
+        SharedPolicy::Refuse
+    }
+}
+
+impl SharedPolicy {
+    fn from_dt(val: u32) -> Result<Self> {
+        match val {
+            0 => Ok(SharedPolicy::Refuse),
+            1 => Ok(SharedPolicy::SoleHolder),
+            2 => Ok(SharedPolicy::Force),
+            _ => Err(error::code::EINVAL),
+        }
+    }
+}
+
+// Access width of a register
+#[derive(Clone, Copy, PartialEq)]
+enum Width {
+    W8,
+    W16,
+    W32,
+}
+
+impl Default for Width {
+    fn default() -> Self {
+        Width::W32
+    }
+}
+
+impl Width {
+    fn from_bits(bits: u32) -> Result<Self> {
+        match bits {
+            8 => Ok(Width::W8),
+            16 => Ok(Width::W16),
+            32 => Ok(Width::W32),
+            _ => Err(error::code::EINVAL),
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Width::W8 => 8,
+            Width::W16 => 16,
+            Width::W32 => 32,
+        }
+    }
+
+    fn read(self, addr: usize) -> u32 {
+        match self {
+            Width::W8 => readb(addr).into(),
+            Width::W16 => readw(addr).into(),
+            Width::W32 => readl(addr),
+        }
+    }
+
+    fn write(self, val: u32, addr: usize) {
+        match self {
+            Width::W8 => writeb(val as u8, addr),
+            Width::W16 => writew(val as u16, addr),
+            Width::W32 => writel(val, addr),
+        }
+    }
+}
+
+// A status bit in a register of any width
+#[derive(Clone, Copy)]
+struct StatusReg {
+    addr: usize,
+    bit_idx: u32,
+    width: Width,
+}
+
+// Hold times of a line overriding the controller-wide one, the long hold applies
+// to lines the map marks as needing a long hold
+#[derive(Clone, Copy)]
+struct HoldTimes {
+    hold_us: u32,
+    long_hold_us: u32,
+}
+
+// A stage of a staged release: a bit and the time to wait after releasing it
+struct ReleaseStage {
+    bit_idx: u32,
+    delay_ms: u32,
+}
+
+// Offsets from a control register to its write-1-to-set and write-1-to-clear aliases
+#[derive(Clone, Copy)]
+struct SetClr {
+    set_offset: usize,
+    clr_offset: usize,
+}
+
+// Convert a map address to a pointer-sized MMIO address. On 32-bit kernels an
+// address above 4 GiB fails with EOVERFLOW instead of being truncated.
+fn mmio_addr(addr: u64) -> Result<usize> {
+    usize::try_from(addr).map_err(|_| {
+        pr_err!("Reset register address {:#x} does not fit a pointer\n", addr);
+        error::code::EOVERFLOW
+    })
+}
+
+// Stand-in base of a lazily mapped window handed to the manager at probe, line
+// addresses in the window are rebased onto the real mapping on first use. Each
+// window gets a distinct 256 MiB slot, far above any window size.
+fn lazy_token(window: usize) -> usize {
+    (window + 1) << 28
+}
+
+// Defined entries of a manager's map with their ids, skipping the gaps. Every
+// walk over the map goes through here rather than indexing it.
+fn defined_lines(manager: &BstResetManager) -> impl Iterator<Item = (usize, &BstRstMap)> {
+    manager
+        .bsta1000b_map
+        .iter()
+        .enumerate()
+        .filter_map(|(rst_id, entry)| Some((rst_id, entry.as_ref()?)))
+}
+
+// Index of the mapped window containing `addr`
+fn window_of(bst_address: &[Option<*mut u8>], window_size: &[usize], addr: usize) -> Option<usize> {
+    bst_address.iter().zip(window_size.iter()).position(|(base, size)| match base {
+        Some(base) => (*base as usize..*base as usize + size).contains(&addr),
+        None => false,
+    })
+}
+
+// Place every defined line at the register and bit the manager's map gives it
+fn place_lines(
+    manager: &BstResetManager,
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+    lines: &mut [LineConfig],
+) -> Result {
+    for (rst_id, map) in defined_lines(manager) {
+        let cfg = &mut lines[rst_id];
+        cfg.addr = mmio_addr(map.addr as u64)?;
+        cfg.window = window_of(bst_address, window_size, cfg.addr);
+        cfg.bit_idx = map.bit_idx;
+    }
+    Ok(())
+}
+
+// Values of `LineState::shadow`
+const SHADOW_NONE: u8 = 0;
+const SHADOW_DEASSERTED: u8 = 1;
+const SHADOW_ASSERTED: u8 = 2;
+
+// Per-line runtime state and operation counters
+#[derive(Default)]
+struct LineState {
+    retries_attempted: AtomicU32,
+    retries_succeeded: AtomicU32,
+    // Pid of the task running an op on the line, zero when idle
+    owner: AtomicI32,
+    // Set once an unbalanced deassert has been reported
+    unbalanced: AtomicBool,
+    // Last status read or driven, reported while the controller is suspended
+    last_status: AtomicBool,
+    // An asynchronous reset was started and the line has not self-cleared yet
+    pending: AtomicBool,
+    // A `reset()` of the line is in progress
+    resetting: AtomicBool,
+    // State last written to the line, one of the `SHADOW_*` values, with `bst,shadow-check`
+    shadow: AtomicU8,
+    // Monotonic time in ns at which the last successful `reset()` finished, zero if never
+    last_reset_ns: AtomicU64,
+    // Start of the current storm window and `reset()` calls in it
+    storm_start_ns: AtomicU64,
+    storm_count: AtomicU32,
+    // Resets are refused until this time after a storm, with a cooldown set
+    storm_until_ns: AtomicU64,
+    // A `reset()` was queued by `async_reset` and has not completed yet
+    async_queued: AtomicBool,
+    // Monotonic time in ns since which the line is known asserted, zero when deasserted
+    asserted_since_ns: AtomicU64,
+    // The watchdog acted on the current assertion, it acts only once per assertion
+    over_max: AtomicBool,
+    // Monotonic time in ns at which the op running on the line times out, zero
+    // when no op timeout is armed, see `arm_op_timeout`
+    op_deadline_ns: AtomicU64,
+    // Negative errno the next op on the line fails with, zero when none is armed
+    #[cfg(feature = "error-injection")]
+    injected: AtomicI32,
+}
+
+// Marks an op in progress on a line, released on drop so error paths clear it too
+struct OpGuard<'a>(Option<&'a AtomicI32>);
+
+impl Drop for OpGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(owner) = self.0 {
+            owner.store(0, Ordering::Release);
+        }
+    }
+}
+
+// Arms the op timeout of a line for one op, disarmed on drop
+struct OpTimeout<'a> {
+    deadline_ns: Option<&'a AtomicU64>,
+    started_ns: i64,
+}
+
+impl OpTimeout<'_> {
+    // The armed timeout has passed, false when this op did not arm one
+    fn expired(&self) -> bool {
+        self.deadline_ns
+            .map_or(false, |ns| ktime_get_ns() > ns.load(Ordering::Relaxed) as i64)
+    }
+}
+
+impl Drop for OpTimeout<'_> {
+    fn drop(&mut self) {
+        if let Some(ns) = self.deadline_ns {
+            ns.store(0, Ordering::Release);
+        }
+    }
+}
+
+// A legacy reset id kept working after renumbering
+struct IdAlias {
+    legacy: u32,
+    id: u32,
+    // Set once the alias has been reported, so migrations are logged only once
+    used: AtomicBool,
+}
+
+// Define a structure to hold reset addresses
+struct BstMap{
+    // Controller device, used to attribute log messages to this instance
+    dev: device::Device,
+    // Serializes read-modify-write cycles on the control registers. Only writers
+    // take it, status reads use `status_lock`, see `line_status`.
+    reg_lock: SpinLock<()>,
+    // Taken for writing around every `reg_lock` section, before `reg_lock`, and
+    // for reading by the status reads of lines with a status mirror
+    status_lock: StatusLock,
+    // Serializes composite `reset()` sequences. They sleep between steps, which
+    // `reg_lock` cannot cover, while single register updates must not sleep, so
+    // the two coexist: `reset_lock` is always taken first and held across the
+    // sequence, `reg_lock` only around each register update within it.
+    reset_lock: ResetLock,
+    // Pid of the task holding `reg_lock`, -1 while it is free, for the ownership
+    // checks of the control register accessors, with the accesses failing them
+    #[cfg(debug_assertions)]
+    reg_lock_owner: AtomicI32,
+    #[cfg(debug_assertions)]
+    reg_lock_violations: AtomicU32,
+    // Whether a malformed status has been reported, once per controller
+    #[cfg(debug_assertions)]
+    status_warned: AtomicBool,
+    bst_address:[Option<*mut u8>; MAX_RESET_REGISTERS],
+    // Physical base and size of each mapped window, as reported by its resource
+    phys_base: [Option<u64>; MAX_RESET_REGISTERS],
+    window_size: [usize; MAX_RESET_REGISTERS],
+    // SET/CLR aliases of the control registers, per window
+    set_clr: [Option<SetClr>; MAX_RESET_REGISTERS],
+    // Windows from `bst,lazy-windows`, mapped on their first use into `lazy_base`
+    lazy_windows: u32,
+    // Status-only windows from `bst,read-only-windows`, never written
+    read_only_windows: u32,
+    lazy_base: [AtomicPtr<u8>; MAX_RESET_REGISTERS],
+    // Serializes mapping the lazy windows
+    lazy_lock: Mutex<()>,
+    // Built once at probe from the mapped register bases
+    manager: BstResetManager,
+    // Configuration of the lines under the primary map and, with
+    // `bst,alternate-map`, the alternate one, empty otherwise. Both live as long
+    // as the controller, so lines looked up before a swap stay valid.
+    maps: [Vec<LineConfig>; 2],
+    // Index into `maps` of the map in use, see `swap_map`
+    active_map: AtomicUsize,
+    // Rewrites allowed when the read-back of a control write shows it did not land
+    write_retries: u32,
+    // Bound on polling a line's ready bit after reset
+    ready_timeout_ms: u32,
+    // Total time budget of a `reset()`, verification waits included
+    reset_timeout_ms: u32,
+    // Bound on any single op, cutting its waits short, from `bst,op-timeout-ms`,
+    // zero when unbounded
+    op_timeout_ms: u32,
+    // Wait between enabling a block's clock and releasing it from reset
+    clock_settle_ms: u32,
+    // Reset storm limit, from `bst,storm-threshold`
+    storm: Option<StormLimit>,
+    // Reset hold time, from the quirks or ACPI `_DSD`, `RST_HOLD_TIME` by default
+    hold_time_ms: u64,
+    // Read control registers back after writing them, see `Quirks`
+    flush_posted_writes: bool,
+    // Access to the control and status registers, from the quirks
+    regs: &'static dyn RegAccess,
+    // Sleeps of the composite ops, `OP_DELAY` unless a test observes them
+    delay: &'static dyn DelayProvider,
+    // Compare each line's bit against the state last written to it on every
+    // read-modify-write, from `bst,shadow-check`
+    shadow_check: bool,
+    // Fail status reads with EIO when the status mirror disagrees, from
+    // `bst,status-mirror-strict`
+    status_mirror_strict: bool,
+    // Misconfigurations fail rather than warn, see `strict_mode`
+    strict: bool,
+    // Deassert the lines consumers still hold at remove, from `bst,release-leaked-holds`
+    release_leaked_holds: bool,
+    state: Vec<LineState>,
+    aliases: Vec<IdAlias>,
+    // Lines driven to a safe state at remove, from `bst,safe-state-on-remove`
+    safe_state: Vec<(usize, bool)>,
+    level_shifter: LevelShifter,
+    power_domain: Option<PowerDomain>,
+    // Consumers address lines as (register index, bit), with `#reset-cells = <2>`
+    direct_cells: bool,
+    // Answer status reads from `LineState::last_status` while suspended
+    cache_status_suspended: bool,
+    // Lines held asserted through the consumer-tracked API
+    holds: Mutex<Vec<Hold>>,
+    // Lines acquired exclusively by a consumer, taken after `holds` when both are needed
+    acquired: Mutex<Vec<Acquisition>>,
+    // Lines pinned by a consumer, taken after `holds` and `acquired`
+    pins: Mutex<Vec<Pin>>,
+    // Most recent ops, for post-mortem analysis through debugfs `op_log`
+    op_log: SpinLock<OpLog>,
+    // Post-reset callbacks by reset id
+    post_reset: Mutex<Vec<(usize, PostReset)>>,
+    // Sequencing hooks run by `reset()` at its phase boundaries, indexed by `Phase`.
+    // A spinlock, as they are looked up with `reset_lock` held.
+    phase_hooks: SpinLock<[Option<PhaseHook>; Phase::COUNT]>,
+    // Successful ops, exposed as a liveness signal through sysfs `heartbeat`
+    heartbeat: AtomicU64,
+    // Last op run through debugfs `trace_op`
+    #[cfg(debug_assertions)]
+    last_trace: OpTrace,
+    // Steps loaded through debugfs `replay`
+    #[cfg(feature = "replay")]
+    replay: Mutex<Vec<ReplayStep>>,
+    // Runs the resets queued by `async_reset`
+    async_work: work::Work,
+    // Signalled by the completion interrupt when the line in `irq_waiting`, an id
+    // plus one or zero for none, is released
+    completion: irq::Completion,
+    irq_waiting: AtomicUsize,
+    // Every op changing a line fails with EPERM while set, see `set_gate`
+    gated: AtomicBool,
+    // Transaction tagging the ops, zero when none, see `begin_transaction`
+    transaction: AtomicU32,
+    // Set once the controller is registered, ops are refused before that and after remove
+    ready: AtomicBool,
+    // Address of the latched reset-source register, from `bst,reset-source-reg`
+    reset_source: Option<usize>,
+    // Bit resetting the controller's own logic, from `bst,self-reset`
+    self_reset: Option<RegBit>,
+}
+
+// Register values around a traced op
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct OpTrace {
+    // Line id plus one, zero when nothing was traced yet
+    id: AtomicU32,
+    before: AtomicU32,
+    after: AtomicU32,
+}
+
+// Kind of a reset op, as recorded in the op log
+#[derive(Clone, Copy)]
+enum OpKind {
+    Assert,
+    Deassert,
+    Status,
+    Reset,
+    Toggle,
+    Acquire,
+    Release,
+}
+
+impl OpKind {
+    fn name(self) -> &'static str {
+        match self {
+            OpKind::Assert => "assert",
+            OpKind::Deassert => "deassert",
+            OpKind::Status => "status",
+            OpKind::Reset => "reset",
+            OpKind::Toggle => "toggle",
+            OpKind::Acquire => "acquire",
+            OpKind::Release => "release",
+        }
+    }
+}
+
+// A step of a replayed reset sequence: wait `delay_ms`, then run the op
+#[cfg(feature = "replay")]
+struct ReplayStep {
+    kind: OpKind,
+    id: u64,
+    delay_ms: u32,
+    // Result of the last run, zero or a negative errno, `None` before one
+    result: Option<i32>,
+}
+
+// Most steps a replayed sequence holds
+#[cfg(feature = "replay")]
+const MAX_REPLAY_STEPS: usize = 64;
+
+// An op and its result, zero or a negative errno
+struct OpRecord {
+    time_ns: i64,
+    id: u64,
+    kind: OpKind,
+    result: i32,
+    // Transaction the op ran in, zero outside of one
+    txn: u32,
+}
+
+// Ring of the last `capacity` ops, the oldest record is overwritten when full
+struct OpLog {
+    records: Vec<OpRecord>,
+    capacity: usize,
+    // Slot the next record goes into once the ring is full
+    next: usize,
+}
+
+impl OpLog {
+    fn new(capacity: usize) -> Result<Self> {
+        Ok(OpLog { records: Vec::try_with_capacity(capacity)?, capacity, next: 0 })
+    }
+
+    fn push(&mut self, record: OpRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() < self.capacity {
+            // Cannot fail, the room was reserved by `new`
+            let _ = self.records.try_push(record);
+        } else {
+            self.records[self.next] = record;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    // Records from the oldest to the newest
+    fn iter(&self) -> impl Iterator<Item = &OpRecord> {
+        let (newer, older) = self.records.split_at(self.next.min(self.records.len()));
+        older.iter().chain(newer.iter())
+    }
+}
+
+fn same_consumer(a: &CStr, b: &CStr) -> bool {
+    a.as_bytes() == b.as_bytes()
+}
+
+// A consumer holding a line asserted, `count` times
+struct Hold {
+    consumer: &'static CStr,
+    id: usize,
+    count: u32,
+}
+
+// A line acquired exclusively by a consumer, kept in the acquired state until
+// released, then put back into the state it was found in
+struct Acquisition {
+    consumer: &'static CStr,
+    id: usize,
+    asserted: bool,
+    prior: bool,
+}
+
+// A line pinned by a consumer, see `BstMap::pin`
+struct Pin {
+    consumer: &'static CStr,
+    id: usize,
+}
+
+// Keeps a line pinned, unpinned when dropped, so a consumer tearing down
+// without `unpin` does not lock the line out for good
+pub(crate) struct LinePin<'a> {
+    data: &'a BstMap,
+    rst_id: usize,
+}
+
+impl LinePin<'_> {
+    // Unpin the line
+    pub(crate) fn unpin(self) {}
+}
+
+impl Drop for LinePin<'_> {
+    fn drop(&mut self) {
+        let mut pins = self.data.pins.lock();
+        if let Some(index) = pins.iter().position(|pin| pin.id == self.rst_id) {
+            let pin = pins.swap_remove(index);
+            dev_info!(self.data.dev, "Reset ID {} unpinned by {}\n", self.rst_id, pin.consumer);
+        }
+    }
+}
+
+impl BstMap {
+    // Initialize the locks, the async work item and the completion once the data
+    // reached its final location, before anything else uses it
+    fn init_pinned(mut self: core::pin::Pin<&mut Self>) {
+        macro_rules! init {
+            ($field:ident, $init:ident, $name:literal) => {{
+                // SAFETY: The field is initialized in place, it is never moved out of the pinned data.
+                let field = unsafe { self.as_mut().map_unchecked_mut(|data| &mut data.$field) };
+                kernel::$init!(field, $name);
+            }};
+        }
+        init!(reg_lock, spinlock_init, "BstMap::reg_lock");
+        #[cfg(not(feature = "no-sleep"))]
+        init!(status_lock, rwsemaphore_init, "BstMap::status_lock");
+        #[cfg(feature = "no-sleep")]
+        init!(status_lock, spinlock_init, "BstMap::status_lock");
+        #[cfg(not(feature = "no-sleep"))]
+        init!(reset_lock, mutex_init, "BstMap::reset_lock");
+        #[cfg(feature = "no-sleep")]
+        init!(reset_lock, spinlock_init, "BstMap::reset_lock");
+        init!(lazy_lock, mutex_init, "BstMap::lazy_lock");
+        init!(holds, mutex_init, "BstMap::holds");
+        init!(acquired, mutex_init, "BstMap::acquired");
+        init!(pins, mutex_init, "BstMap::pins");
+        #[cfg(feature = "replay")]
+        init!(replay, mutex_init, "BstMap::replay");
+        init!(op_log, spinlock_init, "BstMap::op_log");
+        init!(post_reset, mutex_init, "BstMap::post_reset");
+        init!(phase_hooks, spinlock_init, "BstMap::phase_hooks");
+        // SAFETY: The data is pinned, so the work item does not move anymore.
+        unsafe { self.async_work.init(async_reset_work) };
+        // SAFETY: As above.
+        unsafe { self.completion.init() };
+    }
+
+    // Read the latched reset-source bits. They are sticky and write-1-to-clear, with
+    // `clear` the bits read are cleared so the next boot reports only its own reason.
+    fn read_reset_source(&self, clear: bool) -> Result<u32> {
+        self.clear_reset_source(if clear { u32::MAX } else { 0 })
+    }
+
+    // Clear the latched reset-source bits in `mask`, the other latched bits stay
+    // latched. Returns the bits latched before.
+    fn clear_reset_source(&self, mask: u32) -> Result<u32> {
+        let addr = self.reset_source.ok_or(error::code::ENODEV)?;
+        let latched = self.regs.read(addr, Width::W32);
+        if latched & mask != 0 {
+            w1c_clear(self.regs, addr, latched & mask);
+        }
+        Ok(latched)
+    }
+
+    // Recover a wedged controller without unbinding it: pulse its self-reset bit,
+    // then redo the probe-time setup the reset wiped, re-enabling the level
+    // shifter and re-asserting every line a consumer holds. Like an op it waits
+    // for the controller to be registered, and it holds the holds and
+    // `reset_lock` throughout so no hold changes and no `reset()` runs across it.
+    fn recover(&self) -> Result {
+        let bit = self.self_reset.ok_or(error::code::EOPNOTSUPP)?;
+        if !self.ready.load(Ordering::Acquire) {
+            return Err(error::code::EAGAIN);
+        }
+        let _power = self.power()?;
+        let holds = self.holds.lock();
+        let _guard = self.reset_lock.lock();
+        dev_warn!(self.dev, "Resetting the reset controller\n");
+        self.with_reg_lock(|| self.set_bit(bit, true));
+        self.hold(self.delay, true);
+        self.with_reg_lock(|| {
+            self.set_bit(bit, false);
+            self.clear_shadows();
+        });
+
+        if self.level_shifter.enabled {
+            self.set_level_shifter(true);
+        }
+        for hold in holds.iter() {
+            self.assert_line(hold.id)?;
+        }
+        Ok(())
+    }
+
+    // Physical base of a register window, without exposing its mapping
+    fn register_phys_base(&self, index: usize) -> Result<u64> {
+        match self.phys_base.get(index) {
+            Some(Some(base)) => Ok(*base),
+            _ => Err(error::code::EINVAL),
+        }
+    }
+
+    // Check that lines can be driven at all, called at the top of every op
+    fn usable(&self) -> Result {
+        if !self.level_shifter.enabled {
+            return Err(error::code::ENODEV);
+        }
+        Ok(())
+    }
+
+    // Common entry of the reset ops, records every op and its result in `op_log`
+    fn op<R>(
+        &self,
+        kind: OpKind,
+        rst_id: u64,
+        f: impl FnOnce(&Self, usize) -> Result<R>,
+    ) -> Result<R> {
+        self.op_for(kind, None, rst_id, f)
+    }
+
+    // `op` on behalf of a consumer of the consumer API, lines it pinned itself
+    // let it through
+    fn op_for<R>(
+        &self,
+        kind: OpKind,
+        consumer: Option<&CStr>,
+        rst_id: u64,
+        f: impl FnOnce(&Self, usize) -> Result<R>,
+    ) -> Result<R> {
+        let ret = match kind {
+            OpKind::Status => self.run_op(rst_id, f),
+            _ => self.check_gate().and_then(|()| {
+                self.run_op(rst_id, |data, id| {
+                    data.check_pin(consumer, id)?;
+                    f(data, id)
+                })
+            }),
+        };
+        self.record(kind, rst_id, &ret);
+        ret
+    }
+
+    // `op` for several lines driven together, e.g. by `set_states`: every line
+    // passes the checks of an op and is claimed before `f` runs on all of them.
+    // Each line is recorded with its own kind and the result of the batch.
+    fn op_batch<R>(
+        &self,
+        consumer: Option<&CStr>,
+        ops: &[(OpKind, u64)],
+        f: impl FnOnce(&Self, &[usize]) -> Result<R>,
+    ) -> Result<R> {
+        let ret = self.check_gate().and_then(|()| self.run_batch(consumer, ops, f));
+        for &(kind, rst_id) in ops {
+            self.record(kind, rst_id, &ret);
+        }
+        ret
+    }
+
+    // `run_op` for the lines of a batch, under the op timeout of the first one
+    fn run_batch<R>(
+        &self,
+        consumer: Option<&CStr>,
+        ops: &[(OpKind, u64)],
+        f: impl FnOnce(&Self, &[usize]) -> Result<R>,
+    ) -> Result<R> {
+        if !self.ready.load(Ordering::Acquire) {
+            return Err(error::code::EAGAIN);
+        }
+        self.usable()?;
+        let _power = self.power()?;
+        let mut ids = Vec::try_with_capacity(ops.len())?;
+        let mut guards = Vec::try_with_capacity(ops.len())?;
+        for &(_, rst_id) in ops {
+            let rst_id = self.resolve(rst_id);
+            // A line given twice is claimed once, `f` refuses the batch
+            if !ids.contains(&rst_id) {
+                guards.try_push(self.begin_op(rst_id)?)?;
+            }
+            #[cfg(feature = "error-injection")]
+            self.take_injected(rst_id)?;
+            self.check_pin(consumer, rst_id)?;
+            ids.try_push(rst_id)?;
+        }
+        match ids.first() {
+            Some(&first) => self.bounded(first, || f(self, &ids)),
+            None => f(self, &ids),
+        }
+    }
+
+    // Record an op and its result in `op_log`, and in the log while a
+    // transaction is open. Successful ops count towards the heartbeat.
+    fn record<R>(&self, kind: OpKind, rst_id: u64, ret: &Result<R>) {
+        let result = match ret {
+            Ok(_) => 0,
+            Err(e) => e.to_kernel_errno(),
+        };
+        let txn = self.transaction.load(Ordering::Relaxed);
+        if txn != 0 {
+            dev_info!(self.dev, "[txn {}] {} reset ID {}: {}\n", txn, kind.name(), rst_id, result);
+        }
+        let record = OpRecord { time_ns: ktime_get_ns(), id: rst_id, kind, result, txn };
+        self.op_log.lock_irqdisable().push(record);
+        if ret.is_ok() {
+            self.heartbeat.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Keep the power domain of the controller up while the guard lives, when
+    // it has one
+    fn power(&self) -> Result<Option<PowerGuard<'_>>> {
+        self.power_domain.as_ref().map(PowerDomain::get).transpose()
+    }
+
+    // Check the controller, resolve the id and reject re-entrant ops on the
+    // same line, e.g. from a notifier callback
+    fn run_op<R>(&self, rst_id: u64, f: impl FnOnce(&Self, usize) -> Result<R>) -> Result<R> {
+        if !self.ready.load(Ordering::Acquire) {
+            return Err(error::code::EAGAIN);
+        }
+        self.usable()?;
+        let _power = self.power()?;
+        let rst_id = self.resolve(rst_id);
+        let _guard = self.begin_op(rst_id)?;
+        #[cfg(feature = "error-injection")]
+        self.take_injected(rst_id)?;
+        self.bounded(rst_id, || f(self, rst_id))
+    }
+
+    // Run an op on a line under the op timeout, logging what it left behind
+    // when it ran into it
+    fn bounded<R>(&self, rst_id: usize, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        let timeout = self.arm_op_timeout(rst_id);
+        let ret = f();
+        self.check_op_timeout(rst_id, &timeout, &ret);
+        ret
+    }
+
+    // Arm the op timeout of a line for the op about to run. An op nested in
+    // another one, or racing one on the same line, keeps the deadline armed
+    // first, so the outermost op is what is bounded.
+    fn arm_op_timeout(&self, rst_id: usize) -> OpTimeout<'_> {
+        let started_ns = ktime_get_ns();
+        let slot = match self.state.get(rst_id) {
+            Some(state) if self.op_timeout_ms != 0 => &state.op_deadline_ns,
+            _ => return OpTimeout { deadline_ns: None, started_ns },
+        };
+        let deadline = Deadline::after(Duration::from_millis(self.op_timeout_ms.into()));
+        match slot.compare_exchange(0, deadline.0 as u64, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => OpTimeout { deadline_ns: Some(slot), started_ns },
+            Err(_) => OpTimeout { deadline_ns: None, started_ns },
+        }
+    }
+
+    // A deadline `budget` from now, cut short by the op timeout armed on the line
+    fn op_deadline(&self, rst_id: usize, budget: Duration) -> Deadline {
+        let deadline = Deadline::after(budget);
+        match self.state.get(rst_id).map(|state| state.op_deadline_ns.load(Ordering::Acquire)) {
+            Some(ns) if ns != 0 => deadline.earliest(Deadline(ns as i64)),
+            _ => deadline,
+        }
+    }
+
+    // Log what an op that ran into the op timeout left behind
+    fn check_op_timeout<R>(&self, rst_id: usize, timeout: &OpTimeout<'_>, ret: &Result<R>) {
+        if !matches!(ret, Err(e) if *e == error::code::ETIMEDOUT) || !timeout.expired() {
+            return;
+        }
+        let elapsed_us = ktime_get_ns().saturating_sub(timeout.started_ns) / 1000;
+        let status = match self.line_status(rst_id) {
+            Ok(true) => "asserted",
+            Ok(false) => "deasserted",
+            Err(_) => "unknown",
+        };
+        let resetting = self.state.get(rst_id).map_or(false, |s| s.resetting.load(Ordering::Acquire));
+        dev_err!(
+            self.dev,
+            "Op on reset ID {} hit the {} ms op timeout after {} us, line {}{}\n",
+            rst_id,
+            self.op_timeout_ms,
+            elapsed_us,
+            status,
+            if resetting { ", reset in progress" } else { "" }
+        );
+    }
+
+    // Close the gate to refuse every op changing a line, e.g. during a critical
+    // section where no block may be reset, or open it again. Taken with
+    // `reset_lock` so no `reset()` is left half way, single asserts and
+    // deasserts already running still complete.
+    fn set_gate(&self, gated: bool) {
+        let _guard = self.reset_lock.lock();
+        if self.gated.swap(gated, Ordering::AcqRel) != gated {
+            dev_info!(self.dev, "Reset ops {}\n", if gated { "gated" } else { "ungated" });
+        }
+    }
+
+    // Tag every op with a transaction id until `end_transaction`, so the ops of a
+    // bring-up sequence can be picked out of the log and debugfs `op_log`
+    // together. Id 0 means no transaction and is refused, as is opening one
+    // while another is open (EBUSY).
+    fn begin_transaction(&self, txn: u32) -> Result {
+        if txn == 0 {
+            return Err(error::code::EINVAL);
+        }
+        self.transaction
+            .compare_exchange(0, txn, Ordering::AcqRel, Ordering::Relaxed)
+            .map_err(|_| error::code::EBUSY)?;
+        dev_info!(self.dev, "[txn {}] begin\n", txn);
+        Ok(())
+    }
+
+    // Close the transaction opened with `txn`, EINVAL if it is not the open one
+    fn end_transaction(&self, txn: u32) -> Result {
+        self.transaction
+            .compare_exchange(txn, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .map_err(|_| error::code::EINVAL)?;
+        dev_info!(self.dev, "[txn {}] end\n", txn);
+        Ok(())
+    }
+
+    // Refuse to change lines while the gate is closed
+    fn check_gate(&self) -> Result {
+        if self.gated.load(Ordering::Acquire) {
+            return Err(error::code::EPERM);
+        }
+        Ok(())
+    }
+
+    // Fail with the errno armed through debugfs `inject_error`, once
+    #[cfg(feature = "error-injection")]
+    fn take_injected(&self, rst_id: usize) -> Result {
+        let errno = match self.state.get(rst_id) {
+            Some(state) => state.injected.swap(0, Ordering::Relaxed),
+            None => 0,
+        };
+        if errno != 0 {
+            dev_info!(self.dev, "Injecting error {} on reset ID {}\n", errno, rst_id);
+            return Err(error::Error::from_kernel_errno(errno));
+        }
+        Ok(())
+    }
+
+    // Claim a line for the current task. A line already claimed by another
+    // task is concurrent use, not re-entrancy, and is let through.
+    fn begin_op(&self, rst_id: usize) -> Result<OpGuard<'_>> {
+        let owner = match self.state.get(rst_id) {
+            Some(state) => &state.owner,
+            None => return Ok(OpGuard(None)),
+        };
+        let pid = Task::current().pid();
+        match owner.compare_exchange(0, pid, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(OpGuard(Some(owner))),
+            Err(cur) if cur == pid => {
+                dev_err!(self.dev, "Re-entrant op on reset ID {}\n", rst_id);
+                Err(error::code::EDEADLK)
+            }
+            Err(_) => Ok(OpGuard(None)),
+        }
+    }
+
+    // Translate an id coming from a consumer, applying the legacy alias table
+    fn resolve(&self, rst_id: u64) -> usize {
+        for alias in self.aliases.iter() {
+            if u64::from(alias.legacy) == rst_id {
+                if !alias.used.swap(true, Ordering::Relaxed) {
+                    dev_info!(
+                        self.dev,
+                        "Legacy reset ID {} used, mapped to {}\n",
+                        alias.legacy,
+                        alias.id
+                    );
+                }
+                return alias.id as usize;
+            }
+        }
+        rst_id as usize
+    }
+
+    // Translate the cells of a reset specifier, the single-cell form is a map id
+    fn xlate(&self, args: &[u32]) -> Result<u64> {
+        match *args {
+            [rst_id] if !self.direct_cells => Ok(rst_id.into()),
+            [reg, bit_idx] => self.xlate_direct(reg, bit_idx),
+            _ => Err(error::code::EINVAL),
+        }
+    }
+
+    // Translate a two-cell (register index, bit) reset specifier into an id
+    fn xlate_direct(&self, reg: u32, bit_idx: u32) -> Result<u64> {
+        if !self.direct_cells {
+            return Err(error::code::EINVAL);
+        }
+        RegBit::from_dt(&self.bst_address, &self.window_size, reg, 0, bit_idx).map_err(|e| {
+            dev_err!(self.dev, "Invalid direct reset <{} {}>\n", reg, bit_idx);
+            e
+        })?;
+        Ok(DIRECT_ID | u64::from(reg) << 5 | u64::from(bit_idx))
+    }
+
+    // Bit addressed by an id made by `xlate_direct`, `None` for map ids. Direct
+    // lines bypass the map: active high, no flags, a plain assert/deassert.
+    fn direct(&self, rst_id: usize) -> Result<Option<RegBit>> {
+        let rst_id = rst_id as u64;
+        if rst_id & DIRECT_ID == 0 {
+            return Ok(None);
+        }
+        let reg = ((rst_id & !DIRECT_ID) >> 5) as u32;
+        RegBit::from_dt(&self.bst_address, &self.window_size, reg, 0, (rst_id & 0x1f) as u32).map(Some)
+    }
+
+    // Cheap pre-check for consumers and DT validation tools: whether an id names a
+    // defined line whose register is in a present window, so ops can reach it.
+    // Legacy aliases and direct ids count, lazily mapped windows too, they are
+    // mapped on first use. Unlike an op, using an alias is not logged.
+    fn is_supported(&self, rst_id: u64) -> bool {
+        let alias = self.aliases.iter().find(|alias| u64::from(alias.legacy) == rst_id);
+        let rst_id = alias.map_or(rst_id as usize, |alias| alias.id as usize);
+        if let Ok(Some(_)) = self.direct(rst_id) {
+            return true;
+        }
+        match (self.manager.bsta1000b_map.get(rst_id), self.lines().get(rst_id)) {
+            (Some(Some(_)), Some(cfg)) => cfg.window.is_some(),
+            _ => false,
+        }
+    }
+
+    // Index of the register window backing a line, 0..MAX_RESET_REGISTERS, e.g.
+    // to tell which lines contend for a register. EINVAL for ids naming no line,
+    // ENODEV for lines in an absent window. Direct ids carry their index.
+    fn register_index(&self, rst_id: usize) -> Result<usize> {
+        if self.direct(rst_id)?.is_some() {
+            return Ok(((rst_id as u64 & !DIRECT_ID) >> 5) as usize);
+        }
+        match (self.manager.bsta1000b_map.get(rst_id), self.lines().get(rst_id)) {
+            (Some(Some(_)), Some(cfg)) => cfg.window.ok_or(error::code::ENODEV),
+            _ => Err(error::code::EINVAL),
+        }
+    }
+
+    // `direct` for an op that writes the bit, refused in read-only windows
+    fn direct_writable(&self, rst_id: usize) -> Result<Option<RegBit>> {
+        let bit = self.direct(rst_id)?;
+        if bit.is_some() {
+            self.check_writable(Some(self.register_index(rst_id)?), rst_id)?;
+        }
+        Ok(bit)
+    }
+
+    // Refuse to write a line in a window from `bst,read-only-windows`
+    fn check_writable(&self, window: Option<usize>, rst_id: usize) -> Result {
+        match window {
+            Some(window) if self.read_only_windows & (1 << window) != 0 => {
+                fail!(self.dev, EPERM, "Reset ID {} is in read-only window {}\n", rst_id, window)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Configuration of every line under the map in use
+    fn lines(&self) -> &[LineConfig] {
+        &self.maps[self.active_map.load(Ordering::Acquire)]
+    }
+
+    // Switch every line to the other of the two maps, for boards that move between
+    // two validated reset configurations at runtime. The alternate map is built and
+    // validated at probe from `bst,alternate-map`, so the swap cannot leave a half
+    // applied map: it waits for any `reset()` and register update in flight and
+    // applies to every op after it. EBUSY while a line is held, acquired, pinned or
+    // has a reset queued, their state would be carried over to a line that moved.
+    fn swap_map(&self) -> Result {
+        if self.maps[1].is_empty() {
+            return Err(error::code::EOPNOTSUPP);
+        }
+        if !self.ready.load(Ordering::Acquire) {
+            return Err(error::code::EAGAIN);
+        }
+        let _power = self.power()?;
+        let holds = self.holds.lock();
+        let acquired = self.acquired.lock();
+        let pinned = !self.pins.lock().is_empty();
+        let queued = self.state.iter().any(|state| state.async_queued.load(Ordering::Acquire));
+        if !holds.is_empty() || !acquired.is_empty() || pinned || queued {
+            fail!(self.dev, EBUSY, "Cannot swap the reset map while lines are in use\n");
+        }
+        let _guard = self.reset_lock.lock();
+        let active = self.with_reg_lock(|| {
+            let active = self.active_map.load(Ordering::Relaxed) ^ 1;
+            self.active_map.store(active, Ordering::Release);
+            self.clear_shadows();
+            active
+        });
+        // The last seen status belongs to the bits of the old map
+        for (rst_id, _) in self.defined() {
+            if let Ok(line) = self.line(rst_id) {
+                self.cache_status(rst_id, line.read_status(self.regs) != 0);
+            }
+        }
+        let name = if active == 0 { "primary" } else { "alternate" };
+        dev_info!(self.dev, "Switched to the {} reset map\n", name);
+        Ok(())
+    }
+
+    // Defined lines of the map with their ids, see `defined_lines`
+    fn defined(&self) -> impl Iterator<Item = (usize, &BstRstMap)> {
+        defined_lines(&self.manager)
+    }
+
+    // Get a defined line without logging failures. Lines in a lazily mapped window
+    // fail with ENODEV until an op maps it, see `map_lazy`.
+    fn line(&self, rst_id: usize) -> Result<Line<'_>> {
+        match (self.manager.bsta1000b_map.get(rst_id), self.lines().get(rst_id)) {
+            (Some(Some(_)), Some(cfg)) if cfg.window.is_none() => Err(error::code::ENODEV),
+            (Some(Some(bst_rst_map)), Some(cfg)) => {
+                let window = cfg.window.ok_or(error::code::ENODEV)?;
+                let addr = self.resolve_addr(cfg)?;
+                self.check_window_bounds(window, addr, cfg.ctrl_width)?;
+                if let Some(set_clr) = self.set_clr[window] {
+                    self.check_window_bounds(window, addr + set_clr.set_offset, cfg.ctrl_width)?;
+                    self.check_window_bounds(window, addr + set_clr.clr_offset, cfg.ctrl_width)?;
+                }
+                for reg in cfg.status_reg.iter().chain(cfg.status_mirror.iter()) {
+                    self.check_bounds(reg.addr, reg.width)?;
+                }
+                Ok(Line { id: rst_id, map: bst_rst_map, cfg, addr })
+            }
+            _ => Err(error::code::EINVAL),
+        }
+    }
+
+    // Refuse a register that does not lie whole within a mapped window, so a bad
+    // offset in the map or DT faults here rather than on the bus
+    fn check_bounds(&self, addr: usize, width: Width) -> Result {
+        let bytes = (width.bits() / 8) as usize;
+        let fits = |window| self.fits_window(window, addr, bytes);
+        if (0..MAX_RESET_REGISTERS).any(fits) || self.regs.contains(addr, bytes) {
+            return Ok(());
+        }
+        Err(error::code::EFAULT)
+    }
+
+    // `check_bounds` for a register of a given window: an offset past the end of
+    // the window faults even where the next window would take the address
+    fn check_window_bounds(&self, window: usize, addr: usize, width: Width) -> Result {
+        if !self.fits_window(window, addr, (width.bits() / 8) as usize) {
+            return Err(error::code::EFAULT);
+        }
+        Ok(())
+    }
+
+    // Whether `bytes` bytes at `addr` lie whole within a mapped window
+    fn fits_window(&self, window: usize, addr: usize, bytes: usize) -> bool {
+        self.window_base(window).map_or(false, |base| {
+            addr.checked_sub(base).map_or(false, |offset| offset + bytes <= self.window_size[window])
+        })
+    }
+
+    // Control register address of a line, translated for lazily mapped windows
+    fn resolve_addr(&self, cfg: &LineConfig) -> Result<usize> {
+        match cfg.window {
+            Some(window) if self.lazy_windows & (1 << window) != 0 => {
+                let base = self.lazy_base[window].load(Ordering::Acquire);
+                if base.is_null() {
+                    return Err(error::code::ENODEV);
+                }
+                Ok(base as usize + cfg.addr - lazy_token(window))
+            }
+            _ => Ok(cfg.addr),
+        }
+    }
+
+    // Physical address of a line's control register, without exposing its mapping.
+    // Also known for lines in lazy windows not mapped yet, `None` in absent windows.
+    fn phys_addr(&self, cfg: &LineConfig) -> Option<u64> {
+        let window = cfg.window?;
+        let base = if self.lazy_windows & (1 << window) != 0 {
+            lazy_token(window)
+        } else {
+            self.bst_address[window]? as usize
+        };
+        Some(self.phys_base[window]? + (cfg.addr - base) as u64)
+    }
+
+    // Map the lazily mapped window of a line on its first use
+    fn map_lazy(&self, rst_id: usize) -> Result {
+        let window = match self.lines().get(rst_id).and_then(|cfg| cfg.window) {
+            Some(window) if self.lazy_windows & (1 << window) != 0 => window,
+            _ => return Ok(()),
+        };
+        if !self.lazy_base[window].load(Ordering::Acquire).is_null() {
+            return Ok(());
+        }
+        let _guard = self.lazy_lock.lock();
+        if !self.lazy_base[window].load(Ordering::Acquire).is_null() {
+            return Ok(());
+        }
+        let phys = self.phys_base[window].ok_or(error::code::ENOMEM)?;
+        // SAFETY: The device is bound and `phys` spans a memory resource of it, the mapping
+        // goes away when the device unbinds.
+        let base = unsafe {
+            bindings::devm_ioremap(self.dev.raw_device(), phys, self.window_size[window] as _)
+        };
+        if base.is_null() {
+            fail!(self.dev, ENOMEM, "Could not map register window {}\n", window);
+        }
+        dev_info!(self.dev, "Mapped register window {} on first use\n", window);
+        self.lazy_base[window].store(base as *mut u8, Ordering::Release);
+        Ok(())
+    }
+
+    // Base of a register window, whether mapped at probe or lazily
+    fn window_base(&self, window: usize) -> Option<usize> {
+        match self.bst_address.get(window) {
+            Some(Some(base)) => Some(*base as usize),
+            _ => {
+                let base = self.lazy_base.get(window)?.load(Ordering::Acquire);
+                (!base.is_null()).then(|| base as usize)
+            }
+        }
+    }
+
+    // Look up a defined line on behalf of an op
+    fn lookup(&self, rst_id: usize) -> Result<Line<'_>> {
+        self.map_lazy(rst_id)?;
+        self.line(rst_id).map_err(|e| {
+            if e == error::code::ENODEV {
+                dev_err!(self.dev, "Reset ID {} is in an absent register window\n", rst_id);
+            } else if e == error::code::EFAULT {
+                dev_err!(self.dev, "Reset ID {} has a register outside its window\n", rst_id);
+            } else {
+                dev_err!(self.dev, "Invalid reset ID: {}\n", rst_id);
+            }
+            e
+        })
+    }
+
+    // Drive lines sharing one register with a single read-modify-write, skipping
+    // the write when they are already in the requested state. Each line's bit is
+    // set or cleared by its own polarity, so active-high and active-low lines can
+    // share the write. With write retries enabled the write is read back and
+    // repeated until it lands.
+    // Must be called with `reg_lock` held.
+    fn drive_register(&self, addr: usize, lines: &[Line<'_>], asserted: bool) -> Result {
+        self.drive_register_to(addr, lines, |_| asserted)
+    }
+
+    // `drive_register` with each line driven to the state `want` gives it. The
+    // enable bits of gated lines are set around the change.
+    fn drive_register_to(
+        &self,
+        addr: usize,
+        lines: &[Line<'_>],
+        want: impl Fn(&Line<'_>) -> bool + Copy,
+    ) -> Result {
+        self.set_enables(lines, true);
+        let ret = self.drive_register_ungated(addr, lines, want);
+        self.set_enables(lines, false);
+        ret
+    }
+
+    // Set or clear the enable bits of gated lines, the reset bit has no effect
+    // while its enable is clear. Must be called with `reg_lock` held.
+    fn set_enables(&self, lines: &[Line<'_>], on: bool) {
+        for enable in lines.iter().filter_map(|line| line.cfg.enable) {
+            self.set_bit(enable, on);
+        }
+    }
+
+    // The register update proper of `drive_register_to`
+    fn drive_register_ungated(
+        &self,
+        addr: usize,
+        lines: &[Line<'_>],
+        want: impl Fn(&Line<'_>) -> bool + Copy,
+    ) -> Result {
+        let width = lines[0].width();
+        let apply = |reg_val| lines.iter().fold(reg_val, |val, line| line.value(val, want(line)));
+        let reg_val = self.ctrl_read(addr, width);
+        for line in lines {
+            self.check_shadow(*line, reg_val);
+            self.set_shadow(line.id, want(line));
+        }
+        let new_val = apply(reg_val);
+        if new_val == reg_val {
+            return Ok(());
+        }
+        self.write_lines(addr, lines, want, reg_val);
+        if self.write_retries == 0 {
+            return Ok(());
+        }
+
+        let mut attempt = 0;
+        loop {
+            let reg_val = self.ctrl_read(addr, width);
+            if lines.iter().all(|line| line.asserted(reg_val) == want(line)) {
+                if attempt > 0 {
+                    for line in lines {
+                        self.state[line.id].retries_succeeded.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                return Ok(());
+            }
+            if attempt == self.write_retries {
+                fail!(
+                    self.dev,
+                    EIO,
+                    "Reset ID {} did not change state after {} retries\n",
+                    lines[0],
+                    attempt
+                );
+            }
+            attempt += 1;
+            for line in lines {
+                self.state[line.id].retries_attempted.fetch_add(1, Ordering::Relaxed);
+            }
+            self.write_lines(addr, lines, want, reg_val);
+        }
+    }
+
+    // Write the control bits of lines sharing a register. Registers with SET/CLR
+    // aliases only get the line bits written, so bits owned by other drivers in
+    // the same register are never touched. Others get a read-modify-write of `reg_val`.
+    fn write_lines(&self, addr: usize, lines: &[Line<'_>], want: impl Fn(&Line<'_>) -> bool, reg_val: u32) {
+        let width = lines[0].width();
+        let set_clr = lines[0].cfg.window.and_then(|window| self.set_clr[window]);
+        match set_clr {
+            Some(set_clr) => {
+                let (mut set, mut clr) = (0, 0);
+                for line in lines {
+                    if line.value(0, want(line)) != 0 {
+                        set |= line.mask();
+                    } else {
+                        clr |= line.mask();
+                    }
+                }
+                if set != 0 {
+                    self.ctrl_write(set, addr + set_clr.set_offset, width);
+                }
+                if clr != 0 {
+                    self.ctrl_write(clr, addr + set_clr.clr_offset, width);
+                }
+            }
+            None => {
+                let new_val = lines.iter().fold(reg_val, |val, line| line.value(val, want(line)));
+                self.ctrl_write(new_val, addr, width);
+            }
+        }
+    }
+
+    // Drive a line, together with its coupled partner if it has one. Both move in
+    // the same critical section, in a single write when they share a register.
+    fn drive(&self, line: Line<'_>, asserted: bool) -> Result {
+        let partner = match line.cfg.partner {
+            Some(id) => Some(self.lookup(id)?),
+            None => None,
+        };
+        if let Some(partner) = partner {
+            self.check_writable(partner.cfg.window, partner.id)?;
+        }
+        let addr = line.addr();
+
+        self.with_reg_lock(|| {
+            match partner {
+                Some(partner) if partner.addr() == addr => {
+                    self.drive_register(addr, &[line, partner], asserted)?
+                }
+                Some(partner) => {
+                    self.drive_register(addr, &[line], asserted)?;
+                    self.drive_register(partner.addr(), &[partner], asserted)?
+                }
+                None => self.drive_register(addr, &[line], asserted)?,
+            }
+            if !asserted {
+                self.write_post_deassert(line);
+                if let Some(partner) = partner {
+                    self.write_post_deassert(partner);
+                }
+            }
+            Ok(())
+        })?;
+        self.cache_status(line.id, asserted);
+        if let Some(partner) = partner {
+            self.cache_status(partner.id, asserted);
+        }
+        Ok(())
+    }
+
+    // Remember the last known status of a line, for status reads while suspended
+    fn cache_status(&self, rst_id: usize, asserted: bool) {
+        if let Some(state) = self.state.get(rst_id) {
+            state.last_status.store(asserted, Ordering::Relaxed);
+            // Also the start of an assertion, for `bst,max-assert-ms`
+            if !asserted {
+                state.asserted_since_ns.store(0, Ordering::Relaxed);
+            } else if state.asserted_since_ns.load(Ordering::Relaxed) == 0 {
+                state.over_max.store(false, Ordering::Relaxed);
+                state.asserted_since_ns.store((ktime_get_ns() as u64).max(1), Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Status of a line without resuming the controller. Only with
+    // `bst,cache-status-while-suspended` and while the controller is suspended,
+    // in which case the last status seen or driven is returned.
+    fn cached_status(&self, rst_id: u64) -> Option<bool> {
+        let domain = self.power_domain.as_ref()?;
+        if !self.cache_status_suspended || !self.ready.load(Ordering::Acquire) || !domain.suspended() {
+            return None;
+        }
+        let state = self.state.get(self.resolve(rst_id))?;
+        Some(state.last_status.load(Ordering::Relaxed))
+    }
+
+    // Warn when a line's bit differs from the state last written to it, the sign of
+    // a write the hardware silently dropped. Must be called with `reg_lock` held.
+    fn check_shadow(&self, line: Line<'_>, reg_val: u32) {
+        // Hardware releases self-clearing lines on its own
+        if !self.shadow_check || line.cfg.flags & LINE_SELF_CLEARING != 0 {
+            return;
+        }
+        let asserted = match self.state[line.id].shadow.load(Ordering::Relaxed) {
+            SHADOW_NONE => return,
+            shadow => shadow == SHADOW_ASSERTED,
+        };
+        if line.asserted(reg_val) != asserted {
+            dev_warn!(
+                self.dev,
+                "Reset ID {} lost its last write: {} instead of {}\n",
+                line,
+                if asserted { "deasserted" } else { "asserted" },
+                if asserted { "asserted" } else { "deasserted" },
+            );
+        }
+    }
+
+    // Record the state a line is written to, for `check_shadow`
+    fn set_shadow(&self, rst_id: usize, asserted: bool) {
+        if self.shadow_check {
+            let shadow = if asserted { SHADOW_ASSERTED } else { SHADOW_DEASSERTED };
+            self.state[rst_id].shadow.store(shadow, Ordering::Relaxed);
+        }
+    }
+
+    // Forget what was written to every line, after the hardware lost it on purpose
+    fn clear_shadows(&self) {
+        for state in self.state.iter() {
+            state.shadow.store(SHADOW_NONE, Ordering::Relaxed);
+        }
+    }
+
+    // Run the steps loaded through debugfs `replay` in order, each after its delay,
+    // logging every result. A failing step does not stop the sequence, which
+    // replays a capture as it happened. Every step is validated before any runs,
+    // the map may have been swapped since it was loaded.
+    #[cfg(feature = "replay")]
+    fn replay(&self) -> Result {
+        let mut steps = self.replay.lock();
+        if let Some(step) = steps.iter().find(|step| !self.is_supported(step.id)) {
+            fail!(self.dev, EINVAL, "Replay step names unsupported reset ID {}\n", step.id);
+        }
+        for (index, step) in steps.iter_mut().enumerate() {
+            self.delay.sleep(Duration::from_millis(step.delay_ms.into()));
+            let ret = match step.kind {
+                OpKind::Assert => self.op(step.kind, step.id, BstMap::assert_line),
+                OpKind::Deassert => self.op(step.kind, step.id, BstMap::deassert_line),
+                _ => self.op(step.kind, step.id, BstMap::reset_and_wait_ready),
+            };
+            let result = match ret {
+                Ok(()) => 0,
+                Err(e) => e.to_kernel_errno(),
+            };
+            let (op, id) = (step.kind.name(), step.id);
+            dev_info!(self.dev, "Replay step {}: {} reset ID {}: {}\n", index, op, id, result);
+            step.result = Some(result);
+        }
+        Ok(())
+    }
+
+    // Write the companion register of a line just released, so the block finds
+    // it configured as it leaves reset. Must be called with `reg_lock` held.
+    fn write_post_deassert(&self, line: Line<'_>) {
+        if let Some(write) = line.cfg.post_deassert {
+            self.ctrl_write(write.val, write.addr, Width::W32);
+        }
+    }
+
+    // Run `f` with `reg_lock` held, and `status_lock` held for writing around it
+    fn with_reg_lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(not(feature = "no-sleep"))]
+        let _status = self.status_lock.write();
+        #[cfg(feature = "no-sleep")]
+        let _status = self.status_lock.lock();
+        let _guard = self.reg_lock.lock_irqdisable();
+        #[cfg(debug_assertions)]
+        self.reg_lock_owner.store(Task::current().pid(), Ordering::Relaxed);
+        let ret = f();
+        #[cfg(debug_assertions)]
+        self.reg_lock_owner.store(-1, Ordering::Relaxed);
+        ret
+    }
+
+    // Report a control register access by a task not holding `reg_lock`. Its
+    // holder runs with interrupts off, so no other context on its CPU can pass
+    // for it.
+    #[cfg(debug_assertions)]
+    fn check_reg_lock(&self, access: &str, addr: usize) {
+        if self.reg_lock_owner.load(Ordering::Relaxed) == Task::current().pid() {
+            return;
+        }
+        self.reg_lock_violations.fetch_add(1, Ordering::Relaxed);
+        dev_err!(self.dev, "Control register {} at {} without reg_lock\n", access, self.reg_at(addr));
+        // SAFETY: `dump_stack` has no preconditions.
+        unsafe { bindings::dump_stack() };
+    }
+
+    // Set or clear a single control bit: a direct id, an enable bit, the level
+    // shifter or the self-reset bit. Must be called with `reg_lock` held.
+    fn set_bit(&self, bit: RegBit, on: bool) {
+        let reg_val = self.ctrl_read(bit.addr, Width::W32);
+        let mask = 1 << bit.bit_idx;
+        self.ctrl_write(if on { reg_val | mask } else { reg_val & !mask }, bit.addr, Width::W32);
+    }
+
+    // Enable or disable the level shifter once the controller runs, its bit
+    // under `reg_lock` like any control bit and its GPIO, which may sleep, outside
+    fn set_level_shifter(&self, on: bool) {
+        self.level_shifter.set_gpio(on);
+        if let Some(bit) = self.level_shifter.bit {
+            self.with_reg_lock(|| self.set_bit(bit, on));
+        }
+    }
+
+    // Control register accessors for read-modify-write cycles, which are only
+    // safe with `reg_lock` held. Debug builds check that the caller holds it.
+    fn ctrl_read(&self, addr: usize, width: Width) -> u32 {
+        #[cfg(debug_assertions)]
+        self.check_reg_lock("read", addr);
+        self.regs.read(addr, width)
+    }
+
+    fn ctrl_write(&self, val: u32, addr: usize, width: Width) {
+        #[cfg(debug_assertions)]
+        self.check_reg_lock("write", addr);
+        self.regs.write(val, addr, width);
+        if self.flush_posted_writes {
+            self.regs.read(addr, width);
+        }
+    }
+
+    // Refuse to drive a protected line, loudly since a consumer asking for it is a bug
+    fn check_protected(&self, line: Line<'_>) -> Result {
+        if line.cfg.flags & LINE_PROTECTED != 0 {
+            dev_crit!(self.dev, "Refusing to drive protected reset ID {}\n", line);
+            return Err(error::code::EPERM);
+        }
+        Ok(())
+    }
+
+    // Which ops a line accepts, as `assert_line`, `deassert_line` and `reset()`
+    // decide them. The reset core takes no per-line capabilities, ops outside
+    // this set fail with EPERM from the driver instead.
+    fn line_ops(&self, line: Line<'_>) -> LineOps {
+        let writable = match line.cfg.window {
+            Some(window) => self.read_only_windows & (1 << window) == 0,
+            None => false,
+        };
+        let drivable = writable && line.cfg.flags & LINE_PROTECTED == 0;
+        LineOps {
+            assert: drivable && line.cfg.flags & LINE_DEASSERT_ONLY == 0,
+            deassert: drivable && line.cfg.flags & LINE_ASSERT_ONLY == 0,
+            reset: drivable && ResetSequence::for_line(line).is_ok(),
+        }
+    }
+
+    // Features of this instance, from the build and from DT, by name
+    fn features(&self) -> [(&'static str, bool); 23] {
+        let any_line = |flag| self.lines().iter().any(|cfg| cfg.flags & flag != 0);
+        [
+            ("no-sleep", cfg!(feature = "no-sleep")),
+            ("error-injection", cfg!(feature = "error-injection")),
+            ("selftest", cfg!(feature = "selftest")),
+            ("mmio-trace", cfg!(feature = "mmio-trace")),
+            ("replay", cfg!(feature = "replay")),
+            ("trace-op", cfg!(debug_assertions)),
+            ("holds", true),
+            ("verify-writes", self.write_retries > 0),
+            ("self-clearing", any_line(LINE_SELF_CLEARING)),
+            ("edge-triggered", any_line(LINE_EDGE_TRIGGERED)),
+            ("protected", any_line(LINE_PROTECTED)),
+            ("coupled", self.lines().iter().any(|cfg| cfg.partner.is_some())),
+            ("staged-release", self.lines().iter().any(|cfg| !cfg.stages.is_empty())),
+            ("ready-bits", self.lines().iter().any(|cfg| cfg.ready.is_some())),
+            ("direct-cells", self.direct_cells),
+            ("set-clr", self.set_clr.iter().any(Option::is_some)),
+            ("lazy-windows", self.lazy_windows != 0),
+            ("power-domain", self.power_domain.is_some()),
+            ("status-cache", self.cache_status_suspended),
+            ("status-mirror", self.lines().iter().any(|cfg| cfg.status_mirror.is_some())),
+            ("completion-irq", any_line(LINE_COMPLETION_IRQ)),
+            ("recover", self.self_reset.is_some()),
+            ("reset-source", self.reset_source.is_some()),
+        ]
+    }
+
+    // Where a register lies, to show it the way DT describes it
+    fn reg_at(&self, addr: usize) -> RegAt {
+        RegAt((0..MAX_RESET_REGISTERS).find_map(|window| {
+            let offset = addr.checked_sub(self.window_base(window)?)?;
+            (offset < self.window_size[window]).then(|| (window, offset))
+        }))
+    }
+
+    // Write a snapshot of the whole configuration for tooling and support
+    // bundles: the controller-wide settings and features, then every defined
+    // line with its full descriptor, status and hold count. Taken under `holds`
+    // and `reset_lock`, so no hold, `reset()` or map swap lands half way.
+    fn snapshot(&self, out: &mut impl Write) -> Result {
+        let holds = self.holds.lock();
+        let _guard = self.reset_lock.lock();
+        writeln!(out, "version {}", env!("CARGO_PKG_VERSION"))?;
+        write!(out, "features")?;
+        for (name, _) in self.features().iter().filter(|(_, enabled)| *enabled) {
+            write!(out, " {}", name)?;
+        }
+        writeln!(out)?;
+        let map = if self.active_map.load(Ordering::Relaxed) == 0 { "primary" } else { "alternate" };
+        writeln!(out, "map {}", map)?;
+        writeln!(out, "hold-time-ms {}", self.hold_time_ms)?;
+        writeln!(out, "ready-timeout-ms {}", self.ready_timeout_ms)?;
+        writeln!(out, "reset-timeout-ms {}", self.reset_timeout_ms)?;
+        writeln!(out, "op-timeout-ms {}", self.op_timeout_ms)?;
+        writeln!(out, "clock-settle-ms {}", self.clock_settle_ms)?;
+        writeln!(out, "write-retries {}", self.write_retries)?;
+        for (rst_id, _) in self.defined() {
+            let line = match self.line(rst_id) {
+                Ok(line) => line,
+                Err(_) => {
+                    writeln!(out, "line {} absent", rst_id)?;
+                    continue;
+                }
+            };
+            let cfg = line.cfg;
+            let status = match self.line_status(rst_id) {
+                Ok(true) => "asserted",
+                Ok(false) => "deasserted",
+                Err(_) => "unknown",
+            };
+            let held: u32 = holds.iter().filter(|hold| hold.id == rst_id).map(|hold| hold.count).sum();
+            let policy = match cfg.shared_policy {
+                SharedPolicy::Refuse => "refuse",
+                SharedPolicy::SoleHolder => "sole-holder",
+                SharedPolicy::Force => "force",
+            };
+            write!(out, "line {} reg={}.{}", line, self.reg_at(line.addr()), cfg.bit_idx)?;
+            write!(out, " width={} flags={}", cfg.ctrl_width.bits(), line.flags())?;
+            write!(out, " ops={} status={} holds={} policy={}", self.line_ops(line), status, held, policy)?;
+            if let Some(ready) = cfg.ready {
+                write!(out, " ready={}.{}", self.reg_at(ready.addr), ready.bit_idx)?;
+            }
+            if let Some(partner) = cfg.partner {
+                write!(out, " partner={}", partner)?;
+            }
+            let mut sep = " stages=";
+            for stage in cfg.stages.iter() {
+                write!(out, "{}{}/{}", sep, stage.bit_idx, stage.delay_ms)?;
+                sep = ",";
+            }
+            if let Some(reg) = &cfg.status_reg {
+                write!(out, " status-reg={}.{}/{}", self.reg_at(reg.addr), reg.bit_idx, reg.width.bits())?;
+            }
+            if let Some(reg) = &cfg.status_mirror {
+                write!(out, " mirror={}.{}/{}", self.reg_at(reg.addr), reg.bit_idx, reg.width.bits())?;
+            }
+            if let Some(times) = cfg.hold_times {
+                write!(out, " hold-us={}/{}", times.hold_us, times.long_hold_us)?;
+            }
+            if let Some(max) = cfg.max_assert {
+                let action = if max.deassert { "deassert" } else { "warn" };
+                write!(out, " max-assert-ms={}/{}", max.ms, action)?;
+            }
+            if let Some(post) = cfg.post_deassert {
+                write!(out, " post-deassert={}={:#x}", self.reg_at(post.addr), post.val)?;
+            }
+            if let Some(enable) = cfg.enable {
+                write!(out, " enable={}.{}", self.reg_at(enable.addr), enable.bit_idx)?;
+            }
+            let mut sep = " after=";
+            for (prereq, wait_ms) in cfg.prerequisites.iter() {
+                write!(out, "{}{}/{}", sep, prereq, wait_ms)?;
+                sep = ",";
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    // Put a line into reset
+    fn assert_line(&self, rst_id: usize) -> Result {
+        if let Some(bit) = self.direct_writable(rst_id)? {
+            self.with_reg_lock(|| self.set_bit(bit, true));
+            return Ok(());
+        }
+        let line = self.lookup(rst_id)?;
+        self.check_protected(line)?;
+        self.check_writable(line.cfg.window, rst_id)?;
+        if line.cfg.flags & LINE_DEASSERT_ONLY != 0 {
+            fail!(self.dev, EPERM, "Reset ID {} is deassert-only\n", line);
+        }
+        if line.cfg.flags & LINE_EDGE_TRIGGERED != 0 {
+            return self.pulse(line, self.delay);
+        }
+        if !line.cfg.stages.is_empty() {
+            self.drive_mask(line, line.mask() | line.stages_mask(), true);
+            return Ok(());
+        }
+        self.drive(line, true)
+    }
+
+    // Refuse to release a line while one of its prerequisites is still in reset,
+    // EAGAIN unless the prerequisite is released within its wait
+    fn check_prerequisites(&self, line: Line<'_>) -> Result {
+        for &(prereq, wait_ms) in line.cfg.prerequisites.iter() {
+            let deadline = self.op_deadline(line.id, Duration::from_millis(wait_ms.into()));
+            while self.line_status(prereq)? {
+                if wait_ms == 0 || deadline.expired() {
+                    fail!(self.dev, EAGAIN, "Reset ID {} needs reset ID {} deasserted first\n", line, prereq);
+                }
+                self.delay.sleep(Duration::from_millis(1));
+            }
+        }
+        Ok(())
+    }
+
+    // Assert a line on behalf of a named consumer. Holds are counted per line
+    // and the line stays asserted until every hold has been released. Like the
+    // other calls of the consumer API it runs as an op, see `consumer`.
+    fn assert_for(&self, consumer: &'static CStr, rst_id: usize) -> Result {
+        self.lookup(rst_id)?;
+        let mut holds = self.holds.lock();
+        self.check_acquired(rst_id)?;
+        if !holds.iter().any(|hold| hold.id == rst_id) {
+            self.assert_line(rst_id)?;
+        }
+        let held = |hold: &&mut Hold| hold.id == rst_id && same_consumer(hold.consumer, consumer);
+        match holds.iter_mut().find(held) {
+            Some(hold) => hold.count = hold.count.saturating_add(1),
+            None => holds.try_push(Hold { consumer, id: rst_id, count: 1 })?,
+        }
+        Ok(())
+    }
+
+    // Release one hold of a consumer, the line is deasserted with the last hold.
+    // The reset core allows deassert before any assert, so a consumer without a
+    // hold saturates at zero: the deassert still goes through unless another
+    // consumer holds the line. In strict mode it fails with EINVAL instead.
+    fn deassert_for(&self, consumer: &'static CStr, rst_id: usize) -> Result {
+        self.lookup(rst_id)?;
+        let mut holds = self.holds.lock();
+        self.check_acquired(rst_id)?;
+        let held = |hold: &Hold| hold.id == rst_id && same_consumer(hold.consumer, consumer);
+        match holds.iter().position(held) {
+            Some(index) => {
+                holds[index].count -= 1;
+                if holds[index].count == 0 {
+                    holds.swap_remove(index);
+                }
+            }
+            None if self.strict => {
+                fail!(self.dev, EINVAL, "Unbalanced deassert of reset ID {} by {}\n", rst_id, consumer);
+            }
+            None => {
+                if !self.state[rst_id].unbalanced.swap(true, Ordering::Relaxed) {
+                    dev_warn!(self.dev, "Unbalanced deassert of reset ID {} by {}\n", rst_id, consumer);
+                }
+            }
+        }
+        if !holds.iter().any(|hold| hold.id == rst_id) {
+            self.deassert_line(rst_id)?;
+        }
+        Ok(())
+    }
+
+    // Drive several lines to the given states in one critical section, with a single
+    // read-modify-write per register, e.g. to put one block into reset while
+    // releasing another. Every id is checked before anything is written. Only plain
+    // level lines qualify: no restricted flags, partner or release stages. Runs
+    // as a batch op, which checks the gate and the pins of every line.
+    fn set_states(&self, states: &[(usize, bool)]) -> Result {
+        let mut lines = Vec::try_with_capacity(states.len())?;
+        for (index, &(rst_id, _)) in states.iter().enumerate() {
+            let line = self.lookup(rst_id)?;
+            self.check_protected(line)?;
+            self.check_writable(line.cfg.window, rst_id)?;
+            let plain = line.cfg.flags & LINE_RESTRICTED == 0
+                && line.cfg.partner.is_none()
+                && line.cfg.stages.is_empty();
+            if !plain {
+                fail!(self.dev, EPERM, "Reset ID {} [{}] cannot be set in a batch\n", line, line.flags());
+            }
+            if states[..index].iter().any(|&(id, _)| id == rst_id) {
+                fail!(self.dev, EINVAL, "Reset ID {} given twice\n", line);
+            }
+            let same_reg = |other: &&Line<'_>| other.addr() == line.addr();
+            if lines.iter().filter(same_reg).any(|other| other.width() != line.width()) {
+                fail!(self.dev, EINVAL, "Reset ID {} shares a register at another width\n", line);
+            }
+            lines.try_push(line)?;
+        }
+        let want = |line: &Line<'_>| states.iter().any(|&(id, asserted)| id == line.id && asserted);
+
+        // Lines sorted by register, so each register is one contiguous group
+        lines.sort_unstable_by_key(|line| line.addr());
+        self.with_reg_lock(|| {
+            let mut start = 0;
+            while start < lines.len() {
+                let addr = lines[start].addr();
+                let end = start + lines[start..].iter().take_while(|line| line.addr() == addr).count();
+                self.drive_register_to(addr, &lines[start..end], want)?;
+                start = end;
+            }
+            for line in lines.iter().filter(|line| !want(line)) {
+                self.write_post_deassert(*line);
+            }
+            Ok(())
+        })?;
+        for line in lines.iter() {
+            self.cache_status(line.id, want(line));
+        }
+        Ok(())
+    }
+
+    // Deassert lines in order, each one as an op of its own. With `rollback`, a
+    // failure part way re-asserts the lines this call released, newest first,
+    // so the batch leaves them as found. A failure reports how far the batch
+    // got, see `BulkError`.
+    fn deassert_bulk(
+        &self,
+        consumer: Option<&CStr>,
+        ids: &[u64],
+        rollback: bool,
+    ) -> core::result::Result<(), BulkError> {
+        let early = |error| BulkError { succeeded: 0, failed: None, error, rolled_back: false };
+        self.check_gate().map_err(early)?;
+        let pinned = |&rst_id: &u64| self.check_pin(consumer, self.resolve(rst_id));
+        ids.iter().try_for_each(pinned).map_err(early)?;
+        let mut released = Vec::try_with_capacity(ids.len()).map_err(|_| early(error::code::ENOMEM))?;
+        for (index, &rst_id) in ids.iter().enumerate() {
+            let deasserted = self.op_for(OpKind::Deassert, consumer, rst_id, |data, id| {
+                let was_asserted = matches!(data.line_status(id), Ok(true));
+                data.deassert_line(id)?;
+                Ok(was_asserted)
+            });
+            match deasserted {
+                Ok(true) => {
+                    // Has the capacity of every id
+                    let _ = released.try_push(rst_id);
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    if rollback {
+                        for &id in released.iter().rev() {
+                            if self.op_for(OpKind::Assert, consumer, id, BstMap::assert_line).is_err() {
+                                dev_err!(self.dev, "Could not roll back reset ID {}\n", id);
+                            }
+                        }
+                    }
+                    return Err(BulkError {
+                        succeeded: index,
+                        failed: Some(rst_id),
+                        error,
+                        rolled_back: rollback,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Enable a block's clock, let it settle, then release the block from reset, in
+    // that order so the block never leaves reset without a running clock. The
+    // line stays in reset when the clock fails to enable.
+    fn enable_clock_then_deassert<C: BlockClock>(&self, clk: C, rst_id: usize) -> Result<C::Enabled> {
+        let clk = clk.enable().map_err(|e| {
+            dev_err!(self.dev, "Clock of reset ID {} failed to enable\n", rst_id);
+            e
+        })?;
+        self.delay.sleep(Duration::from_millis(self.clock_settle_ms.into()));
+        self.deassert_line(rst_id)?;
+        Ok(clk)
+    }
+
+    // Bring a block up in the canonical order: enable its clock, release it from
+    // reset, then wait for its ready bit if it has one. A failed ready wait
+    // unwinds the sequence, the line goes back into reset before the clock is
+    // disabled, so the block is left as it was found.
+    fn bring_up<C: BlockClock>(&self, clk: C, rst_id: usize) -> Result<C::Enabled> {
+        let clk = self.enable_clock_then_deassert(clk, rst_id)?;
+        let ready = match self.lines().get(rst_id).and_then(|cfg| cfg.ready) {
+            Some(ready) => ready,
+            None => return Ok(clk),
+        };
+        let deadline = self.op_deadline(rst_id, Duration::from_millis(self.ready_timeout_ms.into()));
+        let waited = self
+            .lookup(rst_id)
+            .and_then(|line| self.wait_ready(line, ready, deadline, self.delay));
+        if let Err(e) = waited {
+            if self.assert_line(rst_id).is_err() {
+                dev_err!(self.dev, "Could not put reset ID {} back into reset\n", rst_id);
+            }
+            // Dropping the enabled clock disables it
+            drop(clk);
+            return Err(e);
+        }
+        Ok(clk)
+    }
+
+    // Drive a line into or out of reset, then poll its status until it reads that
+    // state, ETIMEDOUT once `timeout` has passed. Status applies the line's
+    // polarity, so `asserted` is the logical state whatever the wiring. Lines
+    // the hardware releases by itself never stay asserted and fail with EPERM.
+    fn drive_to(&self, rst_id: usize, asserted: bool, timeout: Duration) -> Result {
+        let releases = |cfg: &LineConfig| cfg.flags & (LINE_SELF_CLEARING | LINE_EDGE_TRIGGERED) != 0;
+        if asserted && self.lines().get(rst_id).map_or(false, releases) {
+            fail!(self.dev, EPERM, "Reset ID {} cannot be held asserted\n", rst_id);
+        }
+        let deadline = self.op_deadline(rst_id, timeout);
+        if asserted {
+            self.assert_line(rst_id)?;
+        } else {
+            self.deassert_line(rst_id)?;
+        }
+        while self.line_status(rst_id)? != asserted {
+            if deadline.expired() {
+                let state = if asserted { "asserted" } else { "deasserted" };
+                fail!(self.dev, ETIMEDOUT, "Reset ID {} did not read {} in time\n", rst_id, state);
+            }
+            self.delay.sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    // Acquire a line exclusively for a consumer and keep it asserted or deasserted,
+    // as asked, for as long as the consumer is bound. EBUSY while other consumers
+    // hold or have acquired the line, and while acquired the hold API refuses it
+    // too. The status it had is restored by `release_exclusive`.
+    fn acquire_exclusive(&self, consumer: &'static CStr, rst_id: usize, asserted: bool) -> Result {
+        self.lookup(rst_id)?;
+        let holds = self.holds.lock();
+        let mut acquired = self.acquired.lock();
+        if holds.iter().any(|hold| hold.id == rst_id) || acquired.iter().any(|acq| acq.id == rst_id) {
+            fail!(self.dev, EBUSY, "Reset ID {} is in use, {} cannot acquire it\n", rst_id, consumer);
+        }
+        acquired.try_reserve(1)?;
+        let prior = self.line_status(rst_id)?;
+        if asserted {
+            self.assert_line(rst_id)?;
+        } else {
+            self.deassert_line(rst_id)?;
+        }
+        // Cannot fail, the room was reserved above
+        let _ = acquired.try_push(Acquisition { consumer, id: rst_id, asserted, prior });
+        Ok(())
+    }
+
+    // Release a line acquired with `acquire_exclusive` and restore the status it
+    // had before. The acquisition ends even when the line cannot be restored.
+    fn release_exclusive(&self, consumer: &'static CStr, rst_id: usize) -> Result {
+        let prior = {
+            let mut acquired = self.acquired.lock();
+            let mine = |acq: &Acquisition| acq.id == rst_id && same_consumer(acq.consumer, consumer);
+            let index = acquired.iter().position(mine).ok_or(error::code::EINVAL)?;
+            acquired.swap_remove(index).prior
+        };
+        if prior {
+            self.assert_line(rst_id)
+        } else {
+            self.deassert_line(rst_id)
+        }
+    }
+
+    // Refuse the hold API on a line acquired exclusively
+    fn check_acquired(&self, rst_id: usize) -> Result {
+        if self.acquired.lock().iter().any(|acq| acq.id == rst_id) {
+            return Err(error::code::EBUSY);
+        }
+        Ok(())
+    }
+
+    // Whether a line is acquired by a consumer other than `consumer`, by any
+    // consumer when `consumer` is `None`
+    fn acquired_by_other(&self, consumer: Option<&CStr>, rst_id: usize) -> bool {
+        let other = |acq: &Acquisition| consumer.map_or(true, |c| !same_consumer(c, acq.consumer));
+        self.acquired.lock().iter().any(|acq| acq.id == rst_id && other(acq))
+    }
+
+    // Pin a line for a consumer, e.g. across the firmware update of a block:
+    // until unpinned, asserts, deasserts and resets by anyone else fail with
+    // EBUSY, while the consumer keeps driving the line through the consumer
+    // API. The line is unpinned when the returned guard is dropped.
+    fn pin(&self, consumer: &'static CStr, rst_id: usize) -> Result<LinePin<'_>> {
+        self.lookup(rst_id)?;
+        let mut pins = self.pins.lock();
+        if let Some(pin) = pins.iter().find(|pin| pin.id == rst_id) {
+            fail!(self.dev, EBUSY, "Reset ID {} is pinned by {}\n", rst_id, pin.consumer);
+        }
+        pins.try_push(Pin { consumer, id: rst_id })?;
+        dev_info!(self.dev, "Reset ID {} pinned by {}\n", rst_id, consumer);
+        Ok(LinePin { data: self, rst_id })
+    }
+
+    // Refuse a change of a line pinned by a consumer other than `consumer`, by
+    // any consumer when `consumer` is `None`
+    fn check_pin(&self, consumer: Option<&CStr>, rst_id: usize) -> Result {
+        let pins = self.pins.lock();
+        match pins.iter().find(|pin| pin.id == rst_id) {
+            Some(pin) if consumer.map_or(true, |c| !same_consumer(c, pin.consumer)) => {
+                fail!(self.dev, EBUSY, "Reset ID {} is pinned by {}\n", rst_id, pin.consumer)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Every line a consumer currently holds asserted
+    fn lines_held_by(&self, consumer: &CStr) -> Result<Vec<usize>> {
+        let holds = self.holds.lock();
+        let mut ids = Vec::new();
+        for hold in holds.iter().filter(|hold| same_consumer(hold.consumer, consumer)) {
+            ids.try_push(hold.id)?;
+        }
+        Ok(ids)
+    }
+
+    // Drive a plain level line and return the register value read before and after
+    // the change, for diagnosing polarity and mask problems. Run as the body of an
+    // assert or deassert op.
+    #[cfg(debug_assertions)]
+    fn drive_traced(&self, rst_id: usize, asserted: bool) -> Result<(u32, u32)> {
+        let line = self.lookup(rst_id)?;
+        if line.cfg.flags & LINE_RESTRICTED != 0 || line.cfg.partner.is_some() {
+            fail!(self.dev, EPERM, "Reset ID {} [{}] cannot be traced\n", line, line.flags());
+        }
+        self.check_writable(line.cfg.window, rst_id)?;
+        let addr = line.addr();
+        self.with_reg_lock(|| {
+            let before = self.ctrl_read(addr, line.width());
+            self.drive_register(addr, &[line], asserted)?;
+            Ok((before, self.ctrl_read(addr, line.width())))
+        })
+    }
+
+    // Flip a plain level line whatever its current state, for test harnesses.
+    // Returns whether the line is now held in reset.
+    fn toggle_line(&self, rst_id: usize) -> Result<bool> {
+        let line = self.lookup(rst_id)?;
+        if line.cfg.flags & LINE_RESTRICTED != 0 || line.cfg.partner.is_some() {
+            fail!(self.dev, EPERM, "Reset ID {} [{}] cannot be toggled\n", line, line.flags());
+        }
+        self.check_writable(line.cfg.window, rst_id)?;
+        let addr = line.addr();
+        self.with_reg_lock(|| {
+            let asserted = !line.asserted(self.ctrl_read(addr, line.width()));
+            self.drive_register(addr, &[line], asserted)?;
+            Ok(asserted)
+        })
+    }
+
+    // Trigger an edge-triggered line with an assert, hold, deassert pulse
+    fn pulse(&self, line: Line<'_>, delay: &dyn DelayProvider) -> Result {
+        self.drive(line, true)?;
+        self.hold_line(line, delay, line.map.flags & RESET_LONG_HOLD_TIME != 0);
+        self.drive(line, false)
+    }
+
+    // Release a line from reset
+    fn deassert_line(&self, rst_id: usize) -> Result {
+        if let Some(bit) = self.direct_writable(rst_id)? {
+            self.with_reg_lock(|| self.set_bit(bit, false));
+            return Ok(());
+        }
+        let line = self.lookup(rst_id)?;
+        self.check_protected(line)?;
+        self.check_writable(line.cfg.window, rst_id)?;
+        if line.cfg.flags & LINE_ASSERT_ONLY != 0 {
+            fail!(self.dev, EPERM, "Reset ID {} is assert-only\n", line);
+        }
+        self.check_prerequisites(line)?;
+        // Hardware releases self-clearing lines on its own and edge-triggered
+        // lines are already released at the end of their pulse
+        if line.cfg.flags & (LINE_SELF_CLEARING | LINE_EDGE_TRIGGERED) != 0 {
+            return Ok(());
+        }
+        if !line.cfg.stages.is_empty() {
+            self.release_staged(line, self.delay);
+            return Ok(());
+        }
+        self.drive(line, false)
+    }
+
+    // Release the stages of a multi-rail block in order, waiting after each one
+    // to avoid inrush, then the line itself
+    fn release_staged(&self, line: Line<'_>, delay: &dyn DelayProvider) {
+        for stage in line.cfg.stages.iter() {
+            self.drive_mask(line, 1 << stage.bit_idx, false);
+            delay.sleep(Duration::from_millis(stage.delay_ms.into()));
+        }
+        self.drive_mask(line, line.mask(), false);
+    }
+
+    // Drive arbitrary bits of a line's register with the line's polarity
+    fn drive_mask(&self, line: Line<'_>, mask: u32, asserted: bool) {
+        let addr = line.addr();
+        let (set, width) = (asserted != line.active_low(), line.width());
+        let drives_line = mask & line.mask() != 0;
+        self.with_reg_lock(|| {
+            self.set_enables(&[line], true);
+            match line.cfg.window.and_then(|window| self.set_clr[window]) {
+                Some(set_clr) if set => self.ctrl_write(mask, addr + set_clr.set_offset, width),
+                Some(set_clr) => self.ctrl_write(mask, addr + set_clr.clr_offset, width),
+                None => {
+                    let reg_val = self.ctrl_read(addr, width);
+                    self.check_shadow(line, reg_val);
+                    self.ctrl_write(if set { reg_val | mask } else { reg_val & !mask }, addr, width);
+                }
+            }
+            self.set_enables(&[line], false);
+            if drives_line {
+                self.set_shadow(line.id, asserted);
+                if !asserted {
+                    self.write_post_deassert(line);
+                }
+            }
+        });
+        if drives_line {
+            self.cache_status(line.id, asserted);
+        }
+    }
+
+    // Start the reset of a self-clearing line without waiting for the hardware to
+    // release it, `reset_pending` tells when it has
+    fn start_async_reset(&self, rst_id: usize) -> Result {
+        let line = self.lookup(rst_id)?;
+        if line.cfg.flags & LINE_SELF_CLEARING == 0 {
+            fail!(self.dev, EINVAL, "Reset ID {} is not self-clearing\n", line);
+        }
+        self.state[rst_id].pending.store(true, Ordering::Release);
+        self.assert_line(rst_id).map_err(|e| {
+            self.state[rst_id].pending.store(false, Ordering::Release);
+            e
+        })
+    }
+
+    // Whether an asynchronous reset of a line is still in progress
+    fn reset_pending(&self, rst_id: usize) -> Result<bool> {
+        self.lookup(rst_id)?;
+        let state = &self.state[rst_id];
+        if state.async_queued.load(Ordering::Acquire) {
+            return Ok(true);
+        }
+        let pending = &state.pending;
+        if pending.load(Ordering::Acquire) && !self.line_status(rst_id)? {
+            pending.store(false, Ordering::Release);
+        }
+        Ok(pending.load(Ordering::Acquire))
+    }
+
+    // Run the full `reset()` of a line on the system workqueue and return at once,
+    // for callers that cannot block through long holds. `reset_pending` tells
+    // when it has completed, failures are logged. EBUSY if one is already queued.
+    fn async_reset(&self, rst_id: usize) -> Result {
+        if !self.ready.load(Ordering::Acquire) {
+            return Err(error::code::EAGAIN);
+        }
+        self.check_gate()?;
+        let line = self.lookup(rst_id)?;
+        ResetSequence::for_line(line)?;
+        self.check_pin(None, rst_id)?;
+        if self.state[rst_id].async_queued.swap(true, Ordering::AcqRel) {
+            return Err(error::code::EBUSY);
+        }
+        self.async_work.queue();
+        Ok(())
+    }
+
+    // Run every queued asynchronous reset, on the workqueue
+    fn run_async_resets(&self) {
+        for (rst_id, state) in self.state.iter().enumerate() {
+            if !state.async_queued.load(Ordering::Acquire) {
+                continue;
+            }
+            if let Err(e) = self.op(OpKind::Reset, rst_id as u64, BstMap::reset_and_wait_ready) {
+                dev_err!(self.dev, "Asynchronous reset of ID {} failed: {}\n", rst_id, e.to_kernel_errno());
+            }
+            state.async_queued.store(false, Ordering::Release);
+        }
+    }
+
+    // Stop the asynchronous resets at remove, queued ones are dropped
+    fn stop_async_resets(&self) {
+        self.ready.store(false, Ordering::Release);
+        self.async_work.cancel_sync();
+        for state in self.state.iter() {
+            state.async_queued.store(false, Ordering::Release);
+        }
+    }
+
+    // Report the holds consumers never released at remove, i.e. lines still
+    // asserted through the hold API, with their consumers. With
+    // `bst,release-leaked-holds` the holds are dropped and the lines deasserted,
+    // before the safe states are applied so those still win.
+    fn report_leaked_holds(&self) {
+        let mut holds = self.holds.lock();
+        if holds.is_empty() {
+            return;
+        }
+        for hold in holds.iter() {
+            dev_warn!(
+                self.dev,
+                "Reset ID {} still held {} time(s) by {} on remove\n",
+                hold.id,
+                hold.count,
+                hold.consumer
+            );
+        }
+        if !self.release_leaked_holds {
+            return;
+        }
+        let _power = match self.power() {
+            Ok(power) => power,
+            Err(e) => {
+                dev_err!(self.dev, "Could not power up to release leaked holds: {}\n", e.to_kernel_errno());
+                return;
+            }
+        };
+        while let Some(hold) = holds.pop() {
+            // A line held by several consumers is deasserted once, with its last hold
+            if holds.iter().any(|other| other.id == hold.id) {
+                continue;
+            }
+            match self.deassert_line(hold.id) {
+                Ok(()) => dev_info!(self.dev, "Reset ID {} released on remove\n", hold.id),
+                Err(e) => dev_err!(
+                    self.dev,
+                    "Could not release reset ID {} on remove: {}\n",
+                    hold.id,
+                    e.to_kernel_errno()
+                ),
+            }
+        }
+    }
+
+    // Drive the lines of `bst,safe-state-on-remove` to their safe state at remove,
+    // once ops are refused. Lines the driver may not drive that way fail as they
+    // would for a consumer, failures are logged and the others still applied.
+    fn apply_safe_state(&self) {
+        if self.safe_state.is_empty() {
+            return;
+        }
+        let _power = match self.power() {
+            Ok(power) => power,
+            Err(e) => {
+                dev_err!(self.dev, "Could not power up to apply safe states: {}\n", e.to_kernel_errno());
+                return;
+            }
+        };
+        for &(rst_id, asserted) in self.safe_state.iter() {
+            let ret = if asserted { self.assert_line(rst_id) } else { self.deassert_line(rst_id) };
+            let state = if asserted { "asserted" } else { "deasserted" };
+            match ret {
+                Ok(()) => dev_info!(self.dev, "Reset ID {} {} on remove\n", rst_id, state),
+                Err(e) => dev_err!(
+                    self.dev,
+                    "Could not leave reset ID {} {} on remove: {}\n",
+                    rst_id,
+                    state,
+                    e.to_kernel_errno()
+                ),
+            }
+        }
+    }
+
+    // Status reported to consumers. In the middle of a `reset()` of the line the
+    // register shows a transient state, so EBUSY is returned rather than a
+    // status that is about to change.
+    fn reported_status(&self, rst_id: usize) -> Result<bool> {
+        if let Some(state) = self.state.get(rst_id) {
+            if state.resetting.load(Ordering::Acquire) {
+                return Err(error::code::EBUSY);
+            }
+        }
+        self.line_status(rst_id)
+    }
+
+    // Whether a line is currently held in reset. The status of a line without a
+    // mirror is one register read and takes no lock: `readl` and friends order
+    // it against the writes of this CPU and a writer never leaves a register
+    // half written, so a reader sees the state before or after a write. A
+    // mirrored line is read twice, the status and its mirror, and both reads
+    // must see the same write: they run under `status_lock` for reading, which
+    // every control write holds for writing, and concurrent readers still never
+    // wait on each other. The transient states within a `reset()` are covered
+    // by EBUSY instead.
+    fn line_status(&self, rst_id: usize) -> Result<bool> {
+        if let Some(bit) = self.direct(rst_id)? {
+            return Ok(bit.is_set(self.regs));
+        }
+        let line = self.lookup(rst_id)?;
+        let mirrored = line.cfg.status_mirror.is_some();
+        #[cfg(not(feature = "no-sleep"))]
+        let _guard = mirrored.then(|| self.status_lock.read());
+        #[cfg(feature = "no-sleep")]
+        let _guard = mirrored.then(|| self.status_lock.lock());
+        let bit = line.status_bit(self.regs);
+        let status = line.status_from_bit(bit);
+        #[cfg(debug_assertions)]
+        self.check_status(line, bit, status);
+        self.check_mirror(line, status)?;
+        self.cache_status(rst_id, status != 0);
+        Ok(status != 0)
+    }
+
+    // Compare a status read with the line's status mirror, if it has one. A
+    // mismatch is a hardware fault: always logged, and with `bst,status-mirror-strict`
+    // reported as EIO rather than trusting either register.
+    fn check_mirror(&self, line: Line<'_>, status: u32) -> Result {
+        let mirror = match line.cfg.status_mirror {
+            Some(reg) => line.status_of(self.regs, reg),
+            None => return Ok(()),
+        };
+        if mirror == status {
+            return Ok(());
+        }
+        dev_err!(self.dev, "Reset ID {} status {} disagrees with its mirror {}\n", line, status, mirror);
+        if self.status_mirror_strict {
+            return Err(error::code::EIO);
+        }
+        Ok(())
+    }
+
+    // Catch regressions in the bit math: the bit extracted from the status
+    // register must lie within the line's mask, shifted down to 0 or 1, and the
+    // status must be 0 or 1. Reported once per controller; returns whether the
+    // read was well formed.
+    #[cfg(debug_assertions)]
+    fn check_status(&self, line: Line<'_>, bit: u32, status: u32) -> bool {
+        if bit <= 1 && status <= 1 {
+            return true;
+        }
+        if !self.status_warned.swap(true, Ordering::Relaxed) {
+            dev_warn!(self.dev, "Reset ID {} extracted status bits {:#x} (status {}) outside the mask\n",
+                line, bit, status);
+            // SAFETY: `dump_stack` has no preconditions.
+            unsafe { bindings::dump_stack() };
+        }
+        false
+    }
+
+    // Sleep for the reset hold time, doubled for long-hold lines
+    fn hold(&self, delay: &dyn DelayProvider, long_hold: bool) {
+        delay.sleep(Duration::from_millis(self.hold_time_ms));
+        if long_hold {
+            delay.sleep(Duration::from_millis(self.hold_time_ms));
+        }
+    }
+
+    // Sleep for the hold time of a line, its own from `bst,line-hold-times` if it
+    // has one, the controller-wide one otherwise
+    fn hold_line(&self, line: Line<'_>, delay: &dyn DelayProvider, long_hold: bool) {
+        match line.cfg.hold_times {
+            Some(times) if long_hold => delay.sleep(Duration::from_micros(times.long_hold_us.into())),
+            Some(times) => delay.sleep(Duration::from_micros(times.hold_us.into())),
+            None => self.hold(delay, long_hold),
+        }
+    }
+
+    // Completion interrupt handler: wake the `reset()` waiting for its line to
+    // release. Nothing to do when no line is waiting or it is still in reset.
+    fn complete_reset_irq(&self) {
+        let waiting = self.irq_waiting.load(Ordering::Acquire);
+        if waiting == 0 {
+            return;
+        }
+        // Never maps a window, the waiting line's is mapped already
+        let released = self.line(waiting - 1).map_or(false, |line| line.read_status(self.regs) == 0);
+        if !released {
+            return;
+        }
+        if self.irq_waiting.compare_exchange(waiting, 0, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+            self.completion.complete();
+        }
+    }
+
+    // Run the reset sequence of a line, self-clearing lines are polled until released
+    fn reset_until(&self, line: Line<'_>, deadline: Deadline, delay: &dyn DelayProvider) -> Result {
+        self.check_protected(line)?;
+        self.check_writable(line.cfg.window, line.id)?;
+        let id = line.id;
+        match ResetSequence::for_line(line)? {
+            ResetSequence::Cycle { long_hold } => {
+                self.run_phase_hook(Phase::PreAssert, id);
+                self.assert_line(id)?;
+                self.run_phase_hook(Phase::PostAssert, id);
+                self.hold_line(line, delay, long_hold);
+                self.run_phase_hook(Phase::PreDeassert, id);
+                self.deassert_line(id)?;
+                self.run_phase_hook(Phase::PostDeassert, id);
+                self.hold_line(line, delay, long_hold);
+            }
+            ResetSequence::Pulse { long_hold } => {
+                // Armed before the assert, a fast release may interrupt right away
+                let irq = line.cfg.flags & LINE_COMPLETION_IRQ != 0;
+                if irq {
+                    self.completion.reinit();
+                    self.irq_waiting.store(id + 1, Ordering::Release);
+                }
+                self.run_phase_hook(Phase::PreAssert, id);
+                if let Err(e) = self.assert_line(id) {
+                    self.irq_waiting.store(0, Ordering::Release);
+                    return Err(e);
+                }
+                self.run_phase_hook(Phase::PostAssert, id);
+                self.hold_line(line, delay, long_hold);
+                // The hardware deasserts, its release is the deassert phase
+                self.run_phase_hook(Phase::PreDeassert, id);
+                if irq {
+                    let released = self.completion.wait(deadline.remaining());
+                    self.irq_waiting.store(0, Ordering::Release);
+                    if !released {
+                        fail!(self.dev, ETIMEDOUT, "Reset ID {} did not signal its release\n", line);
+                    }
+                }
+                while self.line_status(id)? {
+                    if deadline.expired() {
+                        fail!(self.dev, ETIMEDOUT, "Reset ID {} did not self-clear\n", line);
+                    }
+                    delay.sleep(Duration::from_millis(1));
+                }
+                self.run_phase_hook(Phase::PostDeassert, id);
+            }
+            ResetSequence::Edge => {
+                let long_hold = line.map.flags & RESET_LONG_HOLD_TIME != 0;
+                self.run_phase_hook(Phase::PreAssert, id);
+                self.drive(line, true)?;
+                self.run_phase_hook(Phase::PostAssert, id);
+                self.hold_line(line, delay, long_hold);
+                self.run_phase_hook(Phase::PreDeassert, id);
+                self.drive(line, false)?;
+                self.run_phase_hook(Phase::PostDeassert, id);
+            }
+        }
+        if deadline.expired() {
+            fail!(self.dev, ETIMEDOUT, "Reset ID {} exceeded its time budget\n", line);
+        }
+        Ok(())
+    }
+
+    // Poll the ready bit of a line until the block reports ready
+    fn wait_ready(
+        &self,
+        line: Line<'_>,
+        ready: RegBit,
+        deadline: Deadline,
+        delay: &dyn DelayProvider,
+    ) -> Result {
+        while !ready.is_set(self.regs) {
+            if deadline.expired() {
+                fail!(self.dev, ETIMEDOUT, "Reset ID {} not ready in time\n", line);
+            }
+            delay.sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    // Reset a line and wait for its ready bit, giving up with ETIMEDOUT once the
+    // whole operation, including the verification waits, has used up `budget`
+    fn reset_with_timeout(
+        &self,
+        rst_id: usize,
+        budget: Duration,
+        delay: &dyn DelayProvider,
+    ) -> Result {
+        if let Some(bit) = self.direct_writable(rst_id)? {
+            self.run_phase_hook(Phase::PreAssert, rst_id);
+            self.with_reg_lock(|| self.set_bit(bit, true));
+            self.run_phase_hook(Phase::PostAssert, rst_id);
+            self.hold(delay, false);
+            self.run_phase_hook(Phase::PreDeassert, rst_id);
+            self.with_reg_lock(|| self.set_bit(bit, false));
+            self.run_phase_hook(Phase::PostDeassert, rst_id);
+            self.hold(delay, false);
+            return Ok(());
+        }
+        let deadline = self.op_deadline(rst_id, budget);
+        let line = self.lookup(rst_id)?;
+        self.reset_until(line, deadline, delay)?;
+        match line.cfg.ready {
+            Some(ready) => {
+                let ready_deadline = Deadline::after(Duration::from_millis(self.ready_timeout_ms.into()));
+                self.wait_ready(line, ready, deadline.earliest(ready_deadline), delay)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Reset a line, then wait for its ready bit when one is configured. From the
+    // reset framework the caller holds nothing, so a held line follows its
+    // `bst,shared-reset-policy` as if held by others.
+    fn reset_and_wait_ready(&self, rst_id: usize) -> Result {
+        self.reset_shared(None, rst_id, None)
+    }
+
+    // `reset()` on behalf of a hold API consumer, `policy` overrides the line's
+    // policy for this call. Lines still held after the reset are put back into
+    // reset, so the reset is a pulse out of it as far as the holders see.
+    fn reset_for(
+        &self,
+        consumer: &'static CStr,
+        rst_id: usize,
+        policy: Option<SharedPolicy>,
+    ) -> Result {
+        self.reset_shared(Some(consumer), rst_id, policy)
+    }
+
+    // Apply the shared policy of a line, then reset it
+    fn reset_shared(
+        &self,
+        consumer: Option<&CStr>,
+        rst_id: usize,
+        policy: Option<SharedPolicy>,
+    ) -> Result {
+        if self.acquired_by_other(consumer, rst_id) {
+            fail!(self.dev, EBUSY, "Reset ID {} is acquired by another consumer\n", rst_id);
+        }
+        let (held, others) = {
+            let holds = self.holds.lock();
+            let other = |hold: &Hold| consumer.map_or(true, |c| !same_consumer(c, hold.consumer));
+            (
+                holds.iter().any(|hold| hold.id == rst_id),
+                holds.iter().any(|hold| hold.id == rst_id && other(hold)),
+            )
+        };
+        if held {
+            let policy = match policy {
+                Some(policy) => policy,
+                None => self.lines().get(rst_id).map_or(SharedPolicy::Refuse, |cfg| cfg.shared_policy),
+            };
+            match policy {
+                SharedPolicy::Force => {
+                    dev_warn!(self.dev, "Forcing reset of held reset ID {}\n", rst_id);
+                }
+                SharedPolicy::SoleHolder if !others => {}
+                _ => fail!(self.dev, EBUSY, "Reset ID {} is held by other consumers\n", rst_id),
+            }
+        }
+        self.run_reset(rst_id)?;
+        if held && self.holds.lock().iter().any(|hold| hold.id == rst_id) {
+            self.assert_line(rst_id)?;
+        }
+        // A reset by the consumer that acquired the line ends in the acquired state
+        let kept = self.acquired.lock().iter().find(|acq| acq.id == rst_id).map(|acq| acq.asserted);
+        if kept == Some(true) {
+            self.assert_line(rst_id)?;
+        }
+        Ok(())
+    }
+
+    // The reset sequence proper, with the ready wait and post-reset callback
+    fn run_reset(&self, rst_id: usize) -> Result {
+        {
+            let _guard = self.reset_lock.lock();
+            let budget = Duration::from_millis(self.reset_timeout_ms.into());
+            let state = self.state.get(rst_id);
+            if let Some(state) = state {
+                self.check_storm(rst_id, state)?;
+                state.resetting.store(true, Ordering::Release);
+            }
+            let ret = self.bounded(rst_id, || self.reset_with_timeout(rst_id, budget, self.delay));
+            if let Some(state) = state {
+                state.resetting.store(false, Ordering::Release);
+                if ret.is_ok() {
+                    state.last_reset_ns.store(ktime_get_ns() as u64, Ordering::Relaxed);
+                }
+            }
+            ret?;
+        }
+        self.run_post_reset(rst_id);
+        Ok(())
+    }
+
+    // Count a `reset()` of a line against the `bst,storm-threshold` limit. Going over
+    // it warns once per window and, with a cooldown, refuses resets of the line
+    // with EBUSY until it has passed. Called with `reset_lock` held.
+    fn check_storm(&self, rst_id: usize, state: &LineState) -> Result {
+        let storm = match &self.storm {
+            Some(storm) => storm,
+            None => return Ok(()),
+        };
+        let now = ktime_get_ns() as u64;
+        if now < state.storm_until_ns.load(Ordering::Relaxed) {
+            return Err(error::code::EBUSY);
+        }
+        if now.saturating_sub(state.storm_start_ns.load(Ordering::Relaxed)) > storm.window_ns {
+            state.storm_start_ns.store(now, Ordering::Relaxed);
+            state.storm_count.store(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        let count = state.storm_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if count <= storm.threshold {
+            return Ok(());
+        }
+        if count == storm.threshold + 1 {
+            let threshold = storm.threshold;
+            dev_warn!(self.dev, "Reset storm on ID {}: over {} resets in the window\n", rst_id, threshold);
+        }
+        if storm.cooldown_ns > 0 {
+            state.storm_until_ns.store(now + storm.cooldown_ns, Ordering::Relaxed);
+            state.storm_start_ns.store(0, Ordering::Relaxed);
+            fail!(self.dev, EBUSY, "Refusing resets of ID {} during the storm cooldown\n", rst_id);
+        }
+        Ok(())
+    }
+
+    // Register the callback run after every successful `reset()` of a line,
+    // replacing any previous one. `None` removes it.
+    fn set_post_reset(&self, rst_id: usize, callback: Option<PostReset>) -> Result {
+        self.lookup(rst_id)?;
+        let mut callbacks = self.post_reset.lock();
+        callbacks.retain(|(id, _)| *id != rst_id);
+        if let Some(callback) = callback {
+            callbacks.try_push((rst_id, callback))?;
+        }
+        Ok(())
+    }
+
+    // Run the post-reset callback of a line. Called without any of the
+    // controller locks held, so the callback may use the controller itself.
+    fn run_post_reset(&self, rst_id: usize) {
+        let callback = self.post_reset.lock().iter().find(|(id, _)| *id == rst_id).map(|(_, cb)| *cb);
+        if let Some(callback) = callback {
+            callback(rst_id);
+        }
+    }
+
+    // Register the hook run at `phase` of every `reset()`, replacing any previous
+    // one. `None` removes it.
+    fn set_phase_hook(&self, phase: Phase, hook: Option<PhaseHook>) {
+        self.phase_hooks.lock()[phase as usize] = hook;
+    }
+
+    // Run the hook of a phase boundary. Called outside `reg_lock` but with
+    // `reset_lock` held, so the hook may assert or deassert lines of the
+    // controller but must not reset them.
+    fn run_phase_hook(&self, phase: Phase, rst_id: usize) {
+        let hook = self.phase_hooks.lock()[phase as usize];
+        if let Some(hook) = hook {
+            hook(rst_id);
+        }
+    }
+}
+
+// More `reset()` calls of a line than `threshold` within `window_ns` is a storm
+struct StormLimit {
+    threshold: u32,
+    window_ns: u64,
+    // Time resets of the line are refused for after a storm, zero to only warn
+    cooldown_ns: u64,
+}
+
+impl StormLimit {
+    fn from_dt(pdev: &platform::Device) -> Option<Self> {
+        let threshold = dt::read_u32(pdev, c_str!("bst,storm-threshold"))?;
+        let window_ms = dt::read_u32(pdev, c_str!("bst,storm-window-ms")).unwrap_or(1000);
+        let cooldown_ms = dt::read_u32(pdev, c_str!("bst,storm-cooldown-ms")).unwrap_or(0);
+        Some(StormLimit {
+            threshold,
+            window_ns: u64::from(window_ms) * 1_000_000,
+            cooldown_ns: u64::from(cooldown_ms) * 1_000_000,
+        })
+    }
+}
+
+// SAFETY: Called by the workqueue with the `async_work` of a `BstMap`, which is
+// cancelled before the data goes away.
+unsafe extern "C" fn async_reset_work(work: *mut bindings::work_struct) {
+    // SAFETY: `work` is the `async_work` of a live `BstMap`, see above.
+    let data = unsafe { &*kernel::container_of!(work, BstMap, async_work) };
+    data.run_async_resets();
+}
+
+/// How far a bulk op got before it failed.
+pub struct BulkError {
+    /// Ids at the front of the batch that went through.
+    pub succeeded: usize,
+    /// Id that failed, `None` when the op failed before any id.
+    pub failed: Option<u64>,
+    /// Error the op failed with.
+    pub error: error::Error,
+    /// Whether the ids that went through were returned to their previous state.
+    pub rolled_back: bool,
+}
+
+impl From<BulkError> for error::Error {
+    fn from(bulk: BulkError) -> Self {
+        bulk.error
+    }
+}
+
+// Clock of a block, enabled before the block leaves reset, see
+// `enable_clock_then_deassert`. Dropping the enabled clock disables it.
+trait BlockClock {
+    type Enabled;
+
+    fn enable(self) -> Result<Self::Enabled>;
+}
+
+impl BlockClock for Clk {
+    type Enabled = EnabledClk;
+
+    fn enable(self) -> Result<EnabledClk> {
+        self.prepare_enable()
+    }
+}
+
+/// Callback restoring a block's state after it was reset, receives the reset id.
+pub type PostReset = fn(usize);
+
+/// Phase boundary of a `reset()` at which a sequencing hook runs.
+#[derive(Clone, Copy)]
+pub enum Phase {
+    /// Before the line is asserted.
+    PreAssert,
+    /// Once the line is asserted.
+    PostAssert,
+    /// Before the line is deasserted.
+    PreDeassert,
+    /// Once the line is deasserted.
+    PostDeassert,
+}
+
+impl Phase {
+    const COUNT: usize = 4;
+}
+
+/// Sequencing hook, e.g. for instrumentation, receives the reset id.
+pub type PhaseHook = fn(usize);
+
+// Point in time after which a composite operation gives up
+#[derive(Clone, Copy)]
+struct Deadline(i64);
+
+impl Deadline {
+    fn after(budget: Duration) -> Self {
+        let budget_ns = i64::try_from(budget.as_nanos()).unwrap_or(i64::MAX);
+        Deadline(ktime_get_ns().saturating_add(budget_ns))
+    }
+
+    fn expired(&self) -> bool {
+        ktime_get_ns() > self.0
+    }
+
+    fn remaining(&self) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(ktime_get_ns()).max(0) as u64)
+    }
 
-// SAFETY: `BstMap` holds a non-null pointer to GPIO registers, which is safe to be used from any thread.
-unsafe impl Send for BstMap {}
-// SAFETY: `BstMap` holds a non-null pointer to GPIO registers, references to which are safe to be used from any thread.
-unsafe impl Sync for BstMap {}
+    fn earliest(self, other: Self) -> Self {
+        Deadline(self.0.min(other.0))
+    }
+}
 
-// Define a structure to hold reset addresses
-#[derive(Clone)]
-struct BstMap{
-    bst_address:[Option<*mut u8>; 5]
+// Monotonic time in nanoseconds
+fn ktime_get_ns() -> i64 {
+    // SAFETY: `ktime_get` has no preconditions.
+    unsafe { bindings::ktime_get() }
+}
+
+// Sequence performed by `reset()` for a given line
+enum ResetSequence {
+    // Assert, hold, deassert, hold
+    Cycle { long_hold: bool },
+    // Assert and hold, hardware releases the line by itself
+    Pulse { long_hold: bool },
+    // A single assert-hold-deassert edge
+    Edge,
+}
+
+impl ResetSequence {
+    // Pick the sequence matching the line flags, or fail when a full cycle is impossible
+    fn for_line(line: Line<'_>) -> Result<Self> {
+        let long_hold = line.map.flags & RESET_LONG_HOLD_TIME != 0;
+        if line.cfg.flags & (LINE_ASSERT_ONLY | LINE_DEASSERT_ONLY) != 0 {
+            return Err(error::code::EPERM);
+        }
+        if line.cfg.flags & LINE_SELF_CLEARING != 0 {
+            return Ok(ResetSequence::Pulse { long_hold });
+        }
+        if line.cfg.flags & LINE_EDGE_TRIGGERED != 0 {
+            return Ok(ResetSequence::Edge);
+        }
+        Ok(ResetSequence::Cycle { long_hold })
+    }
+}
+
+// A manager map entry together with its driver-level flags
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    id: usize,
+    map: &'a BstRstMap,
+    cfg: &'a LineConfig,
+    addr: usize,
+}
+
+// A register as its window and byte offset, "?" outside the mapped windows
+struct RegAt(Option<(usize, usize)>);
+
+impl fmt::Display for RegAt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some((window, offset)) => write!(f, "{}:{:#x}", window, offset),
+            None => f.write_str("?"),
+        }
+    }
+}
+
+// Lines print as their id followed by their name when they have one
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cfg.name {
+            Some(name) => write!(f, "{} ({})", self.id, name),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+// Manager and driver flags of a line by name, e.g. "ZERO_ASSERT_ONE_DEASSERT|SELF_CLEARING"
+struct LineFlags<'a>(Line<'a>);
+
+// Ops a line accepts, printable by name. Status is always accepted.
+struct LineOps {
+    assert: bool,
+    deassert: bool,
+    reset: bool,
+}
+
+impl fmt::Display for LineOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [(self.assert, "assert"), (self.deassert, "deassert"), (self.reset, "reset")];
+        for (_, name) in names.iter().filter(|(set, _)| *set) {
+            write!(f, "{}|", name)?;
+        }
+        write!(f, "status")
+    }
+}
+
+impl fmt::Display for LineFlags<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (map, cfg) = (self.0.map, self.0.cfg);
+        let names = [
+            (map.flags & ZERO_ASSERT_ONE_DEASSERT != 0, "ZERO_ASSERT_ONE_DEASSERT"),
+            (map.flags & RESET_LONG_HOLD_TIME != 0, "RESET_LONG_HOLD_TIME"),
+            (cfg.flags & LINE_SELF_CLEARING != 0, "SELF_CLEARING"),
+            (cfg.flags & LINE_ASSERT_ONLY != 0, "ASSERT_ONLY"),
+            (cfg.flags & LINE_DEASSERT_ONLY != 0, "DEASSERT_ONLY"),
+            (cfg.flags & LINE_INVERTED != 0, "INVERTED"),
+            (cfg.flags & LINE_EDGE_TRIGGERED != 0, "EDGE_TRIGGERED"),
+            (cfg.flags & LINE_PROTECTED != 0, "PROTECTED"),
+            (cfg.flags & LINE_STATUS_INVERTED != 0, "STATUS_INVERTED"),
+            (cfg.flags & LINE_COMPLETION_IRQ != 0, "COMPLETION_IRQ"),
+        ];
+        let mut sep = "";
+        for (_, name) in names.iter().filter(|(set, _)| *set) {
+            write!(f, "{}{}", sep, name)?;
+            sep = "|";
+        }
+        if sep.is_empty() {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Line<'a> {
+    // Flags of the line, printable by name
+    fn flags(self) -> LineFlags<'a> {
+        LineFlags(self)
+    }
+
+    // Control register address of the line
+    fn addr(&self) -> usize {
+        self.addr
+    }
+
+    // Bit mask of the line within its register
+    fn mask(&self) -> u32 {
+        1 << self.cfg.bit_idx
+    }
+
+    // Bit mask of the extra bits of a staged release
+    fn stages_mask(&self) -> u32 {
+        self.cfg.stages.iter().fold(0, |mask, stage| mask | 1 << stage.bit_idx)
+    }
+
+    // Effective polarity: the manager's flag, flipped for inverted lines
+    fn active_low(&self) -> bool {
+        (self.map.flags & ZERO_ASSERT_ONE_DEASSERT != 0) != (self.cfg.flags & LINE_INVERTED != 0)
+    }
+
+    // Register value with the line driven into (or out of) reset
+    fn value(&self, reg_val: u32, asserted: bool) -> u32 {
+        if asserted != self.active_low() {
+            reg_val | self.mask()
+        } else {
+            reg_val & !self.mask()
+        }
+    }
+
+    // Status reported to the reset core: 1 while held in reset, 0 otherwise
+    fn status(&self, reg_val: u32) -> u32 {
+        (self.asserted(reg_val) != self.status_inverted()) as u32
+    }
+
+    // Status read from a separate status register, with the line's polarity
+    fn status_of(&self, regs: &dyn RegAccess, reg: StatusReg) -> u32 {
+        let set = regs.read(reg.addr, reg.width) & (1 << reg.bit_idx) != 0;
+        (set != self.active_low() != self.status_inverted()) as u32
+    }
+
+    // Whether the status bit reads inverted, on top of the control polarity
+    fn status_inverted(&self) -> bool {
+        self.cfg.flags & LINE_STATUS_INVERTED != 0
+    }
+
+    // Status read from the hardware, from the status register when there is one
+    fn read_status(&self, regs: &dyn RegAccess) -> u32 {
+        self.status_from_bit(self.status_bit(regs))
+    }
+
+    // Status bit of the line as read, masked and shifted down to bit 0, from
+    // the status register when there is one
+    fn status_bit(&self, regs: &dyn RegAccess) -> u32 {
+        let (addr, width, bit_idx) = match self.cfg.status_reg {
+            Some(reg) => (reg.addr, reg.width, reg.bit_idx),
+            None => (self.addr(), self.width(), self.cfg.bit_idx),
+        };
+        (regs.read(addr, width) & (1 << bit_idx)) >> bit_idx
+    }
+
+    // Status reported to the reset core for an extracted status bit
+    fn status_from_bit(&self, bit: u32) -> u32 {
+        ((bit != 0) != self.active_low() != self.status_inverted()) as u32
+    }
+
+    // Access width of the control register
+    fn width(&self) -> Width {
+        self.cfg.ctrl_width
+    }
+
+    // Whether a control register value holds the line in reset, by the control
+    // polarity alone, for checking what was written
+    fn asserted(&self, reg_val: u32) -> bool {
+        (reg_val & self.mask() != 0) != self.active_low()
+    }
+}
+
+// Access to every register of the controller, so controllers behind an unusual
+// fabric, e.g. an SPI or I2C expander through a regmap, can supply their own.
+// Addresses are those of the map and of the DT register descriptions, a backend
+// that is not MMIO maps them back to its register offsets. The control and
+// status registers, ready, enable and level shifter bits, companion writes,
+// the reset source and the revision all go through it.
+trait RegAccess: Sync {
+    fn read(&self, addr: usize, width: Width) -> u32;
+    fn write(&self, val: u32, addr: usize, width: Width);
+
+    // Whether `addr` is a register of `bytes` bytes the backend serves outside
+    // the mapped windows, e.g. one it maps itself
+    fn contains(&self, _addr: usize, _bytes: usize) -> bool {
+        false
+    }
+}
+
+// Register access used in production, plain MMIO
+struct Mmio;
+
+impl RegAccess for Mmio {
+    fn read(&self, addr: usize, width: Width) -> u32 {
+        width.read(addr)
+    }
+
+    fn write(&self, val: u32, addr: usize, width: Width) {
+        width.write(val, addr);
+    }
+}
+
+// Source of the sleeps of composite operations, so their timing can be
+// observed without really sleeping
+trait DelayProvider: Sync {
+    fn sleep(&self, duration: Duration);
+}
+
+// Delay provider used in production
+struct CoarseSleep;
+
+impl DelayProvider for CoarseSleep {
+    fn sleep(&self, duration: Duration) {
+        // Holds are hardware minimums, so a sleep cut short is resumed for the
+        // rest of the duration rather than trusted
+        if duration.is_zero() {
+            return;
+        }
+        let deadline = Deadline::after(duration);
+        sleep_once(duration);
+        while !deadline.expired() {
+            sleep_once(deadline.remaining().max(Duration::from_micros(1)));
+        }
+    }
+}
+
+// A single sleep of about `duration`, which may return early
+fn sleep_once(duration: Duration) {
+    // `msleep` would stretch the sub-millisecond part of per-line hold
+    // times to a jiffy, sleep it with `usleep_range` instead
+    let micros = duration.subsec_micros() % 1000;
+    if duration.as_millis() > 0 {
+        coarse_sleep(duration - Duration::from_micros(micros.into()));
+    }
+    if micros != 0 {
+        // SAFETY: `usleep_range_state` has no preconditions beyond a sleepable context.
+        unsafe {
+            bindings::usleep_range_state(micros.into(), (2 * micros).into(), bindings::TASK_UNINTERRUPTIBLE)
+        };
+    }
+}
+
+// Delay provider that never schedules, for the `no-sleep` feature
+#[cfg(feature = "no-sleep")]
+struct BusyWait;
+
+#[cfg(feature = "no-sleep")]
+impl DelayProvider for BusyWait {
+    fn sleep(&self, duration: Duration) {
+        // `udelay` is only accurate for short delays, spin a millisecond at a time
+        for _ in 0..duration.as_millis() {
+            // SAFETY: `__udelay` has no preconditions.
+            unsafe { bindings::__udelay(1000) };
+        }
+        // SAFETY: `__udelay` has no preconditions.
+        unsafe { bindings::__udelay(duration.subsec_micros() % 1000) };
+    }
+}
+
+// Delay provider of the ops, busy-waiting when built with `no-sleep`
+#[cfg(not(feature = "no-sleep"))]
+const OP_DELAY: CoarseSleep = CoarseSleep;
+#[cfg(feature = "no-sleep")]
+const OP_DELAY: BusyWait = BusyWait;
+
+// Lock serializing composite resets, which must not sleep with `no-sleep` either
+#[cfg(not(feature = "no-sleep"))]
+type ResetLock = Mutex<()>;
+#[cfg(feature = "no-sleep")]
+type ResetLock = SpinLock<()>;
+
+// Lock of the status reads of mirrored lines against control writes. Without
+// `no-sleep` readers share it, with `no-sleep` they take turns.
+#[cfg(not(feature = "no-sleep"))]
+type StatusLock = kernel::sync::RwSemaphore<()>;
+#[cfg(feature = "no-sleep")]
+type StatusLock = SpinLock<()>;
+
+
+// A built-in reset map and the silicon revisions it describes
+struct BuiltinMap {
+    name: &'static str,
+    revisions: core::ops::RangeInclusive<u32>,
+    build: fn([Option<*mut u8>; MAX_RESET_REGISTERS]) -> BstResetManager,
+}
+
+// Built-in maps, the first one is used when no revision register is described
+const BUILTIN_MAPS: &[BuiltinMap] = &[BuiltinMap {
+    name: "a1000b",
+    revisions: 0..=u32::MAX,
+    build: build_a1000b,
+}];
+
+// The A1000B manager only takes the windows it describes
+fn build_a1000b(bst_address: [Option<*mut u8>; MAX_RESET_REGISTERS]) -> BstResetManager {
+    let mut windows = [None; A1000B_REGISTERS];
+    windows.copy_from_slice(&bst_address[..A1000B_REGISTERS]);
+    BstResetManager::new(windows)
+}
+
+// Pick the built-in map matching a silicon revision
+fn select_map(revision: Option<u32>) -> &'static BuiltinMap {
+    match revision {
+        Some(revision) => BUILTIN_MAPS
+            .iter()
+            .find(|map| map.revisions.contains(&revision))
+            .unwrap_or(&BUILTIN_MAPS[0]),
+        None => &BUILTIN_MAPS[0],
+    }
+}
+
+// Read the silicon revision from the register given by `bst,revision-reg` (reg index, byte offset)
+fn read_revision(
+    pdev: &platform::Device,
+    bst_address: &[Option<*mut u8>],
+    window_size: &[usize],
+    regs: &dyn RegAccess,
+) -> Result<Option<u32>> {
+    let cells = dt::read_u32_list(pdev, c_str!("bst,revision-reg"))?;
+    if cells.is_empty() {
+        return Ok(None);
+    }
+    if cells.len() != 2 {
+        fail!(pdev, EINVAL, "bst,revision-reg must be (reg, offset)\n");
+    }
+    let reg = RegBit::from_dt(bst_address, window_size, cells[0], cells[1], 0)?;
+    let revision = regs.read(reg.addr, Width::W32);
+    dev_info!(pdev, "Detected silicon revision {:#x}\n", revision);
+    Ok(Some(revision))
+}
+
+// Start address and size of a memory resource of the platform device
+fn resource_range(pdev: &platform::Device, index: u32) -> Option<(u64, u64)> {
+    // SAFETY: The `device` is embedded in a live `platform_device`.
+    let raw = unsafe { kernel::container_of!(pdev.raw_device(), bindings::platform_device, dev) };
+    // SAFETY: `raw` is a valid platform device.
+    let res = unsafe {
+        bindings::platform_get_resource(raw as *mut _, bindings::IORESOURCE_MEM, index)
+    };
+    if res.is_null() {
+        return None;
+    }
+    // SAFETY: `res` points at a resource owned by the device.
+    let (start, end) = unsafe { ((*res).start, (*res).end) };
+    Some((start, end - start + 1))
+}
+
+// Fail unless there are at least as many `reg` resources as present windows.
+// Entries past the present windows are not mapped.
+fn check_reg_resources(pdev: &platform::Device, nr_resources: u32, present: u32) -> Result {
+    if nr_resources < present.count_ones() {
+        fail!(
+            pdev,
+            EINVAL,
+            "Found {} reg resources, expected at least {} for the present windows {:#x}\n",
+            nr_resources,
+            present.count_ones(),
+            present
+        );
+    }
+    Ok(())
+}
+
+// With `bst,expected-defaults` (id, state) pairs, compare every line against the
+// state the board leaves it in before the driver touches any, deasserted unless
+// listed otherwise. A line found in the other state often means a hardware or
+// firmware problem, it is reported rather than fixed. Lines in windows not
+// mapped yet are skipped.
+fn check_defaults(pdev: &platform::Device, data: &BstMap) -> Result {
+    let cells = dt::read_u32_list(pdev, c_str!("bst,expected-defaults"))?;
+    if cells.is_empty() {
+        return Ok(());
+    }
+    if cells.len() % 2 != 0 {
+        fail!(pdev, EINVAL, "bst,expected-defaults must hold (id, state) pairs\n");
+    }
+    let mut expected = Vec::try_with_capacity(data.lines().len())?;
+    for _ in 0..data.lines().len() {
+        expected.try_push(false)?;
+    }
+    for pair in cells.chunks_exact(2) {
+        match expected.get_mut(pair[0] as usize) {
+            Some(state) if pair[1] <= 1 => *state = pair[1] == 1,
+            _ => fail!(pdev, EINVAL, "Invalid expected default {} for reset id {}\n", pair[1], pair[0]),
+        }
+    }
+    let (mut checked, mut unexpected) = (0, 0);
+    for (rst_id, _) in data.defined() {
+        let line = match data.line(rst_id) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        checked += 1;
+        let asserted = line.read_status(data.regs) != 0;
+        if asserted != expected[rst_id] {
+            let found = if asserted { "asserted" } else { "deasserted" };
+            dev_warn!(pdev, "Reset ID {} found {} at probe, against its expected default\n", line, found);
+            unexpected += 1;
+        }
+    }
+    if unexpected > 0 {
+        dev_warn!(pdev, "{} of {} lines differ from their expected default\n", unexpected, checked);
+        if data.strict {
+            return Err(error::code::EIO);
+        }
+    } else {
+        dev_info!(pdev, "All {} lines in their expected default state\n", checked);
+    }
+    Ok(())
+}
+
+// Hold the lines listed in `bst,assert-on-probe` in reset until a consumer releases them.
+// Done before registration so no consumer can observe the lines in between.
+fn assert_on_probe(pdev: &platform::Device, data: &BstMap) -> Result {
+    for id in dt::read_u32_list(pdev, c_str!("bst,assert-on-probe"))? {
+        let line = data.lookup(id as usize)?;
+        if line.cfg.flags & LINE_DEASSERT_ONLY != 0 {
+            dev_warn!(pdev, "Not asserting deassert-only reset {} at probe\n", line);
+            continue;
+        }
+        data.usable()?;
+        data.assert_line(line.id)?;
+        dev_info!(pdev, "Asserted reset {} at probe\n", line);
+    }
+    Ok(())
+}
+
+// Run a full `reset()` of the lines listed in `bst,init-reset`, for blocks that
+// only reach a defined state through a reset cycle. Lines whose flags forbid a
+// reset are skipped with a warning.
+fn init_reset(pdev: &platform::Device, data: &BstMap) -> Result {
+    for id in dt::read_u32_list(pdev, c_str!("bst,init-reset"))? {
+        let line = data.lookup(id as usize)?;
+        if line.cfg.flags & (LINE_ASSERT_ONLY | LINE_DEASSERT_ONLY | LINE_PROTECTED) != 0 {
+            dev_warn!(pdev, "Not resetting restricted reset {} [{}] at probe\n", line, line.flags());
+            continue;
+        }
+        data.op(OpKind::Reset, id.into(), BstMap::reset_and_wait_ready)?;
+        dev_info!(pdev, "Reset {} at probe\n", line);
+    }
+    Ok(())
 }
 
 // Type definitions for reset registrations and device data
 type ResetRegistrations = reset::ResetRegistration<BstResetDriver>;
 type ResetDeviceData = device::Data<ResetRegistrations, (), BstMap>;
 
+// Run `f` on the pinned reset registration of the device data.
+//
+// Invariants: the registration is stored inside the `Arc` allocation of the
+// device data, which never moves while any reference to it exists, and this is
+// the only place handing it out mutably, always as `Pin<&mut _>`. Taking the
+// `Arc` rather than the builder returned by `new_device_data!` makes it
+// impossible to register before the data reached its final location.
+fn with_registration<R>(
+    data: &Arc<ResetDeviceData>,
+    f: impl FnOnce(Pin<&mut ResetRegistrations>) -> Result<R>,
+) -> Result<R> {
+    let mut registrations = data.registrations().ok_or(ENXIO)?;
+    // SAFETY: The registration never moves, see the invariants above.
+    f(unsafe { Pin::new_unchecked(registrations.deref_mut()) })
+}
+
+// Driver data owned by the platform device while bound
+struct BstResetDevice {
+    // Declared first so the interrupt, the files and the scan go away before the
+    // data they point at, the interrupt is freed as the device goes away at remove
+    _irq: Option<irq::Irq>,
+    scan: Option<work::Periodic<ConsistencyScan>>,
+    assert_watchdog: Option<work::Periodic<AssertWatchdog>>,
+    _debugfs: debugfs::Dir,
+    _reset_trigger: Option<sysfs::File>,
+    _heartbeat: sysfs::File,
+    _capabilities: sysfs::File,
+    _gate: sysfs::File,
+    data: Arc<ResetDeviceData>,
+    // Register bus clock, disabled last when the device goes away
+    _clk: Option<EnabledClk>,
+}
+
+// Periodic check of the lines with `bst,max-assert-ms` against how long they have
+// been asserted. Once over its maximum a line is warned about or deasserted, as
+// configured, once per assertion.
+struct AssertWatchdog;
+
+impl work::Job for AssertWatchdog {
+    fn run(&mut self, data: &ResetDeviceData) {
+        let now = ktime_get_ns() as u64;
+        for (rst_id, state) in data.state.iter().enumerate() {
+            let max = match data.lines().get(rst_id).and_then(|cfg| cfg.max_assert) {
+                Some(max) => max,
+                None => continue,
+            };
+            let since = state.asserted_since_ns.load(Ordering::Relaxed);
+            if since == 0 || now.saturating_sub(since) < u64::from(max.ms) * 1_000_000 {
+                continue;
+            }
+            if state.over_max.swap(true, Ordering::Relaxed) {
+                continue;
+            }
+            if !max.deassert {
+                dev_warn!(data.dev, "Reset ID {} asserted for over {} ms\n", rst_id, max.ms);
+                continue;
+            }
+            dev_warn!(data.dev, "Reset ID {} asserted for over {} ms, deasserting it\n", rst_id, max.ms);
+            if data.op(OpKind::Deassert, rst_id as u64, BstMap::deassert_line).is_err() {
+                dev_err!(data.dev, "Could not deassert reset ID {}\n", rst_id);
+            }
+        }
+    }
+}
+
+// Periodic comparison of every line against its last status seen or driven, for
+// boards where firmware may change reset bits behind the driver's back. A change
+// is only reported once two scans in a row see it, so an op caught between its
+// register write and its status update is not mistaken for one.
+struct ConsistencyScan {
+    // Lines that differed on the previous scan
+    suspect: Vec<bool>,
+    // Whether a first scan recorded the starting status of every line
+    primed: bool,
+}
+
+impl work::Job for ConsistencyScan {
+    fn run(&mut self, data: &ResetDeviceData) {
+        // Never wake the controller just to scan it
+        if data.power_domain.as_ref().map_or(false, PowerDomain::suspended) {
+            return;
+        }
+        let _power = match data.power() {
+            Ok(power) => power,
+            Err(_) => return,
+        };
+        for (rst_id, state) in data.state.iter().enumerate() {
+            let line = match data.line(rst_id) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            // Hardware releases self-clearing lines on its own
+            if line.cfg.flags & LINE_SELF_CLEARING != 0 || state.resetting.load(Ordering::Acquire) {
+                self.suspect[rst_id] = false;
+                continue;
+            }
+            let status = line.read_status(data.regs) != 0;
+            if !self.primed {
+                data.cache_status(rst_id, status);
+                continue;
+            }
+            let differs = status != state.last_status.load(Ordering::Relaxed);
+            if differs && self.suspect[rst_id] {
+                let now = if status { "asserted" } else { "deasserted" };
+                dev_warn!(data.dev, "Reset ID {} changed behind the driver, now {}\n", line, now);
+                data.cache_status(rst_id, status);
+                state.shadow.store(SHADOW_NONE, Ordering::Relaxed);
+                self.suspect[rst_id] = false;
+            } else {
+                self.suspect[rst_id] = differs;
+            }
+        }
+        self.primed = true;
+    }
+}
+
 // Implement the platform driver for `BstResetDriver`
 impl platform::Driver for BstResetDriver {
     // Use the ID table for driver matching
     kernel::driver_of_id_table!(BST_RESET_OF_MATCH_TABLE);
-    type Data = Arc<ResetDeviceData>;
+    kernel::driver_acpi_id_table!(BST_RESET_ACPI_MATCH_TABLE);
+    type Data = Box<BstResetDevice>;
     
     // Probe function to initialize the driver
-    fn probe(pdev: &mut platform::Device, _id_info: Option<&Self::IdInfo>) -> Result<Self::Data> {
+    fn probe(pdev: &mut platform::Device, id_info: Option<&Self::IdInfo>) -> Result<Self::Data> {
         dev_info!(pdev, "{} driver in Rust (probe)\n", pdev.name());
+        // Devices matched otherwise, e.g. by name, behave as the A1000B
+        let quirks = match id_info {
+            Some(quirks) => quirks,
+            None => {
+                dev_info!(pdev, "No match data, assuming an A1000B\n");
+                &A1000B_QUIRKS
+            }
+        };
+        dev_info!(pdev, "Using {} variant quirks\n", quirks.name);
+
+        // The register bus may be clocked, bring it up before touching any register.
+        // A provider that is not ready yet makes `clk_get` return EPROBE_DEFER.
+        let acpi = dt::is_acpi(pdev);
+        if acpi {
+            dev_info!(pdev, "Reading properties from ACPI _DSD\n");
+        }
+
+        let clk = if dt::read_bool(pdev, c_str!("clocks")) {
+            Some(pdev.clk_get(None)?.prepare_enable()?)
+        } else {
+            None
+        };
+        let power_domain = PowerDomain::probe(pdev)?;
+        let op_log_capacity = (*op_log_size.read() as usize).min(MAX_OP_LOG_SIZE);
+
+        let mut a1000b_rst_addr: [Option<*mut u8>; MAX_RESET_REGISTERS] =
+            [None; MAX_RESET_REGISTERS];
+        let mut phys_base: [Option<u64>; MAX_RESET_REGISTERS] = [None; MAX_RESET_REGISTERS];
+        let mut window_size: [usize; MAX_RESET_REGISTERS] = [0; MAX_RESET_REGISTERS];
+
+        // Reduced board variants only wire some of the windows. Their `reg`
+        // entries list the present windows in order, absent ones are skipped.
+        let mut all_windows = (1u32 << MAX_RESET_REGISTERS) - 1;
+        // ACPI variants describe their hold time and window count in `_DSD`
+        let mut hold_time_ms = quirks.hold_time_ms.unwrap_or(RST_HOLD_TIME);
+        if acpi {
+            let config = dt::acpi_config(pdev, |name| dt::read_u32(pdev, name))?;
+            if let Some(ms) = config.hold_time_ms {
+                hold_time_ms = ms;
+            }
+            if let Some(windows) = config.windows {
+                all_windows = windows;
+            }
+        }
+        // Single-region variants map one `reg` entry as window 0 and place each
+        // line at an offset into it instead of using the map's windows
+        let line_offsets = dt::read_u32_list(pdev, c_str!("bst,line-offsets"))?;
+        if !line_offsets.is_empty() {
+            all_windows = 1;
+        }
+        let present = dt::read_u32(pdev, c_str!("bst,present-windows")).unwrap_or(all_windows);
+        if present & !all_windows != 0 || present == 0 {
+            fail!(pdev, EINVAL, "Invalid bst,present-windows mask {:#x}\n", present);
+        }
+
+        // Every present window needs a `reg` entry. Counted before mapping
+        // anything, so a short `reg` fails here rather than part way through the
+        // mapping loop.
+        let mut nr_resources: u32 = 0;
+        while resource_range(pdev, nr_resources).is_some() {
+            nr_resources += 1;
+        }
+        check_reg_resources(pdev, nr_resources, present)?;
+
+        // Windows mapped on first use rather than at probe, they cannot hold the
+        // auxiliary registers read at probe such as ready or revision bits
+        let lazy_windows = dt::read_u32(pdev, c_str!("bst,lazy-windows")).unwrap_or(0);
+        if lazy_windows & !present != 0 {
+            fail!(pdev, EINVAL, "bst,lazy-windows {:#x} names absent windows\n", lazy_windows);
+        }
+        // Mapping a window on first use takes a mutex and may sleep, within the
+        // spin lock a no-sleep build runs its ops under
+        if lazy_windows != 0 && cfg!(feature = "no-sleep") {
+            fail!(pdev, EINVAL, "bst,lazy-windows cannot be used with the no-sleep build\n");
+        }
+        // Status-only windows, ops that would write them are refused
+        let read_only_windows = dt::read_u32(pdev, c_str!("bst,read-only-windows")).unwrap_or(0);
+        if read_only_windows & !present != 0 {
+            fail!(pdev, EINVAL, "bst,read-only-windows {:#x} names absent windows\n", read_only_windows);
+        }
+        // Window bases as handed to the manager, with stand-ins for lazy windows
+        let mut manager_bases = [None; MAX_RESET_REGISTERS];
 
-        const TOTAL_REGISTERS: usize = 5;
-        let mut a1000b_rst_addr: [Option<*mut u8>; TOTAL_REGISTERS] = [None; TOTAL_REGISTERS];
-        
         // Map register resources
-        for i in (RstResId::TOP_CRM_BLOCK_SW_RST0 as usize)..=(RstResId::LSP1_RST_CTRL_REG as usize) {
-            let i_u32: u32 = i.try_into().unwrap();
-            let reg_base:*mut u8 = pdev.ioremap_resource(i_u32)?;
+        let res_order = dt::resource_order(pdev, present)?;
+        for i in 0..MAX_RESET_REGISTERS {
+            let res_index = match res_order[i] {
+                Some(res_index) => res_index,
+                None => {
+                    dev_info!(pdev, "Register window {} not present\n", i);
+                    continue;
+                }
+            };
+            if lazy_windows & (1 << i) != 0 {
+                let (start, size) = resource_range(pdev, res_index).ok_or(error::code::EINVAL)?;
+                phys_base[i] = Some(start);
+                window_size[i] = size as usize;
+                manager_bases[i] = Some(lazy_token(i) as *mut u8);
+                continue;
+            }
+            let reg_base:*mut u8 = pdev.ioremap_resource(res_index)?;
             a1000b_rst_addr[i] = Some(reg_base);
+            if let Some((start, size)) = resource_range(pdev, res_index) {
+                phys_base[i] = Some(start);
+                window_size[i] = size as usize;
+            }
             if a1000b_rst_addr[i].is_none() {
-                pr_err!("Could not remap register memory for register {}\n", i);
-                return Err(error::code::ENOMEM);
+                fail!(pdev, ENOMEM, "Could not remap register memory for register {}\n", i);
+            }
+            manager_bases[i] = a1000b_rst_addr[i];
+        }
+        // Registers read until the controller data exists need the domain up too
+        let probe_power = power_domain.as_ref().map(PowerDomain::get).transpose()?;
+        let revision = read_revision(pdev, &a1000b_rst_addr, &window_size, quirks.regs)?;
+        let builtin = select_map(revision);
+        dev_info!(pdev, "Using {} reset map\n", builtin.name);
+        let manager = (builtin.build)(manager_bases);
+        let nr_lines = manager.bsta1000b_map.len();
+        // Run twice on boards with an alternate map, which starts out the same
+        let place_lines = || -> Result<Vec<LineConfig>> {
+            let mut lines = dt::parse_lines(pdev, quirks, nr_lines, &a1000b_rst_addr, &window_size)?;
+            place_lines(&manager, &manager_bases, &window_size, &mut lines)?;
+            if let Some(base) = manager_bases[0].filter(|_| !line_offsets.is_empty()) {
+                let (base, size) = (base as usize, window_size[0]);
+                dt::apply_line_offsets(pdev, &manager, &mut lines, &line_offsets, base, size)?;
             }
+            dt::check_layout(pdev, &manager, &lines)?;
+            Ok(lines)
+        };
+        let lines = place_lines()?;
+        // A map without a single drivable line means a misconfigured manager or
+        // board, never register such a dead controller. Direct ids need no map.
+        let drivable = defined_lines(&manager)
+            .filter(|(rst_id, _)| lines[*rst_id].window.is_some())
+            .count();
+        if drivable == 0 && dt::read_u32(pdev, c_str!("#reset-cells")) != Some(2) {
+            fail!(pdev, ENODEV, "{} reset map has no line in a present window\n", builtin.name);
+        }
+        dt::check_overlaps(pdev, &manager, &lines)?;
+        let alternate_cells = dt::read_u32_list(pdev, c_str!("bst,alternate-map"))?;
+        let alternate = if alternate_cells.is_empty() {
+            Vec::new()
+        } else {
+            let mut alternate = place_lines()?;
+            let (bases, sizes) = (&a1000b_rst_addr, &window_size);
+            dt::apply_alternate_map(pdev, &manager, &mut alternate, &alternate_cells, bases, sizes)?;
+            dt::check_layout(pdev, &manager, &alternate)?;
+            dt::check_overlaps(pdev, &manager, &alternate)?;
+            alternate
+        };
+        let write_retries = dt::read_u32(pdev, c_str!("bst,write-retries")).unwrap_or(0);
+        let ready_timeout_ms = dt::read_u32(pdev, c_str!("bst,ready-timeout-ms")).unwrap_or(100);
+        let reset_timeout_ms = dt::read_u32(pdev, c_str!("bst,reset-timeout-ms")).unwrap_or(1000);
+        let mut state = Vec::try_with_capacity(nr_lines)?;
+        for _ in 0..nr_lines {
+            state.try_push(LineState::default())?;
         }
-        let reg_data = BstMap {bst_address:a1000b_rst_addr};
+        let aliases = dt::parse_aliases(pdev, nr_lines)?;
+        let safe_state = dt::parse_safe_state(pdev, nr_lines)?;
+        let set_clr = dt::parse_set_clr(pdev, &a1000b_rst_addr)?;
+        let level_shifter = LevelShifter::probe(pdev, &a1000b_rst_addr, &window_size, quirks.regs)?;
+        let reset_source = dt::parse_reset_source(pdev, &a1000b_rst_addr, &window_size)?;
+        let self_reset = dt::parse_self_reset(pdev, &a1000b_rst_addr, &window_size)?;
+        drop(probe_power);
+        let reg_data = BstMap {
+            dev: device::Device::from_dev(pdev),
+            reg_lock: uninit_lock!(SpinLock, ()),
+            status_lock: uninit_lock!(StatusLock, ()),
+            reset_lock: uninit_lock!(ResetLock, ()),
+            #[cfg(debug_assertions)]
+            reg_lock_owner: AtomicI32::new(-1),
+            #[cfg(debug_assertions)]
+            reg_lock_violations: AtomicU32::new(0),
+            #[cfg(debug_assertions)]
+            status_warned: AtomicBool::new(false),
+            bst_address: a1000b_rst_addr,
+            phys_base,
+            window_size,
+            set_clr,
+            lazy_windows,
+            read_only_windows,
+            lazy_base: Default::default(),
+            lazy_lock: uninit_lock!(Mutex, ()),
+            manager,
+            maps: [lines, alternate],
+            active_map: AtomicUsize::new(0),
+            write_retries,
+            ready_timeout_ms,
+            reset_timeout_ms,
+            op_timeout_ms: dt::read_u32(pdev, c_str!("bst,op-timeout-ms")).unwrap_or(0),
+            storm: StormLimit::from_dt(pdev),
+            clock_settle_ms: dt::read_u32(pdev, c_str!("bst,clock-settle-ms")).unwrap_or(0),
+            hold_time_ms,
+            flush_posted_writes: quirks.flush_posted_writes,
+            regs: quirks.regs,
+            delay: &OP_DELAY,
+            shadow_check: dt::read_bool(pdev, c_str!("bst,shadow-check")),
+            status_mirror_strict: dt::read_bool(pdev, c_str!("bst,status-mirror-strict")),
+            strict: dt::strict_mode(pdev),
+            release_leaked_holds: dt::read_bool(pdev, c_str!("bst,release-leaked-holds")),
+            state,
+            aliases,
+            safe_state,
+            level_shifter,
+            power_domain,
+            direct_cells: dt::read_u32(pdev, c_str!("#reset-cells")) == Some(2),
+            cache_status_suspended: dt::read_bool(pdev, c_str!("bst,cache-status-while-suspended")),
+            holds: uninit_lock!(Mutex, Vec::new()),
+            acquired: uninit_lock!(Mutex, Vec::new()),
+            pins: uninit_lock!(Mutex, Vec::new()),
+            op_log: uninit_lock!(SpinLock, OpLog::new(op_log_capacity)?),
+            post_reset: uninit_lock!(Mutex, Vec::new()),
+            phase_hooks: uninit_lock!(SpinLock, [None; Phase::COUNT]),
+            heartbeat: AtomicU64::new(0),
+            #[cfg(debug_assertions)]
+            last_trace: OpTrace::default(),
+            #[cfg(feature = "replay")]
+            replay: uninit_lock!(Mutex, Vec::new()),
+            async_work: work::Work::new(),
+            completion: irq::Completion::new(),
+            irq_waiting: AtomicUsize::new(0),
+            gated: AtomicBool::new(false),
+            transaction: AtomicU32::new(0),
+            ready: AtomicBool::new(false),
+            reset_source,
+            self_reset,
+        };
 
         // Register Reset                  
-        let resetdata = kernel::new_device_data!(
+        let mut resetdata = kernel::new_device_data!(
             ResetRegistration::<BstResetDriver>::new(),
             (),
             reg_data,
             "reset Registrations"
         )?;
+
+        // SAFETY: General part of the data is pinned when `resetdata` is.
+        unsafe { resetdata.as_mut().map_unchecked_mut(|d| &mut **d) }.init_pinned();
         
         let arc_resetdata:Arc<ResetDeviceData> = Arc::<ResetDeviceData>::from(resetdata);
+
+        // Requested before any reset, ops waiting for a completion need it
+        let any_irq_line = arc_resetdata.lines().iter().any(|cfg| cfg.flags & LINE_COMPLETION_IRQ != 0);
+        let irq = if any_irq_line {
+            if cfg!(feature = "no-sleep") {
+                fail!(pdev, EINVAL, "Completion interrupts need to sleep, not in a no-sleep build\n");
+            }
+            if !dt::read_bool(pdev, c_str!("interrupts")) {
+                fail!(pdev, EINVAL, "bst,completion-irq-resets needs an interrupt\n");
+            }
+            Some(irq::Irq::request(pdev, pdev.name(), &arc_resetdata)?)
+        } else {
+            None
+        };
         
-        kernel::reset_controller_register!(
-            unsafe {Pin::new_unchecked(arc_resetdata.registrations().ok_or(ENXIO)?.deref_mut()) },
-            pdev,
-            50,
-            arc_resetdata.clone(),
-        )?;
-        Ok(arc_resetdata)
+        {
+            let _power = arc_resetdata.power()?;
+            check_defaults(pdev, &arc_resetdata)?;
+            assert_on_probe(pdev, &arc_resetdata)?;
+        }
+
+        // The framework admits every id below `nr_resets`, never let it exceed the map
+        let mut nr_resets = NR_RESETS;
+        if nr_resets > nr_lines {
+            if arc_resetdata.strict {
+                fail!(pdev, EINVAL, "nr_resets {} exceeds the {} map entries\n", nr_resets, nr_lines);
+            }
+            dev_warn!(pdev, "nr_resets {} exceeds the {} map entries, clamping\n", nr_resets, nr_lines);
+            nr_resets = nr_lines;
+        }
+        with_registration(&arc_resetdata, |registration| {
+            kernel::reset_controller_register!(registration, pdev, nr_resets as u32, arc_resetdata.clone())
+        })?;
+        arc_resetdata.ready.store(true, Ordering::Release);
+        init_reset(pdev, &arc_resetdata)?;
+
+        #[cfg(feature = "selftest")]
+        selftest::run(pdev, &arc_resetdata)?;
+
+        let debugfs = debugfs::Dir::new(pdev.name());
+        debugfs.create_file::<debugfs::RetriesFile>(c_str!("retries"), 0o644, &arc_resetdata);
+        debugfs.create_file::<debugfs::RegistersFile>(c_str!("registers"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::HoldersFile>(c_str!("holders"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::LinesFile>(c_str!("lines"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::StatusJsonFile>(c_str!("status.jsonl"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::MapCsvFile>(c_str!("map.csv"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::SnapshotFile>(c_str!("snapshot"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::OpLogFile>(c_str!("op_log"), 0o444, &arc_resetdata);
+        debugfs.create_file::<debugfs::LastResetFile>(c_str!("last_reset"), 0o444, &arc_resetdata);
+        #[cfg(feature = "replay")]
+        debugfs.create_file::<debugfs::ReplayFile>(c_str!("replay"), 0o600, &arc_resetdata);
+        #[cfg(feature = "mmio-trace")]
+        debugfs.create_file::<debugfs::MmioTraceFile>(c_str!("mmio_trace"), 0o444, &arc_resetdata);
+        #[cfg(feature = "error-injection")]
+        debugfs.create_file::<debugfs::InjectErrorFile>(c_str!("inject_error"), 0o600, &arc_resetdata);
+        if arc_resetdata.self_reset.is_some() {
+            debugfs.create_file::<debugfs::RecoverFile>(c_str!("recover"), 0o200, &arc_resetdata);
+        }
+        if arc_resetdata.reset_source.is_some() {
+            debugfs.create_file::<debugfs::ResetSourceFile>(c_str!("reset_source"), 0o600, &arc_resetdata);
+        }
+        #[cfg(debug_assertions)]
+        debugfs.create_file::<debugfs::TraceOpFile>(c_str!("trace_op"), 0o600, &arc_resetdata);
+
+        let reset_trigger = if dt::read_bool(pdev, c_str!("bst,reset-trigger")) {
+            Some(sysfs::File::new::<sysfs::ResetTrigger>(pdev, c_str!("reset"), 0o200, &arc_resetdata)?)
+        } else {
+            None
+        };
+
+        let heartbeat =
+            sysfs::File::new::<sysfs::Heartbeat>(pdev, c_str!("heartbeat"), 0o444, &arc_resetdata)?;
+
+        let scan = match dt::read_u32(pdev, c_str!("bst,scan-interval-ms")) {
+            Some(interval_ms) if interval_ms > 0 => {
+                let mut suspect = Vec::try_with_capacity(arc_resetdata.state.len())?;
+                for _ in 0..arc_resetdata.state.len() {
+                    suspect.try_push(false)?;
+                }
+                let job = ConsistencyScan { suspect, primed: false };
+                Some(work::Periodic::start(job, interval_ms, &arc_resetdata)?)
+            }
+            _ => None,
+        };
+        // Checked at a quarter of the shortest maximum, so no line overstays by much
+        let maxima = arc_resetdata.lines().iter().filter_map(|cfg| cfg.max_assert);
+        let shortest = maxima.map(|max| max.ms).min();
+        let assert_watchdog = match shortest {
+            Some(ms) => Some(work::Periodic::start(AssertWatchdog, (ms / 4).max(1), &arc_resetdata)?),
+            None => None,
+        };
+        let capabilities =
+            sysfs::File::new::<sysfs::Capabilities>(pdev, c_str!("capabilities"), 0o444, &arc_resetdata)?;
+        let gate = sysfs::File::new::<sysfs::Gate>(pdev, c_str!("gate"), 0o644, &arc_resetdata)?;
+
+        Ok(Box::try_new(BstResetDevice {
+            _irq: irq,
+            scan,
+            assert_watchdog,
+            _debugfs: debugfs,
+            _reset_trigger: reset_trigger,
+            _heartbeat: heartbeat,
+            _capabilities: capabilities,
+            _gate: gate,
+            data: arc_resetdata,
+            _clk: clk,
+        })?)
+    }
+
+    // Remove function, runs before the driver data is dropped
+    fn remove(data: &Self::Data) {
+        if let Some(scan) = &data.scan {
+            scan.stop();
+        }
+        if let Some(watchdog) = &data.assert_watchdog {
+            watchdog.stop();
+        }
+        data.data.stop_async_resets();
+        data.data.report_leaked_holds();
+        // Before the level shifter goes down, the lines would not follow anymore
+        data.data.apply_safe_state();
+        // Disable the level shifter when the controller goes away
+        if data.data.level_shifter.enabled {
+            data.data.set_level_shifter(false);
+        }
     }
 }
 
@@ -114,106 +4086,116 @@ impl Drop for BstResetDriver {
     }
 }
 
-// Implement the reset operations for the driver
+// Implement the reset operations for the driver.
+//
+// Every failure mode maps to its own errno:
+// - EAGAIN: the controller is not registered yet, or a prerequisite of a line
+//   being deasserted is still asserted
+// - EINVAL: the id does not name a line in the map, or in strict mode a hold
+//   API deassert is unbalanced
+// - ENODEV: the line cannot be driven on this board (absent window, level shifter down)
+// - EPERM: the operation is not allowed on a restricted or protected line,
+//   would write a read-only window, or reset ops are gated
+// - EDEADLK: an op was re-entered on the same line by the task already running one
+// - EBUSY: status was queried while a `reset()` of the line is in progress,
+//   the line is in its reset storm cooldown, held by other consumers or
+//   pinned by another consumer
+// - EIO: a control write did not land, even after the configured retries, the
+//   power domain could not be powered, or a status disagreed with its strict mirror
+// - EFAULT: a register of the line lies outside its window or the mapped windows
+// - ETIMEDOUT: a self-clearing line, ready bit, the `reset()` budget or the op timeout timed out
+//
+// Array handles from `reset_control_array_get` reach these ops one line at a
+// time, in the core's order, with no lock of the core held. Each op holds
+// `reg_lock` only around its own register update and `reset_lock` only within
+// a `reset()`, both released before it returns, so a bulk call over several
+// lines of one register never waits on itself. A line listed twice, or a line
+// and its alias, is two ops in a row, not re-entrancy.
 #[vtable]
 impl reset::ResetDriverOps for BstResetDriver {
     type Data = Arc<ResetDeviceData>;
 
     // Assert the reset signal
     fn assert(data: ArcBorrow<'_, ResetDeviceData>, rst_id: u64) -> Result<i32> {
-        let bstops_address = data.bst_address;
-        let rst_id_usize = rst_id as usize;
-        let manager = BstResetManager::new(bstops_address);
-        
-        if let Some(bst_rst_map) = &manager.bsta1000b_map[rst_id_usize] {
-            let reg_val = readl(bst_rst_map.addr as usize);
-            let new_val = if bst_rst_map.flags & ZERO_ASSERT_ONE_DEASSERT != 0 {
-                reg_val & !(1 << bst_rst_map.bit_idx)
-            } else {
-                reg_val | (1 << bst_rst_map.bit_idx)
-            };
-            writel(new_val, bst_rst_map.addr as usize);
-            return Ok(0);
-        } else {
-            pr_err!("Invalid reset ID: {}\n", rst_id_usize);
-            return Err(error::code::EINVAL);
-        }
+        data.op(OpKind::Assert, rst_id, BstMap::assert_line)?;
+        Ok(0)
     }
 
     // Deassert the reset signal
     fn deassert(data: ArcBorrow<'_, ResetDeviceData>, rst_id: u64) -> Result<i32> {
-        let bstops_address = data.bst_address;
-        let rst_id_usize = rst_id as usize;
-        let manager = BstResetManager::new(bstops_address);
-        
-        if let Some(bst_rst_map) = &manager.bsta1000b_map[rst_id_usize] {
-            let reg_val = readl(bst_rst_map.addr as usize);
-            let new_val = if bst_rst_map.flags & ZERO_ASSERT_ONE_DEASSERT != 0 {
-                reg_val | (1 << bst_rst_map.bit_idx)
-            } else {
-                reg_val & !(1 << bst_rst_map.bit_idx)
-            };
-            writel(new_val, bst_rst_map.addr as usize);
-            return Ok(0);
-        } else {
-            pr_err!("Invalid reset ID: {}\n", rst_id_usize);
-            return Err(error::code::EINVAL);
-        }
+        data.op(OpKind::Deassert, rst_id, BstMap::deassert_line)?;
+        Ok(0)
     }
 
     // Check the reset status
     fn status(data: ArcBorrow<'_, ResetDeviceData>, rst_id: u64) -> Result<i32> {
-        let bstops_address = data.bst_address;
-        let rst_id_usize = rst_id as usize;
-        let manager = BstResetManager::new(bstops_address);
-        
-        if let Some(bst_rst_map) = &manager.bsta1000b_map[rst_id_usize] {
-            let reg_val = readl(bst_rst_map.addr as usize);
-            let status = if bst_rst_map.flags & ZERO_ASSERT_ONE_DEASSERT != 0 {
-                !(reg_val & (1 << bst_rst_map.bit_idx)) as i32
-            } else {
-                !(!(reg_val & (1 << bst_rst_map.bit_idx))) as i32
-            };
-            return Ok(status);
-        } else {
-            pr_err!("Invalid reset ID: {}\n", rst_id_usize);
-            return Err(error::code::EINVAL);
+        if let Some(status) = data.cached_status(rst_id) {
+            return Ok(status as i32);
         }
+        Ok(data.op(OpKind::Status, rst_id, BstMap::reported_status)? as i32)
     }
 
     // Perform a reset operation
     fn reset(data: ArcBorrow<'_, ResetDeviceData>, rst_id: u64) -> Result<i32> {
-        let bstops_address = data.bst_address;
-        let rst_id_usize = rst_id as usize;
-        let manager = BstResetManager::new(bstops_address);
-        
-        if let Some(bst_rst_map) = &manager.bsta1000b_map[rst_id_usize] {
-            BstResetDriver::assert(data, rst_id)?;
-            coarse_sleep(Duration::from_millis(RST_HOLD_TIME));
-            if bst_rst_map.flags & RESET_LONG_HOLD_TIME != 0 {
-                coarse_sleep(Duration::from_millis(RST_HOLD_TIME));
-            }
-            BstResetDriver::deassert(data, rst_id)?;
-            coarse_sleep(Duration::from_millis(RST_HOLD_TIME));
-            if bst_rst_map.flags & RESET_LONG_HOLD_TIME != 0 {
-                coarse_sleep(Duration::from_millis(RST_HOLD_TIME));
-            }
-            return Ok(0);
-        } else {
-            pr_err!("Invalid reset ID: {}\n", rst_id_usize);
-            return Err(error::code::EINVAL);
-        }
+        data.op(OpKind::Reset, rst_id, BstMap::reset_and_wait_ready)?;
+        Ok(0)
+    }
+
+    // Translate a reset specifier, the single-cell form is a map id
+    fn of_xlate(data: ArcBorrow<'_, ResetDeviceData>, args: &[u32]) -> Result<u64> {
+        data.xlate(args)
     }
 }
 
+// Clear bits of a write-1-to-clear register. Only `mask` is written, never a
+// read-modify-write, which would write back every other latched bit as a 1
+// and clear it too.
+fn w1c_clear(regs: &dyn RegAccess, addr: usize, mask: u32) {
+    regs.write(mask, addr, Width::W32);
+}
+
+// Read an 8-bit value from a memory-mapped register
+fn readb(addr: usize) -> u8 {
+    let val = unsafe { bindings::readb(addr as _) };
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Read, addr, val.into(), 8);
+    val
+}
+
+// Read a 16-bit value from a memory-mapped register
+fn readw(addr: usize) -> u16 {
+    let val = unsafe { bindings::readw(addr as _) };
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Read, addr, val.into(), 16);
+    val
+}
+
+// Write an 8-bit value to a memory-mapped register
+fn writeb(val: u8, addr: usize) {
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Write, addr, val.into(), 8);
+    unsafe { bindings::writeb(val, addr as _) }
+}
+
+// Write a 16-bit value to a memory-mapped register
+fn writew(val: u16, addr: usize) {
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Write, addr, val.into(), 16);
+    unsafe { bindings::writew(val, addr as _) }
+}
+
 // Function to read a 32-bit value from a memory-mapped register
 fn readl(addr: usize) -> u32 {
     let val = unsafe { bindings::readl(addr as _) };
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Read, addr, val, 32);
     val
 }
     
 // Function to write a 32-bit value to a memory-mapped register
 fn writel(val: u32, addr: usize) {
+    #[cfg(feature = "mmio-trace")]
+    mmio_trace::record(mmio_trace::Dir::Write, addr, val, 32);
     unsafe { bindings::writel(val, addr as _) }
 }
     
\ No newline at end of file
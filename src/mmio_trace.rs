@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Ring of the last MMIO accesses, built with the `mmio-trace` feature
+//!
+//! Every `read*`/`write*` of a register is recorded with its address, value,
+//! direction and width, for correlating the driver's accesses with a hardware
+//! trace. The ring is shared by all controller instances, each one's debugfs
+//! `mmio_trace` shows the entries within its own windows. Recording takes no
+//! lock: a writer claims a slot and publishes it with its sequence number, a
+//! reader skips slots rewritten while it read them.
+
+use kernel::prelude::*;
+
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+// Accesses kept, the oldest are overwritten
+const RING_SIZE: usize = 256;
+
+// Direction of an access
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Dir {
+    Read,
+    Write,
+}
+
+// A recorded access, as read back from the ring
+pub(crate) struct Access {
+    pub(crate) seq: u64,
+    pub(crate) addr: usize,
+    pub(crate) val: u32,
+    pub(crate) dir: Dir,
+    pub(crate) bits: u8,
+}
+
+struct Slot {
+    // Sequence number of the access plus one, zero while unused or being written
+    seq: AtomicU64,
+    addr: AtomicUsize,
+    val: AtomicU32,
+    // Width in bits, with the top bit set for writes
+    kind: AtomicU8,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot {
+    seq: AtomicU64::new(0),
+    addr: AtomicUsize::new(0),
+    val: AtomicU32::new(0),
+    kind: AtomicU8::new(0),
+};
+
+static RING: [Slot; RING_SIZE] = [EMPTY; RING_SIZE];
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+const WRITE: u8 = 0x80;
+
+// Record an access of `bits` width
+pub(crate) fn record(dir: Dir, addr: usize, val: u32, bits: u8) {
+    let seq = NEXT.fetch_add(1, Ordering::Relaxed);
+    let slot = &RING[seq as usize % RING_SIZE];
+    slot.seq.store(0, Ordering::Relaxed);
+    // Orders the claim before the fields, so a reader seeing any new field
+    // sees the slot claimed on its re-check
+    fence(Ordering::Release);
+    slot.addr.store(addr, Ordering::Relaxed);
+    slot.val.store(val, Ordering::Relaxed);
+    slot.kind.store(if dir == Dir::Write { bits | WRITE } else { bits }, Ordering::Relaxed);
+    slot.seq.store(seq + 1, Ordering::Release);
+}
+
+// Call `f` with every access still in the ring, oldest first
+pub(crate) fn for_each(mut f: impl FnMut(&Access) -> Result) -> Result {
+    let next = NEXT.load(Ordering::Acquire);
+    for seq in next.saturating_sub(RING_SIZE as u64)..next {
+        let slot = &RING[seq as usize % RING_SIZE];
+        if slot.seq.load(Ordering::Acquire) != seq + 1 {
+            continue;
+        }
+        let kind = slot.kind.load(Ordering::Relaxed);
+        let access = Access {
+            seq,
+            addr: slot.addr.load(Ordering::Relaxed),
+            val: slot.val.load(Ordering::Relaxed),
+            dir: if kind & WRITE != 0 { Dir::Write } else { Dir::Read },
+            bits: kind & !WRITE,
+        };
+        // Pairs with the fence of `record`: orders the field reads before the
+        // re-check. Overwritten while it was read, the fields may mix two accesses.
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) != seq + 1 {
+            continue;
+        }
+        f(&access)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: GPL-2.0
+//! End-to-end selftest of the reset controller, built with the `selftest` feature
+//!
+//! A `selftest` child node of the controller acts as a consumer:
+//!
+//! ```text
+//! rstc: reset-controller@... {
+//!     ...
+//!     selftest {
+//!         resets = <&rstc 12>;
+//!     };
+//! };
+//! ```
+//!
+//! After registration the line is acquired through the reset framework like
+//! any consumer would, asserted, deasserted and reset, and the register state
+//! is checked after each step.
+//!
+//! Before that, and without the child node too, the polarity and mask math is
+//! checked against random register values and flag combinations, for the bit
+//! of every line in the map, then the hermetic cases in `cases` run the driver
+//! logic on fake controllers.
+
+use kernel::{
+    bindings,
+    c_str,
+    error,
+    platform,
+    prelude::*,
+};
+
+use core::ffi::c_int;
+
+use crate::{dt, Line, LineConfig, ResetDeviceData, LINE_INVERTED, LINE_STATUS_INVERTED};
+
+mod cases;
+mod fake;
+
+// Register values tried per line and flag combination
+const BIT_MATH_ROUNDS: usize = 64;
+
+// xorshift32, enough to spread register values without an RNG dependency
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+// Check the invariants of the bit math of a line on one register value
+fn check_bit_math(line: Line<'_>, reg_val: u32) -> Result {
+    let mask = line.mask();
+    let asserted = line.value(reg_val, true);
+    let deasserted = line.value(reg_val, false);
+    let invariants = [
+        ("assert drives the line into reset", line.asserted(asserted)),
+        ("deassert releases the line", !line.asserted(deasserted)),
+        ("other bits are untouched", ((asserted ^ reg_val) | (deasserted ^ reg_val)) & !mask == 0),
+        ("deassert after assert restores a released bit", line.value(asserted, false) == deasserted),
+        ("assert is idempotent", line.value(asserted, true) == asserted),
+        ("status after assert", (line.status(asserted) != 0) != line.status_inverted()),
+        ("status after deassert", (line.status(deasserted) == 0) != line.status_inverted()),
+    ];
+    for (name, holds) in invariants {
+        if !holds {
+            pr_err!("selftest: reset ID {}, value {:#010x}: {} fails\n", line.id, reg_val, name);
+            return Err(error::code::EINVAL);
+        }
+    }
+    Ok(())
+}
+
+// Check the bit math of every line in the map under every driver polarity flag
+fn bit_math(data: &ResetDeviceData) -> Result {
+    // SAFETY: `ktime_get` has no preconditions.
+    let mut rng = Rng(unsafe { bindings::ktime_get() } as u32 | 1);
+    for (rst_id, map) in data.defined() {
+        for flags in [0, LINE_INVERTED, LINE_STATUS_INVERTED, LINE_INVERTED | LINE_STATUS_INVERTED] {
+            let cfg = LineConfig { flags, bit_idx: map.bit_idx, ..LineConfig::default() };
+            // Only the bit math is exercised, no register is accessed
+            let line = Line { id: rst_id, map, cfg: &cfg, addr: 0 };
+            check_bit_math(line, 0)?;
+            check_bit_math(line, u32::MAX)?;
+            for _ in 0..BIT_MATH_ROUNDS {
+                check_bit_math(line, rng.next())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A reset control acquired through the framework, released on drop
+struct Control(*mut bindings::reset_control);
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        // SAFETY: `self.0` was returned by `__of_reset_control_get`.
+        unsafe { bindings::reset_control_put(self.0) };
+    }
+}
+
+// Turn the return value of a framework call into a `Result`
+fn step(ret: c_int) -> Result {
+    if ret < 0 {
+        return Err(error::Error::from_kernel_errno(ret));
+    }
+    Ok(())
+}
+
+// Check the line status the framework and the registers report after a step
+fn check(data: &ResetDeviceData, rst_id: usize, what: &str, asserted: bool) -> Result {
+    let status = data.line_status(rst_id)?;
+    if status != asserted {
+        pr_err!("selftest: reset ID {} after {}: status {}, expected {}\n", rst_id, what, status, asserted);
+        return Err(error::code::EIO);
+    }
+    Ok(())
+}
+
+// Run the selftest if the controller node has a `selftest` consumer child
+pub(crate) fn run(pdev: &platform::Device, data: &ResetDeviceData) -> Result {
+    bit_math(data).map_err(|e| {
+        dev_err!(pdev, "selftest: bit math failed\n");
+        e
+    })?;
+    cases::run(pdev)?;
+    let np = dt::child(pdev, c_str!("selftest"));
+    if np.is_null() {
+        return Ok(());
+    }
+    let ret = run_on(np, data);
+    // SAFETY: `np` was returned with a reference held by `dt::child`.
+    unsafe { bindings::of_node_put(np) };
+    match &ret {
+        Ok(()) => dev_info!(pdev, "selftest passed\n"),
+        Err(e) => dev_err!(pdev, "selftest failed: {}\n", e.to_kernel_errno()),
+    }
+    ret
+}
+
+fn run_on(np: *mut bindings::device_node, data: &ResetDeviceData) -> Result {
+    let rst_id = dt::phandle_arg(np, c_str!("resets"), c_str!("#reset-cells"))? as usize;
+
+    // SAFETY: `np` is a valid node, a null id picks the first `resets` entry.
+    let rstc = unsafe { bindings::__of_reset_control_get(np, core::ptr::null(), 0, false, false, true) };
+    if rstc.is_null() {
+        return Err(error::code::ENODEV);
+    }
+    let err = crate::ptr_err(rstc as *const core::ffi::c_void);
+    if err != 0 {
+        return Err(error::Error::from_kernel_errno(err));
+    }
+    let rstc = Control(rstc);
+
+    // SAFETY: `rstc.0` is a valid exclusive reset control until `rstc` is dropped,
+    // which holds for every call below.
+    step(unsafe { bindings::reset_control_assert(rstc.0) })?;
+    check(data, rst_id, "assert", true)?;
+    // SAFETY: As above.
+    step(unsafe { bindings::reset_control_deassert(rstc.0) })?;
+    check(data, rst_id, "deassert", false)?;
+    // SAFETY: As above.
+    step(unsafe { bindings::reset_control_reset(rstc.0) })?;
+    check(data, rst_id, "reset", false)?;
+
+    // SAFETY: As above.
+    let status = unsafe { bindings::reset_control_status(rstc.0) };
+    if status != 0 {
+        pr_err!("selftest: framework status {} after reset\n", status);
+        return Err(error::code::EIO);
+    }
+    Ok(())
+}
@@ -0,0 +1,1956 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Hermetic cases of the selftest, each on a fake controller of its own
+//!
+//! Cases need neither hardware nor DT: they build a `Fake`, configure its lines
+//! the way the DT properties would, and run ops through the same entry points
+//! the framework and the consumers use.
+
+use kernel::{
+    c_str,
+    delay::coarse_sleep,
+    error,
+    platform,
+    prelude::*,
+    str::CStr,
+};
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bst_reset_rust::{RESET_LONG_HOLD_TIME, ZERO_ASSERT_ONE_DEASSERT};
+
+use crate::{
+    check_reg_resources,
+    consumer::{Phase, SharedPolicy},
+    debugfs::write_csv_field,
+    dt, ktime_get_ns, place_lines, select_map, work::Job, AssertWatchdog, BstMap, IdAlias, LineConfig,
+    MaxAssert, OpKind, RegBit, ReleaseStage, SetClr, StatusReg, StormLimit, Width, BUILTIN_MAPS,
+    LINE_ASSERT_ONLY, LINE_DEASSERT_ONLY, LINE_EDGE_TRIGGERED, LINE_INVERTED, LINE_SELF_CLEARING,
+    LINE_STATUS_INVERTED, MAX_RESET_REGISTERS,
+};
+
+#[cfg(debug_assertions)]
+use crate::debugfs::{File, TraceOpFile};
+#[cfg(feature = "mmio-trace")]
+use crate::mmio_trace::{self, Dir};
+#[cfg(not(feature = "no-sleep"))]
+use crate::LINE_COMPLETION_IRQ;
+#[cfg(feature = "no-sleep")]
+use crate::{DelayProvider, OP_DELAY};
+
+use super::fake::{
+    plain_lines, shared_register, Fake, FakeClock, CLOCK_DISABLED, CLOCK_ENABLED_AT, FAKE_BASE, FAKE_EXTRA,
+    FAKE_REGS, FAKE_WINDOW_SIZE, RECORDING_DELAY,
+};
+
+// Consumer names of the consumer API cases
+const CONSUMER_A: &CStr = c_str!("selftest-a");
+const CONSUMER_B: &CStr = c_str!("selftest-b");
+
+// A case and the name it is reported under
+type Case = (&'static str, fn(&platform::Device) -> Result);
+
+const CASES: &[Case] = &[
+    ("fake register backend", fake_backend),
+    ("reset hold sequence", hold_sequence),
+    ("reg_lock ownership", reg_lock_ownership),
+    ("status extraction check", status_extraction),
+    ("mixed control and status widths", mixed_widths),
+    ("consumer calls run as ops", consumer_ops),
+    ("counted holds", counted_holds),
+    ("leaked holds at remove", leaked_holds),
+    ("shared reset policies", shared_policies),
+    ("exclusive acquisition", exclusive_acquisition),
+    ("pins", pins),
+    ("toggle", toggle),
+    ("traced writes", trace_op),
+    ("set_states", set_states),
+    ("bulk deassert rollback", bulk_rollback),
+    ("clock before deassert", clock_before_deassert),
+    ("asynchronous resets", async_resets),
+    ("completion interrupt", completion_irq),
+    ("post-reset callback and phase hooks", reset_callbacks),
+    ("drive_to", drive_to),
+    ("transactions", transactions),
+    ("map swap", map_swap),
+    ("is_supported", is_supported),
+    ("controller recovery", recover_controller),
+    ("ACPI properties", acpi_properties),
+    ("deassert prerequisites", deassert_prerequisites),
+    ("map.csv quoting", csv_quoting),
+    ("status reads against writers", status_reads),
+    ("reset sequences by line flags", reset_sequences),
+    ("redundant writes skipped", redundant_writes),
+    ("global polarity inversion", global_invert),
+    ("write retries", write_retries),
+    ("legacy id aliases", legacy_aliases),
+    ("ready bits", ready_bits),
+    ("reset time budget", reset_budget),
+    ("coupled lines", coupled_lines),
+    ("absent register windows", absent_windows),
+    ("register bounds", register_bounds),
+    ("reg resource count", reg_resources),
+    ("built-in map selection", builtin_maps),
+    ("re-entrant ops", reentrant_ops),
+    ("SET/CLR register aliases", set_clr_aliases),
+    ("ops before registration", not_ready),
+    ("serialized resets", serialized_resets),
+    ("busy-wait holds", busy_wait_holds),
+    ("strict mode", strict_failures),
+    ("op timeout", op_timeout),
+    ("reset source bits", reset_source),
+    ("staged release", staged_release),
+    ("direct register and bit ids", direct_ids),
+    ("coupled lines of opposite polarity", opposite_polarity_pair),
+    ("reset storms", reset_storms),
+    ("inverted status reads", status_inverted),
+    ("safe states at remove", safe_state),
+    ("status mirrors", status_mirror),
+    ("single reset-source bits", reset_source_bits),
+    ("maximum assert duration", max_assert),
+    ("enable bits", enable_bits),
+    ("MMIO trace order", mmio_trace_order),
+];
+
+// Run every case, the first failure fails the selftest
+pub(crate) fn run(pdev: &platform::Device) -> Result {
+    for (name, case) in CASES {
+        if let Err(e) = case(pdev) {
+            dev_err!(pdev, "selftest: {} failed: {}\n", name, e.to_kernel_errno());
+            return Err(e);
+        }
+    }
+    dev_info!(pdev, "selftest: {} cases passed\n", CASES.len());
+    Ok(())
+}
+
+// Fail the case unless `holds`, reporting what was expected
+fn expect(holds: bool, what: &str) -> Result {
+    if !holds {
+        pr_err!("selftest: expected {}\n", what);
+        return Err(error::code::EINVAL);
+    }
+    Ok(())
+}
+
+// Fail the case unless `result` is the error `code`
+fn expect_err<T>(result: Result<T>, code: error::Error, what: &str) -> Result {
+    let errno = code.to_kernel_errno();
+    match result {
+        Err(e) if e.to_kernel_errno() == errno => Ok(()),
+        Err(e) => {
+            pr_err!("selftest: {}: errno {}, expected {}\n", what, e.to_kernel_errno(), errno);
+            Err(error::code::EINVAL)
+        }
+        Ok(_) => {
+            pr_err!("selftest: {}: succeeded, expected errno {}\n", what, errno);
+            Err(error::code::EINVAL)
+        }
+    }
+}
+
+// Every register access goes through the backend: a line driven on the fake
+// lands where the bit math puts it and its status reads back from the fake,
+// the same steps the framework test runs on MMIO
+fn fake_backend(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let before = FAKE_REGS.peek(line.addr(), line.width());
+
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    let asserted = FAKE_REGS.peek(line.addr(), line.width());
+    expect(asserted == line.value(before, true), "assert to land in the fake register")?;
+    expect(fake.line_status(id)?, "the line to read back asserted")?;
+
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    let deasserted = FAKE_REGS.peek(line.addr(), line.width());
+    expect(deasserted == line.value(asserted, false), "deassert to land in the fake register")?;
+    expect(!fake.line_status(id)?, "the line to read back deasserted")
+}
+
+// `reset()` of a level line holds it asserted, then deasserted, for the hold
+// time each, and each hold twice over on lines needing a long hold
+fn hold_sequence(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        for (id, long_hold) in [(ids[0], false), (ids[1], true)] {
+            if let Some(Some(map)) = data.manager.bsta1000b_map.get_mut(id) {
+                if long_hold {
+                    map.flags |= RESET_LONG_HOLD_TIME;
+                } else {
+                    map.flags &= !RESET_LONG_HOLD_TIME;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    let hold = fake.hold_time_ms * 1000;
+
+    fake.op(OpKind::Reset, ids[0] as u64, BstMap::reset_and_wait_ready)?;
+    expect(RECORDING_DELAY.recorded()?[..] == [hold, hold], "a hold after assert and after deassert")?;
+
+    RECORDING_DELAY.clear();
+    fake.op(OpKind::Reset, ids[1] as u64, BstMap::reset_and_wait_ready)?;
+    expect(RECORDING_DELAY.recorded()?[..] == [hold; 4], "the holds of a long-hold line doubled")
+}
+
+// Debug builds report control register accesses by a task not holding
+// `reg_lock`, and only those
+#[cfg(debug_assertions)]
+fn reg_lock_ownership(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let violations = || fake.reg_lock_violations.load(Ordering::Relaxed);
+
+    fake.with_reg_lock(|| fake.ctrl_write(0, line.addr(), line.width()));
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    expect(violations() == 0, "no report for accesses under reg_lock")?;
+
+    fake.ctrl_write(0, line.addr(), line.width());
+    expect(violations() == 1, "an unlocked control register write to be reported")?;
+    fake.with_reg_lock(|| ());
+    fake.ctrl_read(line.addr(), line.width());
+    expect(violations() == 2, "a control register read after the lock is released to be reported")
+}
+
+// The ownership check is compiled out of release builds
+#[cfg(not(debug_assertions))]
+fn reg_lock_ownership(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// Debug builds check the status bit extracted from the register against the
+// line's mask: reads of a well formed register pass, a corrupted extraction is
+// reported, on this controller only
+#[cfg(debug_assertions)]
+fn status_extraction(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let warned = || fake.status_warned.load(Ordering::Relaxed);
+
+    FAKE_REGS.poke(u32::MAX, line.addr(), line.width());
+    fake.line_status(id)?;
+    FAKE_REGS.poke(0, line.addr(), line.width());
+    fake.line_status(id)?;
+    expect(!warned(), "no report for reads of the register")?;
+
+    // The whole register rather than the line's bit, as an unmasked extraction
+    let unmasked = u32::MAX;
+    expect(!fake.check_status(line, unmasked, line.status_from_bit(unmasked)), "an unmasked status to fail")?;
+    expect(warned(), "an unmasked status to be reported")?;
+    expect(!fake.check_status(line, 1, 2), "a status outside 0/1 to fail")
+}
+
+// The status check is compiled out of release builds
+#[cfg(not(debug_assertions))]
+fn status_extraction(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// A line can be driven through a 32-bit control register while its status
+// comes from a 16-bit register elsewhere, each access at its own width. Probe
+// rejects a line whose bit or alignment does not fit its control width.
+fn mixed_widths(pdev: &platform::Device) -> Result {
+    let status = StatusReg { addr: FAKE_EXTRA + 2, bit_idx: 3, width: Width::W16 };
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        let (addr, bit_idx) = (data.maps[0][id].addr, data.maps[0][id].bit_idx);
+
+        data.maps[0][id].ctrl_width = Width::W16;
+        data.maps[0][id].bit_idx = 16;
+        let layout = dt::check_layout(pdev, &data.manager, &data.maps[0]);
+        expect_err(layout, error::code::EINVAL, "a bit past a 16-bit control register")?;
+
+        data.maps[0][id].ctrl_width = Width::W32;
+        data.maps[0][id].bit_idx = bit_idx;
+        data.maps[0][id].addr = addr + 2;
+        let layout = dt::check_layout(pdev, &data.manager, &data.maps[0]);
+        expect_err(layout, error::code::EINVAL, "a misaligned 32-bit control register")?;
+
+        data.maps[0][id].addr = addr;
+        data.maps[0][id].status_reg = Some(status);
+        dt::check_layout(pdev, &data.manager, &data.maps[0])
+    })?;
+    let line = fake.line(id)?;
+    let before = FAKE_REGS.peek(line.addr(), Width::W32);
+
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    let asserted = FAKE_REGS.peek(line.addr(), Width::W32);
+    expect(asserted == line.value(before, true), "a 32-bit control write")?;
+
+    // The status bit sits in the upper half of the 32-bit word, a status read
+    // must only see that half
+    FAKE_REGS.poke(1 << status.bit_idx, FAKE_EXTRA, Width::W16);
+    FAKE_REGS.poke(0, status.addr, Width::W16);
+    expect(fake.line_status(id)? == (line.status_from_bit(0) != 0), "the status from the 16-bit register")?;
+    FAKE_REGS.poke(1 << status.bit_idx, status.addr, Width::W16);
+    expect(fake.line_status(id)? == (line.status_from_bit(1) != 0), "the status bit to read back set")?;
+    expect(FAKE_REGS.peek(line.addr(), Width::W32) == asserted, "status reads to leave the control register")
+}
+
+// Whether a line reads asserted on the fake
+fn asserted(fake: &Fake, id: usize) -> Result<bool> {
+    fake.line_status(id)
+}
+
+// The last record of the op log, as (kind, id, result, transaction)
+fn last_op(fake: &Fake) -> Option<(&'static str, u64, i32, u32)> {
+    let log = fake.op_log.lock_irqdisable();
+    let record = log.iter().last()?;
+    Some((record.kind.name(), record.id, record.result, record.txn))
+}
+
+// Every call of the consumer API reading or changing a line takes the op path:
+// refused before the controller is ready and while the gate is closed, and
+// recorded in the op log and the heartbeat
+fn consumer_ops(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let ids = plain_lines(&fake, 2)?;
+    let (a, b) = (ids[0] as u64, ids[1] as u64);
+    let ctrl = fake.controller(CONSUMER_A);
+    let eagain = error::code::EAGAIN;
+
+    fake.ready.store(false, Ordering::Release);
+    expect_err(ctrl.assert(a), eagain, "a hold before the controller is ready")?;
+    expect_err(ctrl.toggle(a), eagain, "a toggle before the controller is ready")?;
+    expect_err(ctrl.set_states(&[(a, true), (b, false)]), eagain, "a batch before the controller is ready")?;
+    expect_err(ctrl.drive_to(a, true, Duration::from_millis(1)), eagain, "drive_to before ready")?;
+    expect_err(ctrl.acquire(a, true), eagain, "an acquisition before the controller is ready")?;
+    let bulk = ctrl.deassert_bulk(&[a], false).map_err(|bulk| bulk.error);
+    expect_err(bulk, eagain, "a bulk deassert before the controller is ready")?;
+    fake.ready.store(true, Ordering::Release);
+
+    fake.gated.store(true, Ordering::Release);
+    expect_err(ctrl.toggle(a), error::code::EPERM, "a toggle while gated")?;
+    expect_err(ctrl.set_states(&[(a, true)]), error::code::EPERM, "a batch while gated")?;
+    expect(ctrl.status(a).is_ok(), "status reads while gated")?;
+    fake.gated.store(false, Ordering::Release);
+
+    let beats = fake.heartbeat.load(Ordering::Relaxed);
+    ctrl.toggle(a)?;
+    expect(last_op(&fake) == Some(("toggle", a, 0, 0)), "the toggle recorded in the op log")?;
+    ctrl.set_states(&[(a, false), (b, true)])?;
+    expect(last_op(&fake) == Some(("assert", b, 0, 0)), "each line of a batch recorded")?;
+    expect(fake.heartbeat.load(Ordering::Relaxed) == beats + 3, "the heartbeat counting each line")
+}
+
+// Holds are counted per consumer: the line stays asserted until every hold
+// of every consumer is released, and an unbalanced deassert never underflows
+fn counted_holds(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let ids = plain_lines(&fake, 2)?;
+    let (id, other) = (ids[0], ids[1]);
+    let (a, b) = (fake.controller(CONSUMER_A), fake.controller(CONSUMER_B));
+
+    a.assert(id as u64)?;
+    a.assert(id as u64)?;
+    a.assert(other as u64)?;
+    b.assert(id as u64)?;
+    expect(asserted(&fake, id)?, "the held line asserted")?;
+    expect(a.held()?[..] == [id, other], "both lines of a consumer reported")?;
+
+    a.deassert(id as u64)?;
+    a.deassert(id as u64)?;
+    expect(asserted(&fake, id)?, "the line kept asserted by the other consumer")?;
+    expect(a.held()?[..] == [other], "the released line dropped from the report")?;
+    b.deassert(id as u64)?;
+    expect(!asserted(&fake, id)?, "the line released with its last hold")?;
+
+    // Unbalanced: warned about once, no hold goes negative
+    b.deassert(id as u64)?;
+    b.deassert(id as u64)?;
+    expect(fake.state[id].unbalanced.load(Ordering::Relaxed), "the unbalanced deassert reported")?;
+    b.assert(id as u64)?;
+    b.deassert(id as u64)?;
+    expect(!asserted(&fake, id)?, "one deassert releasing one hold after the unbalanced ones")?;
+    expect(b.held()?.is_empty(), "no hold left")
+}
+
+// Holds never released are reported at remove, and with
+// `bst,release-leaked-holds` dropped with their lines deasserted
+fn leaked_holds(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    fake.controller(CONSUMER_A).assert(id as u64)?;
+    fake.report_leaked_holds();
+    expect(fake.holds.lock().len() == 1, "a leaked hold kept without bst,release-leaked-holds")?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, |data| {
+        data.release_leaked_holds = true;
+        Ok(())
+    })?;
+    let ctrl = fake.controller(CONSUMER_A);
+    ctrl.assert(id as u64)?;
+    ctrl.assert(id as u64)?;
+    fake.report_leaked_holds();
+    expect(fake.holds.lock().is_empty(), "the leaked holds dropped")?;
+    expect(!asserted(&fake, id)?, "the leaked line deasserted")
+}
+
+// `reset()` of a held line follows the shared policy given per call
+fn shared_policies(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0] as u64;
+    let (a, b) = (fake.controller(CONSUMER_A), fake.controller(CONSUMER_B));
+    b.assert(id)?;
+
+    let ebusy = error::code::EBUSY;
+    expect_err(a.reset_with_policy(id, SharedPolicy::Refuse), ebusy, "a refused reset of a held line")?;
+    expect_err(a.reset_with_policy(id, SharedPolicy::SoleHolder), ebusy, "a reset by a non-holder")?;
+    expect_err(fake.op(OpKind::Reset, id, BstMap::reset_and_wait_ready), ebusy, "the line's default policy")?;
+    a.reset_with_policy(id, SharedPolicy::Force)?;
+    expect(asserted(&fake, id as usize)?, "a forced reset leaving the held line asserted")?;
+    b.reset_with_policy(id, SharedPolicy::SoleHolder)?;
+    expect(asserted(&fake, id as usize)?, "the sole holder's reset leaving its line asserted")?;
+    b.deassert(id)
+}
+
+// An acquired line is kept in its acquired state, refused to everyone else
+// and put back into the state it had on release
+fn exclusive_acquisition(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let (a, b) = (fake.controller(CONSUMER_A), fake.controller(CONSUMER_B));
+    let ebusy = error::code::EBUSY;
+    let prior = asserted(&fake, id)?;
+
+    a.acquire(id as u64, true)?;
+    expect(asserted(&fake, id)?, "the line kept in its acquired state")?;
+    expect_err(b.acquire(id as u64, false), ebusy, "a second acquisition")?;
+    expect_err(b.assert(id as u64), ebusy, "a hold of an acquired line")?;
+    expect_err(b.reset(id as u64), ebusy, "a reset by another consumer")?;
+    a.release(id as u64)?;
+    expect(asserted(&fake, id)? == prior, "the prior state restored on release")?;
+    expect(last_op(&fake) == Some(("release", id as u64, 0, 0)), "the release recorded")?;
+
+    b.assert(id as u64)?;
+    expect_err(a.acquire(id as u64, false), ebusy, "acquiring a held line")?;
+    b.deassert(id as u64)
+}
+
+// A pinned line refuses changes by everyone but its pinner, until unpinned
+fn pins(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0] as u64;
+    let (a, b) = (fake.controller(CONSUMER_A), fake.controller(CONSUMER_B));
+    let ebusy = error::code::EBUSY;
+
+    let pin = a.pin(id)?;
+    expect_err(b.pin(id).map(|_| ()), ebusy, "a second pin")?;
+    expect_err(b.assert(id), ebusy, "a hold by another consumer")?;
+    expect_err(fake.op(OpKind::Assert, id, BstMap::assert_line), ebusy, "a framework assert")?;
+    a.toggle(id)?;
+    a.toggle(id)?;
+    pin.unpin();
+    fake.op(OpKind::Assert, id, BstMap::assert_line)?;
+
+    // Dropping the pin unpins too, so a consumer going away cannot lock the line out
+    drop(a.pin(id)?);
+    b.toggle(id).map(|_| ())
+}
+
+// Toggling twice puts a line back where it was, and reports the logical state
+fn toggle(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let ctrl = fake.controller(CONSUMER_A);
+    let before = FAKE_REGS.peek(line.addr(), line.width());
+    let was = asserted(&fake, id)?;
+
+    expect(ctrl.toggle(id as u64)? == !was, "the first toggle flipping the line")?;
+    expect(asserted(&fake, id)? == !was, "the status following the toggle")?;
+    expect(ctrl.toggle(id as u64)? == was, "the second toggle flipping it back")?;
+    expect(FAKE_REGS.peek(line.addr(), line.width()) == before, "the register as it was")
+}
+
+// A debugfs `trace_op` write runs as an op and records the register before and
+// after the change, which differ by the line's bit alone
+#[cfg(debug_assertions)]
+fn trace_op(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let trace = &fake.last_trace;
+    let store = |op: &str| {
+        let mut cmd = Rendered(Vec::new());
+        write!(cmd, "{} {}", op, id).map_err(|_| error::code::ENOMEM)?;
+        TraceOpFile::store(fake.data(), &cmd.0)
+    };
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+
+    for op in ["assert", "deassert"] {
+        store(op)?;
+        let (before, after) = (trace.before.load(Ordering::Relaxed), trace.after.load(Ordering::Relaxed));
+        expect(trace.id.load(Ordering::Relaxed) == id as u32 + 1, "the traced line recorded")?;
+        expect(before ^ after == line.mask(), "the values differing by the line's mask")?;
+        expect(last_op(&fake) == Some((op, id as u64, 0, 0)), "the traced write in the op log")?;
+    }
+
+    // It passes the gate like any other write
+    fake.gated.store(true, Ordering::Release);
+    let refused = store("assert");
+    fake.gated.store(false, Ordering::Release);
+    expect_err(refused, error::code::EPERM, "a traced write while gated")
+}
+
+// Traced writes are a debug build feature
+#[cfg(not(debug_assertions))]
+fn trace_op(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// A batch is checked whole before anything is written, then each register is
+// updated once for all its lines
+fn set_states(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let lines = fake.lines();
+    let (x, y) = shared_register(&fake).ok_or(error::code::ENODEV)?;
+    let plain = |id: usize| {
+        let cfg = &lines[id];
+        cfg.flags == 0 && cfg.partner.is_none() && cfg.stages.is_empty()
+    };
+    if !plain(x) || !plain(y) {
+        pr_info!("selftest: no plain lines share a register, set_states not checked\n");
+        return Ok(());
+    }
+    let z = plain_lines(&fake, 2)?
+        .into_iter()
+        .find(|id| lines[*id].addr != lines[x].addr)
+        .ok_or(error::code::ENODEV)?;
+    let ctrl = fake.controller(CONSUMER_A);
+
+    let writes = FAKE_REGS.writes();
+    let invalid = fake.manager.bsta1000b_map.len() as u64;
+    let undefined = [(x as u64, true), (invalid, true)];
+    expect_err(ctrl.set_states(&undefined), error::code::EINVAL, "an undefined id")?;
+    let twice = [(x as u64, true), (x as u64, false)];
+    expect_err(ctrl.set_states(&twice), error::code::EINVAL, "an id given twice")?;
+    expect(FAKE_REGS.writes() == writes, "nothing written by a refused batch")?;
+
+    ctrl.set_states(&[(x as u64, true), (z as u64, true), (y as u64, false)])?;
+    expect(FAKE_REGS.writes() <= writes + 2, "one write per register")?;
+    expect(asserted(&fake, x)? && asserted(&fake, z)? && !asserted(&fake, y)?, "every line in its state")?;
+    ctrl.set_states(&[(x as u64, false), (y as u64, true)])?;
+    expect(!asserted(&fake, x)? && asserted(&fake, y)?, "a mixed update of one register")
+}
+
+// A bulk deassert failing part way reports how far it got and, with rollback,
+// puts the lines it released back into reset
+fn bulk_rollback(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let ids = plain_lines(&fake, 2)?;
+    let (first, last) = (ids[0] as u64, ids[1] as u64);
+    let invalid = fake.manager.bsta1000b_map.len() as u64;
+    let ctrl = fake.controller(CONSUMER_A);
+    for id in [first, last] {
+        fake.op(OpKind::Assert, id, BstMap::assert_line)?;
+    }
+
+    let failed = match ctrl.deassert_bulk(&[first, invalid, last], true) {
+        Err(bulk) => bulk,
+        Ok(()) => return expect(false, "a batch with an undefined id to fail"),
+    };
+    expect(failed.succeeded == 1 && failed.failed == Some(invalid), "the failing id and the ones before")?;
+    expect(failed.rolled_back && failed.error == error::code::EINVAL, "the rollback reported")?;
+    expect(asserted(&fake, first as usize)?, "the released line put back into reset")?;
+    expect(asserted(&fake, last as usize)?, "the line after the failure untouched")?;
+
+    let failed = match ctrl.deassert_bulk(&[first, invalid], false) {
+        Err(bulk) => bulk,
+        Ok(()) => return expect(false, "the batch to fail again"),
+    };
+    expect(!failed.rolled_back, "no rollback without it")?;
+    expect(!asserted(&fake, first as usize)?, "the released line left released")?;
+    ctrl.deassert_bulk(&[last], true).map_err(|bulk| bulk.error)?;
+    expect(!asserted(&fake, last as usize)?, "a batch that goes through")
+}
+
+// The clock is enabled and settles before the deassert is written, a clock
+// failing to enable leaves the line in reset, and a failed ready wait of a
+// bring-up puts the line back into reset with its clock disabled
+fn clock_before_deassert(pdev: &platform::Device) -> Result {
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.clock_settle_ms = 3;
+        data.maps[0][id].ready = Some(RegBit { addr: FAKE_EXTRA, bit_idx: 0 });
+        Ok(())
+    })?;
+    let deassert = |fails: bool| {
+        fake.op(OpKind::Deassert, id as u64, |data, id| {
+            data.enable_clock_then_deassert(FakeClock { fails }, id)
+        })
+    };
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+
+    expect_err(deassert(true).map(drop), error::code::EIO, "a clock failing to enable")?;
+    expect(asserted(&fake, id)?, "the line left in reset without its clock")?;
+
+    RECORDING_DELAY.clear();
+    let writes = FAKE_REGS.writes();
+    let clk = deassert(false)?;
+    expect(CLOCK_ENABLED_AT.load(Ordering::Acquire) == writes, "the clock enabled before any write")?;
+    expect(RECORDING_DELAY.recorded()?.first() == Some(&3000), "the settle time after the enable")?;
+    expect(!asserted(&fake, id)?, "the line released once its clock runs")?;
+    drop(clk);
+
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    CLOCK_DISABLED.store(false, Ordering::Release);
+    let bring_up = || {
+        let clk = FakeClock { fails: false };
+        fake.op(OpKind::Deassert, id as u64, |data, id| data.bring_up(clk, id))
+    };
+    expect_err(bring_up().map(drop), error::code::ETIMEDOUT, "a block never ready")?;
+    expect(asserted(&fake, id)?, "the line put back into reset")?;
+    expect(CLOCK_DISABLED.load(Ordering::Acquire), "the clock disabled after the line")?;
+
+    FAKE_REGS.poke(1, FAKE_EXTRA, Width::W32);
+    CLOCK_DISABLED.store(false, Ordering::Release);
+    let clk = bring_up()?;
+    expect(!asserted(&fake, id)? && !CLOCK_DISABLED.load(Ordering::Acquire), "a block brought up")?;
+    drop(clk);
+    Ok(())
+}
+
+// Asynchronous resets: a self-clearing line is pending until the hardware
+// releases it, a queued `reset()` completes in the background, and remove
+// drops the queued ones
+fn async_resets(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[0]].flags |= LINE_SELF_CLEARING;
+        Ok(())
+    })?;
+    let (clearing, level) = (ids[0], ids[1]);
+    let ctrl = fake.controller(CONSUMER_A);
+
+    ctrl.start_async_reset(clearing as u64)?;
+    expect(ctrl.reset_pending(clearing as u64)?, "the reset pending while the line reads asserted")?;
+    let line = fake.line(clearing)?;
+    let reg = FAKE_REGS.peek(line.addr(), line.width());
+    FAKE_REGS.poke(line.value(reg, false), line.addr(), line.width());
+    expect(!ctrl.reset_pending(clearing as u64)?, "the reset done once the hardware released the line")?;
+    let einval = error::code::EINVAL;
+    expect_err(ctrl.start_async_reset(level as u64), einval, "a line that does not self-clear")?;
+
+    RECORDING_DELAY.clear();
+    ctrl.async_reset(level as u64)?;
+    for _ in 0..1000 {
+        if !ctrl.reset_pending(level as u64)? {
+            break;
+        }
+        coarse_sleep(Duration::from_millis(1));
+    }
+    expect(!ctrl.reset_pending(level as u64)?, "the queued reset to complete")?;
+    expect(last_op(&fake) == Some(("reset", level as u64, 0, 0)), "the reset run as an op")?;
+    let hold = fake.hold_time_ms * 1000;
+    expect(RECORDING_DELAY.recorded()?[..] == [hold, hold], "the holds of the queued reset")?;
+
+    ctrl.async_reset(level as u64)?;
+    fake.stop_async_resets();
+    expect(!fake.state[level].async_queued.load(Ordering::Acquire), "the queued reset dropped at remove")?;
+    expect_err(ctrl.async_reset(level as u64), error::code::EAGAIN, "a reset queued after remove")
+}
+
+// The queued reset of a completion-irq line sleeps after the assert until the
+// interrupt arrives with the line released, and wakes on it rather than running
+// into its timeout. An edge no reset waits for is handled all the same.
+#[cfg(not(feature = "no-sleep"))]
+fn completion_irq(pdev: &platform::Device) -> Result {
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.maps[0][id].flags |= LINE_SELF_CLEARING | LINE_COMPLETION_IRQ;
+        Ok(())
+    })?;
+    let line = fake.line(id)?;
+    let (addr, width) = (line.addr(), line.width());
+    let ctrl = fake.controller(CONSUMER_A);
+    expect(fake.inject_irq(), "an interrupt with no reset waiting handled")?;
+
+    ctrl.async_reset(id as u64)?;
+    let mut waiting = false;
+    for _ in 0..1000 {
+        let asserted = line.asserted(FAKE_REGS.peek(addr, width));
+        waiting = asserted && fake.irq_waiting.load(Ordering::Acquire) == id + 1;
+        if waiting {
+            break;
+        }
+        coarse_sleep(Duration::from_millis(1));
+    }
+    expect(waiting, "the reset waiting for its interrupt once the line is asserted")?;
+    FAKE_REGS.poke(line.value(FAKE_REGS.peek(addr, width), false), addr, width);
+    expect(fake.inject_irq(), "the completion interrupt handled")?;
+    for _ in 0..1000 {
+        if !ctrl.reset_pending(id as u64)? {
+            break;
+        }
+        coarse_sleep(Duration::from_millis(1));
+    }
+    expect(!ctrl.reset_pending(id as u64)?, "the queued reset to complete")?;
+    expect(last_op(&fake) == Some(("reset", id as u64, 0, 0)), "the reset woken by the interrupt")?;
+    expect(fake.irq_waiting.load(Ordering::Acquire) == 0, "no line left waiting")
+}
+
+// Completion interrupts are not available in the no-sleep build
+#[cfg(feature = "no-sleep")]
+fn completion_irq(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// Reset id passed to the post-reset callback plus one, zero before it ran
+static POST_RESET: AtomicUsize = AtomicUsize::new(0);
+
+fn post_reset(rst_id: usize) {
+    POST_RESET.store(rst_id + 1, Ordering::Release);
+}
+
+// Phase hooks run, as the phase in the upper and the id in the lower 16 bits
+static PHASES: [AtomicUsize; Phase::COUNT] =
+    [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+static PHASES_RUN: AtomicUsize = AtomicUsize::new(0);
+static PHASE_OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+fn record_phase(phase: Phase, rst_id: usize) {
+    match PHASES.get(PHASES_RUN.fetch_add(1, Ordering::AcqRel)) {
+        Some(slot) => slot.store(((phase as usize) << 16) | rst_id, Ordering::Release),
+        None => PHASE_OVERFLOW.store(true, Ordering::Release),
+    }
+}
+
+fn pre_assert(rst_id: usize) {
+    record_phase(Phase::PreAssert, rst_id)
+}
+
+fn post_assert(rst_id: usize) {
+    record_phase(Phase::PostAssert, rst_id)
+}
+
+fn pre_deassert(rst_id: usize) {
+    record_phase(Phase::PreDeassert, rst_id)
+}
+
+fn post_deassert(rst_id: usize) {
+    record_phase(Phase::PostDeassert, rst_id)
+}
+
+// The post-reset callback runs after a successful reset with the line's id,
+// and the four phase hooks at their boundaries, in order
+fn reset_callbacks(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let ctrl = fake.controller(CONSUMER_A);
+    POST_RESET.store(0, Ordering::Release);
+    PHASES_RUN.store(0, Ordering::Release);
+    PHASE_OVERFLOW.store(false, Ordering::Release);
+
+    ctrl.set_post_reset(id as u64, Some(post_reset))?;
+    let hooks = [
+        (Phase::PreAssert, pre_assert as fn(usize)),
+        (Phase::PostAssert, post_assert),
+        (Phase::PreDeassert, pre_deassert),
+        (Phase::PostDeassert, post_deassert),
+    ];
+    for (phase, hook) in hooks {
+        ctrl.set_phase_hook(phase, Some(hook));
+    }
+    ctrl.reset(id as u64)?;
+    expect(POST_RESET.load(Ordering::Acquire) == id + 1, "the callback run with the line's id")?;
+    expect(PHASES_RUN.load(Ordering::Acquire) == Phase::COUNT, "every hook run once")?;
+    expect(!PHASE_OVERFLOW.load(Ordering::Acquire), "no hook run twice")?;
+    for (index, (phase, _)) in hooks.iter().enumerate() {
+        let recorded = PHASES[index].load(Ordering::Acquire);
+        expect(recorded == ((*phase as usize) << 16) | id, "the hooks in phase order")?;
+    }
+
+    for (phase, _) in hooks {
+        ctrl.set_phase_hook(phase, None);
+    }
+    ctrl.set_post_reset(id as u64, None)?;
+    POST_RESET.store(0, Ordering::Release);
+    ctrl.reset(id as u64)?;
+    expect(POST_RESET.load(Ordering::Acquire) == 0, "no callback once removed")?;
+    expect(PHASES_RUN.load(Ordering::Acquire) == Phase::COUNT, "no hook once removed")
+}
+
+// `drive_to` reaches both logical states and times out on a line whose
+// status never follows
+fn drive_to(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        let status = StatusReg { addr: FAKE_EXTRA, bit_idx: 0, width: Width::W32 };
+        data.maps[0][ids[1]].status_reg = Some(status);
+        Ok(())
+    })?;
+    let (id, stuck) = (ids[0] as u64, ids[1]);
+    let ctrl = fake.controller(CONSUMER_A);
+    let timeout = Duration::from_millis(5);
+
+    ctrl.drive_to(id, true, timeout)?;
+    expect(asserted(&fake, id as usize)?, "the line driven into reset")?;
+    ctrl.drive_to(id, false, timeout)?;
+    expect(!asserted(&fake, id as usize)?, "the line driven out of reset")?;
+
+    let target = fake.line(stuck)?.status_from_bit(0) == 0;
+    let stuck_drive = ctrl.drive_to(stuck as u64, target, timeout);
+    expect_err(stuck_drive, error::code::ETIMEDOUT, "a line whose status never follows")
+}
+
+// Ops in a transaction are tagged with its id, one transaction at a time
+fn transactions(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0] as u64;
+    let ctrl = fake.controller(CONSUMER_A);
+
+    expect_err(ctrl.begin_transaction(0), error::code::EINVAL, "transaction id 0")?;
+    ctrl.begin_transaction(7)?;
+    expect_err(ctrl.begin_transaction(8), error::code::EBUSY, "a second open transaction")?;
+    ctrl.toggle(id)?;
+    expect(last_op(&fake) == Some(("toggle", id, 0, 7)), "the op tagged with the transaction")?;
+    expect_err(ctrl.end_transaction(8), error::code::EINVAL, "closing another transaction")?;
+    ctrl.end_transaction(7)?;
+    ctrl.toggle(id)?;
+    expect(last_op(&fake) == Some(("toggle", id, 0, 0)), "no tag after the transaction")
+}
+
+// After a swap, ops use the register bits of the alternate map. A swap is
+// refused without an alternate map and while a line is held.
+fn map_swap(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let swap = fake.controller(CONSUMER_A).swap_map();
+    expect_err(swap, error::code::EOPNOTSUPP, "a swap without an alternate map")?;
+    drop(fake);
+
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        let mut alternate = Vec::try_with_capacity(data.maps[0].len())?;
+        for _ in 0..data.maps[0].len() {
+            alternate.try_push(LineConfig::default())?;
+        }
+        place_lines(&data.manager, &data.bst_address, &data.window_size, &mut alternate)?;
+        let bits = alternate[id].ctrl_width.bits();
+        alternate[id].bit_idx = (alternate[id].bit_idx + 1) % bits;
+        data.maps[1] = alternate;
+        Ok(())
+    })?;
+    let ctrl = fake.controller(CONSUMER_A);
+    let primary = fake.line(id)?.mask();
+
+    ctrl.assert(id as u64)?;
+    expect_err(ctrl.swap_map(), error::code::EBUSY, "a swap while a line is held")?;
+    ctrl.deassert(id as u64)?;
+
+    ctrl.swap_map()?;
+    let line = fake.line(id)?;
+    expect(line.mask() != primary, "the line moved by the alternate map")?;
+    let before = FAKE_REGS.peek(line.addr(), line.width());
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    let after = FAKE_REGS.peek(line.addr(), line.width());
+    expect(after == line.value(before, true), "the assert landing on the alternate bit")?;
+    expect((after ^ before) & primary == 0, "the primary bit left alone")
+}
+
+// Only ids naming a defined line in a present window are supported, legacy
+// aliases of them included
+fn is_supported(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[1]].window = None;
+        data.aliases.try_push(IdAlias { legacy: 900, id: ids[0] as u32, used: AtomicBool::new(false) })?;
+        Ok(())
+    })?;
+    let ctrl = fake.controller(CONSUMER_A);
+    let undefined = fake.manager.bsta1000b_map.len() as u64;
+
+    expect(ctrl.is_supported(ids[0] as u64), "a defined line to be supported")?;
+    expect(ctrl.is_supported(900), "a legacy alias to be supported")?;
+    expect(!ctrl.is_supported(ids[1] as u64), "a line in an absent window to be unsupported")?;
+    expect(!ctrl.is_supported(undefined), "an id past the map to be unsupported")
+}
+
+// Recovery pulses the self-reset bit under the locks of an op and puts the
+// held lines, whose state the self-reset wiped, back into reset
+fn recover_controller(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    expect_err(fake.recover(), error::code::EOPNOTSUPP, "recovery without bst,self-reset")?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, |data| {
+        data.self_reset = Some(RegBit { addr: FAKE_EXTRA, bit_idx: 0 });
+        Ok(())
+    })?;
+    let id = plain_lines(&fake, 1)?[0];
+    let ctrl = fake.controller(CONSUMER_A);
+    ctrl.assert(id as u64)?;
+
+    fake.ready.store(false, Ordering::Release);
+    expect_err(fake.recover(), error::code::EAGAIN, "recovery before the controller is ready")?;
+    fake.ready.store(true, Ordering::Release);
+
+    let line = fake.line(id)?;
+    let (addr, width) = (line.addr(), line.width());
+    FAKE_REGS.poke(line.value(FAKE_REGS.peek(addr, width), false), addr, width);
+    RECORDING_DELAY.clear();
+    fake.recover()?;
+    expect(asserted(&fake, id)?, "the held line asserted again")?;
+    expect(FAKE_REGS.peek(FAKE_EXTRA, Width::W32) & 1 == 0, "the self-reset bit released")?;
+    let hold = fake.hold_time_ms * 1000;
+    expect(RECORDING_DELAY.recorded()?[..] == [hold, hold], "a long hold of the self-reset bit")?;
+    ctrl.deassert(id as u64)
+}
+
+// The `_DSD` properties of ACPI variants, read through a stand-in for the
+// firmware: the hold time as given, the window count as a window mask
+fn acpi_properties(pdev: &platform::Device) -> Result {
+    let dsd = |hold: Option<u32>, windows: Option<u32>| {
+        move |name: &CStr| match name.as_bytes() {
+            b"bst,hold-time-ms" => hold,
+            b"bst,num-windows" => windows,
+            _ => None,
+        }
+    };
+    let config = dt::acpi_config(pdev, dsd(None, None))?;
+    expect(config.hold_time_ms.is_none() && config.windows.is_none(), "nothing without _DSD properties")?;
+    let config = dt::acpi_config(pdev, dsd(Some(20), Some(3)))?;
+    expect(config.hold_time_ms == Some(20), "the hold time from _DSD")?;
+    expect(config.windows == Some(0b111), "three windows from _DSD")?;
+    let config = dt::acpi_config(pdev, dsd(None, Some(MAX_RESET_REGISTERS as u32)))?;
+    expect(config.windows == Some((1 << MAX_RESET_REGISTERS) - 1), "every window")?;
+    let einval = error::code::EINVAL;
+    expect_err(dt::acpi_config(pdev, dsd(None, Some(0))).map(|_| ()), einval, "no window")?;
+    let too_many = dsd(None, Some(MAX_RESET_REGISTERS as u32 + 1));
+    expect_err(dt::acpi_config(pdev, too_many).map(|_| ()), einval, "more windows than slots")
+}
+
+// A line is released only once its prerequisite is, and prerequisites that
+// form a cycle, which no order of deasserts could satisfy, are refused
+fn deassert_prerequisites(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[1]].prerequisites.try_push((ids[0], 0))?;
+        Ok(())
+    })?;
+    let (prereq, dependent) = (ids[0] as u64, ids[1] as u64);
+    for id in [prereq, dependent] {
+        fake.op(OpKind::Assert, id, BstMap::assert_line)?;
+    }
+    let deassert = |id| fake.op(OpKind::Deassert, id, BstMap::deassert_line);
+    expect_err(deassert(dependent), error::code::EAGAIN, "a line released before its prerequisite")?;
+    expect(asserted(&fake, ids[1])?, "the dependent line left in reset")?;
+    deassert(prereq)?;
+    deassert(dependent)?;
+    expect(!asserted(&fake, ids[1])?, "the dependent line released after its prerequisite")?;
+
+    let mut lines = Vec::try_with_capacity(4)?;
+    for _ in 0..4 {
+        lines.try_push(LineConfig::default())?;
+    }
+    // 0 needs 1 and 3, 1 needs 2, 3 needs 2: a diamond without a cycle
+    for (id, prereq) in [(0, 1), (0, 3), (1, 2), (3, 2)] {
+        lines[id].prerequisites.try_push((prereq, 0))?;
+    }
+    dt::check_prerequisite_cycles(pdev, &lines)?;
+    // 2 needs 0 closes 0 -> 1 -> 2 -> 0
+    lines[2].prerequisites.try_push((0, 0))?;
+    let cycle = dt::check_prerequisite_cycles(pdev, &lines);
+    expect_err(cycle, error::code::EINVAL, "a prerequisite cycle through three lines")
+}
+
+// Bytes written through `fmt::Write`, for checking rendered output
+struct Rendered(Vec<u8>);
+
+impl core::fmt::Write for Rendered {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &b in s.as_bytes() {
+            self.0.try_push(b).map_err(|_| core::fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+// Split the first field off a CSV row as RFC 4180 reads it, returning the field
+// unquoted and the rest of the row after its separator
+fn split_csv_field(row: &[u8]) -> Result<(Vec<u8>, &[u8])> {
+    let mut field = Vec::new();
+    if row.first() != Some(&b'"') {
+        let end = row.iter().position(|&b| b == b',').unwrap_or(row.len());
+        for &b in &row[..end] {
+            field.try_push(b)?;
+        }
+        return Ok((field, row.get(end + 1..).unwrap_or(&[])));
+    }
+    let mut index = 1;
+    loop {
+        match (row.get(index), row.get(index + 1)) {
+            (Some(b'"'), Some(b'"')) => {
+                field.try_push(b'"')?;
+                index += 2;
+            }
+            (Some(b'"'), next) => {
+                let rest = if next == Some(&b',') { &row[index + 2..] } else { &row[index + 1..] };
+                return Ok((field, rest));
+            }
+            (Some(&b), _) => {
+                field.try_push(b)?;
+                index += 1;
+            }
+            (None, _) => return Err(error::code::EINVAL),
+        }
+    }
+}
+
+// Names with separators, quotes and line breaks survive a map.csv row
+fn csv_quoting(_pdev: &platform::Device) -> Result {
+    let names: [&[u8]; 7] = [b"uart0", b"a,b", b"say \"hi\"", b"two\nlines", b"\"", b",", b""];
+    for name in names {
+        let mut row = Rendered(Vec::new());
+        let written = write!(row, "7,")
+            .and_then(|()| write_csv_field(&mut row, name))
+            .and_then(|()| write!(row, ",0,0x1000,3,32,INVERTED"));
+        written.map_err(|_| error::code::ENOMEM)?;
+        let (id, rest) = split_csv_field(&row.0)?;
+        let (parsed, rest) = split_csv_field(rest)?;
+        expect(id[..] == *b"7", "the id before the name")?;
+        expect(parsed[..] == *name, "the name read back as written")?;
+        expect(rest == b"0,0x1000,3,32,INVERTED", "the fields after the name intact")?;
+    }
+    Ok(())
+}
+
+// Status reads of lines without a mirror take no lock: they complete while a
+// writer holds `reg_lock`, and readers racing a `reset()` on the workqueue see
+// the line before or after a write, or EBUSY within the reset, while a line
+// nobody writes reads steady. A mirrored line is read under `status_lock`, which
+// holds a writer off until the reader is done.
+fn status_reads(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 3)?;
+        let mirror = StatusReg { addr: FAKE_EXTRA, bit_idx: 0, width: Width::W32 };
+        data.maps[0][ids[2]].status_mirror = Some(mirror);
+        Ok(())
+    })?;
+    let (busy, steady, mirrored) = (ids[0], ids[1], ids[2]);
+    fake.op(OpKind::Deassert, busy as u64, BstMap::deassert_line)?;
+    fake.op(OpKind::Assert, steady as u64, BstMap::assert_line)?;
+
+    let read = fake.with_reg_lock(|| (fake.line_status(busy), fake.line_status(steady)));
+    expect(matches!(read, (Ok(false), Ok(true))), "status reads within a writer's critical section")?;
+
+    let ctrl = fake.controller(CONSUMER_A);
+    let ebusy = error::code::EBUSY.to_kernel_errno();
+    ctrl.async_reset(busy as u64)?;
+    for _ in 0..1000 {
+        if !ctrl.reset_pending(busy as u64)? {
+            break;
+        }
+        if let Err(e) = ctrl.status(busy as u64) {
+            expect(e.to_kernel_errno() == ebusy, "a status or EBUSY during a reset")?;
+        }
+        expect(ctrl.status(steady as u64)?, "a line nobody writes reading steady")?;
+        coarse_sleep(Duration::from_millis(1));
+    }
+    expect(!ctrl.reset_pending(busy as u64)?, "the reset to complete")?;
+    expect(!ctrl.status(busy as u64)?, "the line released by the reset")?;
+
+    fake.op(OpKind::Assert, mirrored as u64, BstMap::assert_line)?;
+    expect(ctrl.status(mirrored as u64)?, "a mirrored line read under the status lock")?;
+    // With `no-sleep` the status lock is a spinlock, which cannot be held across
+    // the wait below
+    #[cfg(not(feature = "no-sleep"))]
+    {
+        let writes = FAKE_REGS.writes();
+        let reader = fake.status_lock.read();
+        ctrl.async_reset(mirrored as u64)?;
+        coarse_sleep(Duration::from_millis(5));
+        let waited = FAKE_REGS.writes() == writes;
+        drop(reader);
+        expect(waited, "a writer waiting for a reader of a mirrored line")?;
+        for _ in 0..1000 {
+            if !ctrl.reset_pending(mirrored as u64)? {
+                break;
+            }
+            coarse_sleep(Duration::from_millis(1));
+        }
+        expect(FAKE_REGS.writes() > writes, "the writer going through once the reader is done")?;
+        expect(!ctrl.status(mirrored as u64)?, "the mirrored line released by the reset")?;
+    }
+    Ok(())
+}
+
+// `reset()` picks its sequence from the line flags: a self-clearing line is
+// asserted once and left for the hardware to release, an edge-triggered one
+// gets a single pulse, and lines that can only be asserted or deasserted
+// refuse it without a write
+fn reset_sequences(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 4)?;
+        let flags = [LINE_SELF_CLEARING, LINE_EDGE_TRIGGERED, LINE_ASSERT_ONLY, LINE_DEASSERT_ONLY];
+        for (&id, flags) in ids.iter().zip(flags) {
+            data.maps[0][id].flags |= flags;
+            if let Some(Some(map)) = data.manager.bsta1000b_map.get_mut(id) {
+                map.flags &= !RESET_LONG_HOLD_TIME;
+            }
+        }
+        Ok(())
+    })?;
+    let (clearing, edge) = (ids[0], ids[1]);
+    let reset = |id: usize| fake.op(OpKind::Reset, id as u64, BstMap::reset_and_wait_ready);
+    let hold = fake.hold_time_ms * 1000;
+
+    // Both start released, whatever their polarity
+    let release = |id: usize| -> Result {
+        let line = fake.line(id)?;
+        FAKE_REGS.poke(line.value(0, false), line.addr(), line.width());
+        Ok(())
+    };
+    release(clearing)?;
+    release(edge)?;
+    let line = fake.line(clearing)?;
+    FAKE_REGS.release_on_read(line.addr() & !3, line.mask(), line.value(0, false));
+    let writes = FAKE_REGS.writes();
+    reset(clearing)?;
+    expect(FAKE_REGS.writes() == writes + 1, "a self-clearing line asserted and never deasserted")?;
+    expect(!asserted(&fake, clearing)?, "the self-clearing line released by the hardware")?;
+    expect(RECORDING_DELAY.recorded()?.first() == Some(&hold), "the hold before polling for the release")?;
+    FAKE_REGS.release_on_read(0, 0, 0);
+
+    RECORDING_DELAY.clear();
+    let writes = FAKE_REGS.writes();
+    reset(edge)?;
+    expect(FAKE_REGS.writes() == writes + 2, "an edge as one assert and one deassert")?;
+    expect(RECORDING_DELAY.recorded()?[..] == [hold], "a single hold within the edge")?;
+    expect(!asserted(&fake, edge)?, "the edge-triggered line released")?;
+
+    let writes = FAKE_REGS.writes();
+    expect_err(reset(ids[2]), error::code::EPERM, "a reset of an assert-only line")?;
+    expect_err(reset(ids[3]), error::code::EPERM, "a reset of a deassert-only line")?;
+    expect(FAKE_REGS.writes() == writes, "nothing written for a refused reset")
+}
+
+// A line already in the requested state is not written again, whether the
+// framework or a second hold asks for it
+fn redundant_writes(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let op = |kind, f: fn(&BstMap, usize) -> Result| fake.op(kind, id as u64, f);
+
+    op(OpKind::Deassert, BstMap::deassert_line)?;
+    let writes = FAKE_REGS.writes();
+    op(OpKind::Deassert, BstMap::deassert_line)?;
+    expect(FAKE_REGS.writes() == writes, "no write deasserting a released line")?;
+    op(OpKind::Assert, BstMap::assert_line)?;
+    expect(FAKE_REGS.writes() == writes + 1, "one write asserting it")?;
+    op(OpKind::Assert, BstMap::assert_line)?;
+    expect(FAKE_REGS.writes() == writes + 1, "no write asserting it again")?;
+    op(OpKind::Deassert, BstMap::deassert_line)?;
+
+    let (a, b) = (fake.controller(CONSUMER_A), fake.controller(CONSUMER_B));
+    let writes = FAKE_REGS.writes();
+    a.assert(id as u64)?;
+    b.assert(id as u64)?;
+    expect(FAKE_REGS.writes() == writes + 1, "one write for two holds")?;
+    a.deassert(id as u64)?;
+    b.deassert(id as u64)?;
+    expect(FAKE_REGS.writes() == writes + 2, "one write for the last release")
+}
+
+// `bst,global-invert` flips the bit written for every line, and a line also
+// listed in `bst,inverted-resets` goes back to the manager's polarity. Status
+// reads follow the polarity, so the lines still read back asserted.
+fn global_invert(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[1]].flags |= LINE_INVERTED;
+        dt::invert_all(&mut data.maps[0]);
+        Ok(())
+    })?;
+    for (id, inverted) in [(ids[0], true), (ids[1], false)] {
+        let line = fake.line(id)?;
+        let active_low = line.map.flags & ZERO_ASSERT_ONE_DEASSERT != 0;
+        fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+        let set = FAKE_REGS.peek(line.addr(), line.width()) & line.mask() != 0;
+        expect(set == (active_low == inverted), "the asserted bit by the line's polarity")?;
+        expect(asserted(&fake, id)?, "the line reading back asserted")?;
+    }
+    Ok(())
+}
+
+// With write retries, a write lost on the bus is read back, repeated and
+// counted, and a line that never changes state fails with EIO once the
+// retries are used up
+fn write_retries(pdev: &platform::Device) -> Result {
+    let fake = Fake::new(pdev, |data| {
+        data.write_retries = 1;
+        Ok(())
+    })?;
+    let id = plain_lines(&fake, 1)?[0];
+    let state = &fake.state[id];
+    let counts = || {
+        (state.retries_attempted.load(Ordering::Relaxed), state.retries_succeeded.load(Ordering::Relaxed))
+    };
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+
+    FAKE_REGS.drop_writes(1);
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    expect(asserted(&fake, id)?, "the line asserted by the repeated write")?;
+    expect(counts() == (1, 1), "the retry counted as attempted and succeeded")?;
+
+    FAKE_REGS.drop_writes(2);
+    let deassert = fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line);
+    expect_err(deassert, error::code::EIO, "a line whose writes never land")?;
+    expect(counts() == (2, 1), "the failed retry counted as attempted only")?;
+    expect(asserted(&fake, id)?, "the line left where it was")
+}
+
+// A legacy id from `bst,id-aliases` drives the line it maps to, is reported
+// once, and is logged under the id the consumer gave
+fn legacy_aliases(pdev: &platform::Device) -> Result {
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.aliases.try_push(IdAlias { legacy: 900, id: id as u32, used: AtomicBool::new(false) })?;
+        Ok(())
+    })?;
+    expect(!fake.aliases[0].used.load(Ordering::Relaxed), "the alias unused before any op")?;
+
+    fake.op(OpKind::Assert, 900, BstMap::assert_line)?;
+    expect(asserted(&fake, id)?, "the aliased line asserted")?;
+    expect(fake.aliases[0].used.load(Ordering::Relaxed), "the alias reported as used")?;
+    expect(last_op(&fake) == Some(("assert", 900, 0, 0)), "the op logged under the legacy id")?;
+    fake.controller(CONSUMER_A).toggle(900)?;
+    expect(!asserted(&fake, id)?, "consumer calls resolving the alias too")
+}
+
+// `reset()` of a line with a `bst,ready-bits` entry waits for the block to
+// report ready after the sequence, ETIMEDOUT once `bst,ready-timeout-ms` passes
+fn ready_bits(pdev: &platform::Device) -> Result {
+    let ready = RegBit { addr: FAKE_EXTRA, bit_idx: 5 };
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.maps[0][id].ready = Some(ready);
+        Ok(())
+    })?;
+    let reset = || fake.op(OpKind::Reset, id as u64, BstMap::reset_and_wait_ready);
+
+    expect_err(reset(), error::code::ETIMEDOUT, "a block never reporting ready")?;
+    expect(!asserted(&fake, id)?, "the line released before the ready wait")?;
+    FAKE_REGS.poke(1 << ready.bit_idx, ready.addr, Width::W32);
+    reset()?;
+    expect(last_op(&fake) == Some(("reset", id as u64, 0, 0)), "the reset succeeding once the block is ready")
+}
+
+// `bst,reset-timeout-ms` bounds the whole `reset()`: a self-clearing line the
+// hardware never releases fails with ETIMEDOUT, and so does a ready wait that
+// would outlast the budget, while a sequence within it goes through
+fn reset_budget(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 3)?;
+        data.reset_timeout_ms = 5;
+        data.ready_timeout_ms = 60_000;
+        data.maps[0][ids[0]].flags |= LINE_SELF_CLEARING;
+        data.maps[0][ids[1]].ready = Some(RegBit { addr: FAKE_EXTRA, bit_idx: 0 });
+        Ok(())
+    })?;
+    let reset = |id: usize| fake.op(OpKind::Reset, id as u64, BstMap::reset_and_wait_ready);
+
+    expect_err(reset(ids[0]), error::code::ETIMEDOUT, "a self-clearing line never released")?;
+    expect(asserted(&fake, ids[0])?, "the stuck line left asserted")?;
+    let started_ns = ktime_get_ns();
+    expect_err(reset(ids[1]), error::code::ETIMEDOUT, "a ready wait cut short by the budget")?;
+    expect(ktime_get_ns() - started_ns < 1_000_000_000, "the budget ending the wait, not the ready timeout")?;
+    reset(ids[2])
+}
+
+// Coupled lines from `bst,coupled-resets` move together whichever of them is
+// driven, with a single write when they share a register
+fn coupled_lines(pdev: &platform::Device) -> Result {
+    let mut pairs = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        let ids = plain_lines(data, 2)?;
+        pairs.try_push((ids[0], ids[1]))?;
+        let lines = &data.maps[0];
+        let plain = |id: usize| !ids.contains(&id) && lines[id].flags == 0 && lines[id].stages.is_empty();
+        if let Some((a, b)) = shared_register(data).filter(|&(a, b)| plain(a) && plain(b)) {
+            pairs.try_push((a, b))?;
+        }
+        for &(a, b) in pairs.iter() {
+            data.maps[0][a].partner = Some(b);
+            data.maps[0][b].partner = Some(a);
+        }
+        Ok(())
+    })?;
+    for (index, &(a, b)) in pairs.iter().enumerate() {
+        let shared = index == 1;
+        fake.op(OpKind::Deassert, a as u64, BstMap::deassert_line)?;
+        let writes = FAKE_REGS.writes();
+        fake.op(OpKind::Assert, a as u64, BstMap::assert_line)?;
+        expect(asserted(&fake, a)? && asserted(&fake, b)?, "both coupled lines asserted")?;
+        let expected = if shared { writes + 1 } else { writes + 2 };
+        expect(FAKE_REGS.writes() == expected, "a single write for lines sharing a register")?;
+        fake.op(OpKind::Deassert, b as u64, BstMap::deassert_line)?;
+        expect(!asserted(&fake, a)? && !asserted(&fake, b)?, "both released through the partner")?;
+    }
+    if pairs.len() == 1 {
+        pr_info!("selftest: no lines share a register, the single write not checked\n");
+    }
+    Ok(())
+}
+
+// Lines in a window missing from `bst,present-windows` fail every op with
+// ENODEV without touching a register, the other lines keep working
+fn absent_windows(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[0]].window = None;
+        Ok(())
+    })?;
+    let (absent, present) = (ids[0] as u64, ids[1] as u64);
+    let enodev = error::code::ENODEV;
+
+    let writes = FAKE_REGS.writes();
+    expect_err(fake.op(OpKind::Assert, absent, BstMap::assert_line), enodev, "an assert")?;
+    expect_err(fake.op(OpKind::Reset, absent, BstMap::reset_and_wait_ready), enodev, "a reset")?;
+    expect_err(fake.op(OpKind::Status, absent, BstMap::reported_status), enodev, "a status read")?;
+    expect(FAKE_REGS.writes() == writes, "nothing written for a line in an absent window")?;
+    fake.op(OpKind::Assert, present, BstMap::assert_line)?;
+    expect(asserted(&fake, ids[1])?, "a line in a present window driven")
+}
+
+// A line whose offset runs past its window fails every op with EFAULT without
+// touching a register, even where the next window takes the address, and so
+// does a SET/CLR alias past the window. DT registers past their window are
+// refused when they are parsed.
+fn register_bounds(pdev: &platform::Device) -> Result {
+    let efault = error::code::EFAULT;
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.maps[0][id].addr += FAKE_WINDOW_SIZE;
+        Ok(())
+    })?;
+    let writes = FAKE_REGS.writes();
+    expect_err(fake.op(OpKind::Assert, id as u64, BstMap::assert_line), efault, "an assert past the window")?;
+    let status = fake.op(OpKind::Status, id as u64, BstMap::reported_status);
+    expect_err(status, efault, "a status read past the window")?;
+    expect(FAKE_REGS.writes() == writes, "no write for a line past its window")?;
+
+    let (bases, sizes) = (&fake.bst_address, &fake.window_size);
+    let last = FAKE_WINDOW_SIZE as u32 - 4;
+    expect(RegBit::from_dt(bases, sizes, 0, last, 0).is_ok(), "the last register of a window from DT")?;
+    let past = RegBit::from_dt(bases, sizes, 0, last + 4, 0);
+    expect_err(past, efault, "a DT register past its window")?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        let window = data.maps[0][id].window.ok_or(error::code::ENODEV)?;
+        data.set_clr[window] = Some(SetClr { set_offset: 0x100, clr_offset: FAKE_WINDOW_SIZE });
+        Ok(())
+    })?;
+    let writes = FAKE_REGS.writes();
+    let assert = fake.op(OpKind::Assert, id as u64, BstMap::assert_line);
+    expect_err(assert, efault, "an assert with a SET/CLR alias past the window")?;
+    expect(FAKE_REGS.writes() == writes, "no write through the aliases")
+}
+
+// Probe needs a `reg` resource for every present window: too few fail with
+// EINVAL up front, extra ones are allowed
+fn reg_resources(pdev: &platform::Device) -> Result {
+    expect_err(check_reg_resources(pdev, 0, 0b1), error::code::EINVAL, "no resources")?;
+    expect_err(check_reg_resources(pdev, 2, 0b1011), error::code::EINVAL, "too few resources")?;
+    check_reg_resources(pdev, 3, 0b1011)?;
+    check_reg_resources(pdev, 4, 0b1011)
+}
+
+// Every revision picks exactly one built-in map, so the order of the table
+// never decides it, and a board without `bst,revision-reg` gets the first
+fn builtin_maps(_pdev: &platform::Device) -> Result {
+    for (index, map) in BUILTIN_MAPS.iter().enumerate() {
+        let overlaps = BUILTIN_MAPS[index + 1..].iter().any(|other| {
+            other.revisions.start() <= map.revisions.end() && map.revisions.start() <= other.revisions.end()
+        });
+        expect(!overlaps, "the built-in maps to cover disjoint revisions")?;
+        for revision in [*map.revisions.start(), *map.revisions.end()] {
+            expect(core::ptr::eq(select_map(Some(revision)), map), "a revision picking its map")?;
+        }
+    }
+    expect(core::ptr::eq(select_map(None), &BUILTIN_MAPS[0]), "the first map without a revision")?;
+    expect(select_map(None).name == "a1000b", "the A1000B map by default")
+}
+
+// An op on a line from within an op on the same line, as from a notifier,
+// fails with EDEADLK, ops on other lines go through, and the claim is dropped
+// with the outer op even when it fails
+fn reentrant_ops(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let ids = plain_lines(&fake, 2)?;
+    let (id, other) = (ids[0] as u64, ids[1] as u64);
+
+    let nested = fake.op(OpKind::Reset, id, |data, id| {
+        let inner = data.op(OpKind::Assert, id as u64, BstMap::assert_line);
+        expect_err(inner, error::code::EDEADLK, "an op nested on the same line")?;
+        data.op(OpKind::Assert, other, BstMap::assert_line)
+    });
+    nested?;
+    expect(asserted(&fake, ids[1])?, "a nested op on another line")?;
+    expect(fake.state[ids[0]].owner.load(Ordering::Acquire) == 0, "the line released by the outer op")?;
+
+    let failed = fake.op(OpKind::Assert, id, |_, _| -> Result { Err(error::code::EIO) });
+    expect_err(failed, error::code::EIO, "a failing op")?;
+    expect(fake.state[ids[0]].owner.load(Ordering::Acquire) == 0, "the line released by the failed op")?;
+    fake.op(OpKind::Assert, id, BstMap::assert_line)
+}
+
+// In a window with `bst,set-clr-windows` aliases only the line's bit is
+// written, to the SET or CLR alias by its polarity, so the bits other
+// drivers own in the register are never read back and rewritten
+fn set_clr_aliases(pdev: &platform::Device) -> Result {
+    let set_clr = SetClr { set_offset: 0x100, clr_offset: 0x200 };
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        let window = data.maps[0][id].window.ok_or(error::code::ENODEV)?;
+        data.set_clr[window] = Some(set_clr);
+        Ok(())
+    })?;
+    let line = fake.line(id)?;
+    let (addr, width, mask) = (line.addr(), line.width(), line.mask());
+    let released = line.value(!mask, false);
+    FAKE_REGS.poke(released, addr, width);
+    // The alias a line drives into reset, and the one releasing it
+    let (assert_at, release_at) = match line.value(0, true) {
+        0 => (addr + set_clr.clr_offset, addr + set_clr.set_offset),
+        _ => (addr + set_clr.set_offset, addr + set_clr.clr_offset),
+    };
+
+    let writes = FAKE_REGS.writes();
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    expect(FAKE_REGS.writes() == writes + 1, "a single write to assert")?;
+    expect(FAKE_REGS.peek(assert_at, width) == mask, "only the line's bit written to its alias")?;
+    expect(FAKE_REGS.peek(addr, width) == released, "the control register itself left alone")?;
+
+    // The hardware applies the alias write to the control register
+    FAKE_REGS.poke(line.value(released, true), addr, width);
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    expect(FAKE_REGS.peek(release_at, width) == mask, "the release written to the other alias")?;
+    expect(FAKE_REGS.peek(addr, width) == line.value(released, true), "still no control register write")
+}
+
+// Until the controller is registered every framework op, status reads
+// included, fails with EAGAIN without touching a register, and is logged
+fn not_ready(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0] as u64;
+    let eagain = error::code::EAGAIN;
+    fake.ready.store(false, Ordering::Release);
+
+    let writes = FAKE_REGS.writes();
+    expect_err(fake.op(OpKind::Assert, id, BstMap::assert_line), eagain, "an early assert")?;
+    expect_err(fake.op(OpKind::Deassert, id, BstMap::deassert_line), eagain, "an early deassert")?;
+    expect_err(fake.op(OpKind::Reset, id, BstMap::reset_and_wait_ready), eagain, "an early reset")?;
+    expect_err(fake.op(OpKind::Status, id, BstMap::reported_status), eagain, "an early status read")?;
+    expect(FAKE_REGS.writes() == writes, "nothing written before registration")?;
+    expect(last_op(&fake) == Some(("status", id, eagain.to_kernel_errno(), 0)), "the refusal logged")?;
+
+    fake.ready.store(true, Ordering::Release);
+    fake.op(OpKind::Assert, id, BstMap::assert_line)?;
+    expect(asserted(&fake, id as usize)?, "ops going through once registered")
+}
+
+// `reset()` runs under `reset_lock`: a reset queued while another holds it
+// waits without writing anything, and completes once the lock is released.
+// Status reads do not wait on it.
+#[cfg(not(feature = "no-sleep"))]
+fn serialized_resets(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let ctrl = fake.controller(CONSUMER_A);
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+
+    let writes = FAKE_REGS.writes();
+    {
+        let _guard = fake.reset_lock.lock();
+        ctrl.async_reset(id as u64)?;
+        coarse_sleep(Duration::from_millis(20));
+        expect(ctrl.reset_pending(id as u64)?, "the queued reset waiting for the lock")?;
+        expect(FAKE_REGS.writes() == writes, "nothing written while another reset runs")?;
+        expect(!ctrl.status(id as u64)?, "status reads while the lock is held")?;
+    }
+    for _ in 0..1000 {
+        if !ctrl.reset_pending(id as u64)? {
+            break;
+        }
+        coarse_sleep(Duration::from_millis(1));
+    }
+    expect(!ctrl.reset_pending(id as u64)?, "the reset run once the lock is free")?;
+    expect(FAKE_REGS.writes() == writes + 2, "the whole sequence written after the wait")
+}
+
+// The lock is a spinlock without sleeping, it cannot be held across a sleep
+#[cfg(feature = "no-sleep")]
+fn serialized_resets(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// The `no-sleep` build holds lines by busy-waiting: the delay of the ops
+// waits out a hold in full with `reg_lock` taken, where sleeping is not
+// allowed, and `reset_lock` can be taken in atomic context too
+#[cfg(feature = "no-sleep")]
+fn busy_wait_holds(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let hold = Duration::from_micros(2500);
+    let started_ns = ktime_get_ns();
+    {
+        let _guard = fake.reset_lock.lock();
+        fake.with_reg_lock(|| OP_DELAY.sleep(hold));
+    }
+    let elapsed_ns = ktime_get_ns() - started_ns;
+    expect(elapsed_ns >= hold.as_nanos() as i64, "a busy-wait lasting the whole hold")
+}
+
+// Sleeping builds are what every other case runs on
+#[cfg(not(feature = "no-sleep"))]
+fn busy_wait_holds(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
+
+// Strict mode turns warnings into failures: an unbalanced deassert fails with
+// EINVAL without a write, and two ids on one register bit fail the map check,
+// which only warns otherwise
+fn strict_failures(pdev: &platform::Device) -> Result {
+    let fake = Fake::new(pdev, |data| {
+        data.strict = true;
+        Ok(())
+    })?;
+    let id = plain_lines(&fake, 1)?[0];
+    let writes = FAKE_REGS.writes();
+    let unbalanced = fake.controller(CONSUMER_A).deassert(id as u64);
+    expect_err(unbalanced, error::code::EINVAL, "an unbalanced deassert")?;
+    expect(FAKE_REGS.writes() == writes, "nothing written for it")?;
+
+    let mut lines = Vec::try_with_capacity(fake.lines().len())?;
+    for cfg in fake.lines() {
+        lines.try_push(LineConfig { addr: cfg.addr, bit_idx: cfg.bit_idx, ..LineConfig::default() })?;
+    }
+    dt::check_overlaps(pdev, &fake.manager, &lines)?;
+    let other = fake.defined().map(|(id, _)| id).find(|other| *other != id).ok_or(error::code::ENODEV)?;
+    lines[other].addr = lines[id].addr;
+    lines[other].bit_idx = lines[id].bit_idx;
+    let strict = dt::strict_mode(pdev) || dt::read_bool(pdev, c_str!("bst,strict-map"));
+    let overlaps = dt::check_overlaps(pdev, &fake.manager, &lines);
+    if strict {
+        expect_err(overlaps, error::code::EINVAL, "two ids on one bit in strict mode")
+    } else {
+        expect(overlaps.is_ok(), "two ids on one bit only warned about")
+    }
+}
+
+// `bst,op-timeout-ms` cuts every wait of an op short: a ready wait far longer
+// than the op timeout fails with ETIMEDOUT once it passes, a prerequisite wait
+// gives up with EAGAIN, and the timeout is disarmed after the op
+fn op_timeout(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 3)?;
+        data.op_timeout_ms = 5;
+        data.reset_timeout_ms = 60_000;
+        data.ready_timeout_ms = 60_000;
+        data.maps[0][ids[0]].ready = Some(RegBit { addr: FAKE_EXTRA, bit_idx: 0 });
+        data.maps[0][ids[2]].prerequisites.try_push((ids[1], 60_000))?;
+        Ok(())
+    })?;
+    let (slow, prereq, dependent) = (ids[0], ids[1] as u64, ids[2] as u64);
+    let quick = |started_ns: i64| ktime_get_ns() - started_ns < 1_000_000_000;
+
+    let started_ns = ktime_get_ns();
+    let reset = fake.op(OpKind::Reset, slow as u64, BstMap::reset_and_wait_ready);
+    expect_err(reset, error::code::ETIMEDOUT, "a block never ready")?;
+    expect(quick(started_ns), "the ready wait ended by the op timeout")?;
+    expect(fake.state[slow].op_deadline_ns.load(Ordering::Acquire) == 0, "the timeout disarmed")?;
+
+    fake.op(OpKind::Assert, prereq, BstMap::assert_line)?;
+    fake.op(OpKind::Assert, dependent, BstMap::assert_line)?;
+    let started_ns = ktime_get_ns();
+    let deassert = fake.op(OpKind::Deassert, dependent, BstMap::deassert_line);
+    expect_err(deassert, error::code::EAGAIN, "a prerequisite never released")?;
+    expect(quick(started_ns), "the prerequisite wait ended by the op timeout")?;
+    fake.op(OpKind::Deassert, prereq, BstMap::deassert_line)?;
+    fake.op(OpKind::Deassert, dependent, BstMap::deassert_line)
+}
+
+// The latched reset-source bits read as latched, stay latched unless cleared,
+// and without `bst,reset-source-reg` there is nothing to read
+fn reset_source(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    expect_err(fake.read_reset_source(false), error::code::ENODEV, "a controller without the register")?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, |data| {
+        data.reset_source = Some(FAKE_EXTRA);
+        Ok(())
+    })?;
+    FAKE_REGS.poke(0b1010, FAKE_EXTRA, Width::W32);
+    let writes = FAKE_REGS.writes();
+    expect(fake.read_reset_source(false)? == 0b1010, "the latched bits")?;
+    expect(FAKE_REGS.writes() == writes, "a plain read leaving them latched")?;
+    expect(fake.read_reset_source(true)? == 0b1010, "the bits latched before the clear")?;
+    // The fake keeps what was written rather than clearing it, as W1C hardware would
+    expect(FAKE_REGS.writes() == writes + 1, "a single write to clear")?;
+    expect(FAKE_REGS.peek(FAKE_EXTRA, Width::W32) == 0b1010, "ones written to the latched bits")
+}
+
+// A staged line is asserted with its stage bits in one write, and released
+// stage by stage, each followed by its wait, before the line's own bit
+fn staged_release(pdev: &platform::Device) -> Result {
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        let cfg = &mut data.maps[0][id];
+        let bits = cfg.ctrl_width.bits();
+        for (step, delay_ms) in [(1, 2), (2, 3)] {
+            cfg.stages.try_push(ReleaseStage { bit_idx: (cfg.bit_idx + step) % bits, delay_ms })?;
+        }
+        Ok(())
+    })?;
+    let line = fake.line(id)?;
+    let bits = line.mask() | line.stages_mask();
+    let (addr, width) = (line.addr(), line.width());
+    let driven = |asserted: bool| {
+        let reg = FAKE_REGS.peek(addr, width) & bits;
+        reg == if asserted != line.active_low() { bits } else { 0 }
+    };
+
+    let writes = FAKE_REGS.writes();
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    expect(FAKE_REGS.writes() == writes + 1 && driven(true), "the line and its stages asserted at once")?;
+
+    RECORDING_DELAY.clear();
+    let writes = FAKE_REGS.writes();
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    expect(driven(false), "every stage and the line released")?;
+    expect(FAKE_REGS.writes() == writes + 3, "one write per stage and one for the line")?;
+    expect(RECORDING_DELAY.recorded()?[..] == [2000, 3000], "the wait of each stage, in order")
+}
+
+// With `#reset-cells = <2>` a specifier names a register window and bit
+// directly: the id drives that bit active high, bypassing the map, and
+// specifiers outside the windows or of the other form are refused
+fn direct_ids(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    expect_err(fake.xlate(&[1, 3]), error::code::EINVAL, "a two-cell specifier without bst,direct-cells")?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, |data| {
+        data.direct_cells = true;
+        Ok(())
+    })?;
+    let einval = error::code::EINVAL;
+    expect_err(fake.xlate(&[3]), einval, "a one-cell specifier with two cells expected")?;
+    expect_err(fake.xlate(&[1, 32]), einval, "a bit past the register")?;
+    expect_err(fake.xlate(&[MAX_RESET_REGISTERS as u32, 0]), einval, "a window that is not mapped")?;
+
+    let id = fake.xlate(&[1, 3])?;
+    let addr = FAKE_BASE + FAKE_WINDOW_SIZE;
+    fake.op(OpKind::Assert, id, BstMap::assert_line)?;
+    expect(FAKE_REGS.peek(addr, Width::W32) == 1 << 3, "the bit set in the window's first register")?;
+    expect(fake.op(OpKind::Status, id, BstMap::reported_status)?, "the direct line reading asserted")?;
+    fake.op(OpKind::Deassert, id, BstMap::deassert_line)?;
+    expect(FAKE_REGS.peek(addr, Width::W32) == 0, "the bit cleared again")
+}
+
+// Coupled lines of opposite polarity sharing a register are still driven by
+// one write, each bit set or cleared by its own line's polarity
+fn opposite_polarity_pair(pdev: &platform::Device) -> Result {
+    let mut pair = None;
+    let fake = Fake::new(pdev, |data| {
+        let lines = &data.maps[0];
+        let plain = |id: usize| {
+            let cfg = &lines[id];
+            cfg.flags == 0 && cfg.stages.is_empty() && cfg.partner.is_none()
+        };
+        pair = shared_register(data).filter(|&(a, b)| plain(a) && plain(b));
+        if let Some((a, b)) = pair {
+            data.maps[0][a].partner = Some(b);
+            data.maps[0][b].partner = Some(a);
+            data.maps[0][b].flags |= LINE_INVERTED;
+        }
+        Ok(())
+    })?;
+    let (a, b) = match pair {
+        Some(pair) => pair,
+        None => {
+            pr_info!("selftest: no plain lines share a register, opposite polarity not checked\n");
+            return Ok(());
+        }
+    };
+    let (line_a, line_b) = (fake.line(a)?, fake.line(b)?);
+    expect(line_a.active_low() != line_b.active_low(), "the pair of opposite polarity")?;
+    let (addr, width) = (line_a.addr(), line_a.width());
+
+    fake.op(OpKind::Deassert, a as u64, BstMap::deassert_line)?;
+    let released = FAKE_REGS.peek(addr, width);
+    let writes = FAKE_REGS.writes();
+    fake.op(OpKind::Assert, a as u64, BstMap::assert_line)?;
+    expect(FAKE_REGS.writes() == writes + 1, "a single write for the pair")?;
+    let expected = line_b.value(line_a.value(released, true), true);
+    expect(FAKE_REGS.peek(addr, width) == expected, "each bit by its own polarity")?;
+    expect(asserted(&fake, a)? && asserted(&fake, b)?, "both lines reading asserted")?;
+    fake.op(OpKind::Deassert, b as u64, BstMap::deassert_line)?;
+    expect(FAKE_REGS.peek(addr, width) == released, "both released together")
+}
+
+// Resets of a line past `bst,storm-threshold` within the window are a storm:
+// with a cooldown they are refused with EBUSY until it passes, other lines
+// are unaffected, and without one the storm is only warned about
+fn reset_storms(pdev: &platform::Device) -> Result {
+    let storm = |cooldown_ms: u64| {
+        move |data: &mut BstMap| -> Result {
+            let cooldown_ns = cooldown_ms * 1_000_000;
+            data.storm = Some(StormLimit { threshold: 2, window_ns: 10_000_000_000, cooldown_ns });
+            Ok(())
+        }
+    };
+    let fake = Fake::new(pdev, storm(20))?;
+    let ids = plain_lines(&fake, 2)?;
+    let reset = |id: usize| fake.op(OpKind::Reset, id as u64, BstMap::reset_and_wait_ready);
+    reset(ids[0])?;
+    reset(ids[0])?;
+    expect_err(reset(ids[0]), error::code::EBUSY, "a reset over the threshold")?;
+    expect_err(reset(ids[0]), error::code::EBUSY, "a reset within the cooldown")?;
+    reset(ids[1])?;
+    coarse_sleep(Duration::from_millis(30));
+    reset(ids[0])?;
+    drop(fake);
+
+    let fake = Fake::new(pdev, storm(0))?;
+    for _ in 0..4 {
+        fake.op(OpKind::Reset, ids[0] as u64, BstMap::reset_and_wait_ready)?;
+    }
+    Ok(())
+}
+
+// A line in `bst,status-inverted-resets` reads its status with the opposite
+// sense while its control bit keeps the line's polarity
+fn status_inverted(pdev: &platform::Device) -> Result {
+    let status = StatusReg { addr: FAKE_EXTRA, bit_idx: 0, width: Width::W32 };
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.maps[0][id].status_reg = Some(status);
+        data.maps[0][id].flags |= LINE_STATUS_INVERTED;
+        Ok(())
+    })?;
+    let line = fake.line(id)?;
+    for bit in [false, true] {
+        FAKE_REGS.poke(bit as u32, status.addr, status.width);
+        // Without the flag the line would read asserted for `bit != active_low`
+        expect(asserted(&fake, id)? == (bit == line.active_low()), "the status bit read inverted")?;
+    }
+
+    let before = FAKE_REGS.peek(line.addr(), line.width());
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    let written = FAKE_REGS.peek(line.addr(), line.width());
+    expect(written == line.value(before, true), "the control bit by the line's polarity alone")
+}
+
+// At remove the lines of `bst,safe-state-on-remove` are driven to their safe
+// states even though ops are refused by then, and a line that cannot be
+// driven that way does not keep the others from theirs
+fn safe_state(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 3)?;
+        data.maps[0][ids[1]].flags |= LINE_ASSERT_ONLY;
+        for (id, asserted) in [(ids[0], true), (ids[1], false), (ids[2], false)] {
+            data.safe_state.try_push((id, asserted))?;
+        }
+        Ok(())
+    })?;
+    fake.op(OpKind::Deassert, ids[0] as u64, BstMap::deassert_line)?;
+    fake.op(OpKind::Assert, ids[1] as u64, BstMap::assert_line)?;
+    fake.op(OpKind::Assert, ids[2] as u64, BstMap::assert_line)?;
+
+    fake.stop_async_resets();
+    fake.apply_safe_state();
+    expect(asserted(&fake, ids[0])?, "a line left asserted")?;
+    expect(asserted(&fake, ids[1])?, "an assert-only line left as it was")?;
+    expect(!asserted(&fake, ids[2])?, "the line after the failing one released")
+}
+
+// A status read is compared with the line's `bst,status-mirrors` copy: one
+// agreeing passes, one disagreeing is logged and the control register trusted,
+// or with `bst,status-mirror-strict` the read fails with EIO
+fn status_mirror(pdev: &platform::Device) -> Result {
+    let mirror = StatusReg { addr: FAKE_EXTRA, bit_idx: 4, width: Width::W32 };
+    for strict in [false, true] {
+        let mut id = 0;
+        let fake = Fake::new(pdev, |data| {
+            id = plain_lines(data, 1)?[0];
+            data.maps[0][id].status_mirror = Some(mirror);
+            data.status_mirror_strict = strict;
+            Ok(())
+        })?;
+        let line = fake.line(id)?;
+        let status = || fake.op(OpKind::Status, id as u64, BstMap::reported_status);
+        fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+
+        // The mirror bit reads asserted when set, for an active-high line
+        let agreeing = (!line.active_low() as u32) << mirror.bit_idx;
+        FAKE_REGS.poke(agreeing, mirror.addr, mirror.width);
+        expect(status()?, "a status read agreeing with its mirror")?;
+        FAKE_REGS.poke(agreeing ^ 1 << mirror.bit_idx, mirror.addr, mirror.width);
+        if strict {
+            expect_err(status(), error::code::EIO, "a disagreeing mirror in strict mode")?;
+        } else {
+            expect(status()?, "the control register trusted over the mirror")?;
+        }
+    }
+    Ok(())
+}
+
+// Clearing some reset-source bits writes ones to those bits alone, never a
+// read-modify-write that would clear every latched bit, and clearing bits
+// that are not latched writes nothing
+fn reset_source_bits(pdev: &platform::Device) -> Result {
+    let fake = Fake::new(pdev, |data| {
+        data.reset_source = Some(FAKE_EXTRA);
+        Ok(())
+    })?;
+    FAKE_REGS.poke(0b1011, FAKE_EXTRA, Width::W32);
+    let writes = FAKE_REGS.writes();
+    expect(fake.clear_reset_source(0b0100)? == 0b1011, "the latched bits returned")?;
+    expect(FAKE_REGS.writes() == writes, "no write for a bit not latched")?;
+    expect(fake.clear_reset_source(0b0110)? == 0b1011, "the latched bits returned again")?;
+    // The fake keeps the written value, W1C hardware would clear those bits
+    expect(FAKE_REGS.writes() == writes + 1, "a single write")?;
+    expect(FAKE_REGS.peek(FAKE_EXTRA, Width::W32) == 0b0010, "a one written to the bit asked for only")
+}
+
+// The assert watchdog acts once on a line asserted for longer than its
+// `bst,max-assert-ms`: it only warns about a line set to warn, and releases a
+// line set to be deasserted
+fn max_assert(pdev: &platform::Device) -> Result {
+    let mut ids = Vec::new();
+    let fake = Fake::new(pdev, |data| {
+        ids = plain_lines(data, 2)?;
+        data.maps[0][ids[0]].max_assert = Some(MaxAssert { ms: 2, deassert: false });
+        data.maps[0][ids[1]].max_assert = Some(MaxAssert { ms: 2, deassert: true });
+        Ok(())
+    })?;
+    let (warned, released) = (ids[0], ids[1]);
+    for id in [warned, released] {
+        fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+        fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    }
+
+    coarse_sleep(Duration::from_millis(5));
+    AssertWatchdog.run(fake.data());
+    expect(asserted(&fake, warned)?, "a line set to warn left asserted")?;
+    expect(fake.state[warned].over_max.load(Ordering::Relaxed), "the overlong assertion noted")?;
+    expect(!asserted(&fake, released)?, "a line set to be deasserted released")?;
+    expect(last_op(&fake) == Some(("deassert", released as u64, 0, 0)), "the release run as an op")?;
+
+    // Asserting again starts a new assertion the watchdog acts on afresh
+    fake.op(OpKind::Deassert, warned as u64, BstMap::deassert_line)?;
+    fake.op(OpKind::Assert, warned as u64, BstMap::assert_line)?;
+    expect(!fake.state[warned].over_max.load(Ordering::Relaxed), "a new assertion not over yet")
+}
+
+// The enable bit of a gated line is set for the reset bit write and cleared
+// after it, leaving the other bits of its register alone
+fn enable_bits(pdev: &platform::Device) -> Result {
+    let enable = RegBit { addr: FAKE_EXTRA, bit_idx: 3 };
+    let mut id = 0;
+    let fake = Fake::new(pdev, |data| {
+        id = plain_lines(data, 1)?[0];
+        data.maps[0][id].enable = Some(enable);
+        Ok(())
+    })?;
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    FAKE_REGS.poke(0b0001, FAKE_EXTRA, Width::W32);
+
+    let gated = |kind, drive: fn(&BstMap, usize) -> Result, want| {
+        let writes = FAKE_REGS.writes();
+        fake.op(kind, id as u64, drive)?;
+        expect(asserted(&fake, id)? == want, "the gated line driven")?;
+        expect(FAKE_REGS.writes() == writes + 3, "the enable set and cleared around the write")?;
+        expect(FAKE_REGS.peek(FAKE_EXTRA, Width::W32) == 0b0001, "the enable bit left clear")
+    };
+    gated(OpKind::Assert, BstMap::assert_line, true)?;
+    gated(OpKind::Deassert, BstMap::deassert_line, false)
+}
+
+// The MMIO trace keeps the accesses of a sequence of ops in the order they were
+// made: an assert then a deassert read the register before each write, and
+// the writes land as the assert then the release
+#[cfg(feature = "mmio-trace")]
+fn mmio_trace_order(pdev: &platform::Device) -> Result {
+    let fake = Fake::plain(pdev)?;
+    let id = plain_lines(&fake, 1)?[0];
+    let line = fake.line(id)?;
+    let (addr, width) = (line.addr(), line.width());
+    let released = line.value(FAKE_REGS.peek(addr, width), false);
+    FAKE_REGS.poke(released, addr, width);
+    let mut first = 0;
+    mmio_trace::for_each(|access| {
+        first = access.seq + 1;
+        Ok(())
+    })?;
+
+    fake.op(OpKind::Assert, id as u64, BstMap::assert_line)?;
+    fake.op(OpKind::Deassert, id as u64, BstMap::deassert_line)?;
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+    let mut prev = None;
+    let mut ordered = true;
+    mmio_trace::for_each(|access| {
+        if access.seq < first {
+            return Ok(());
+        }
+        ordered &= prev.map_or(true, |seq| seq < access.seq);
+        prev = Some(access.seq);
+        match access.dir {
+            Dir::Read if access.addr == addr => reads.try_push(access.seq)?,
+            Dir::Write if access.addr == addr => writes.try_push((access.seq, access.val))?,
+            _ => (),
+        }
+        Ok(())
+    })?;
+    expect(ordered, "the accesses in the order they were made")?;
+    let asserted = line.value(released, true);
+    let (assert_at, release_at) = match writes[..] {
+        [(assert_at, a), (release_at, r)] if a == asserted && r == released => (assert_at, release_at),
+        _ => return expect(false, "the assert written, then the release"),
+    };
+    // Each write is a read-modify-write of the register
+    let read_between = |from, to| reads.iter().any(|&seq| from <= seq && seq < to);
+    expect(read_between(first, assert_at), "the register read before the assert")?;
+    expect(read_between(assert_at, release_at), "the register read again before the release")
+}
+
+// Only built with the trace
+#[cfg(not(feature = "mmio-trace"))]
+fn mmio_trace_order(_pdev: &platform::Device) -> Result {
+    Ok(())
+}
@@ -0,0 +1,435 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Fake controller of the selftest: the driver logic on registers kept in memory
+//!
+//! A `Fake` is a `BstMap` built the way probe builds one, on the A1000B map, with
+//! every window backed by `FAKE_REGS` instead of MMIO. Cases configure it before
+//! it is pinned, then drive it through the entry points the framework and the
+//! consumers use, and check the registers, the logs and the errors. It is never
+//! registered with the reset framework.
+
+use kernel::{
+    bindings,
+    device,
+    error,
+    platform,
+    prelude::*,
+    reset::ResetRegistration,
+    str::CStr,
+    sync::{Arc, Mutex, SpinLock},
+};
+
+use core::{
+    ffi::c_void,
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    build_a1000b, consumer::Controller, irq, place_lines, work, BlockClock, BstMap, BstResetDriver,
+    DelayProvider, LevelShifter, LineConfig, LineState, OpLog, Phase, RegAccess, ResetDeviceData, ResetLock,
+    StatusLock, Width, A1000B_REGISTERS, MAX_RESET_REGISTERS,
+};
+
+#[cfg(feature = "mmio-trace")]
+use crate::mmio_trace;
+
+// Base and size of each fake window. They are only ever used as addresses into
+// `FAKE_REGS`, never dereferenced.
+pub(crate) const FAKE_BASE: usize = 0x6000_0000;
+pub(crate) const FAKE_WINDOW_SIZE: usize = 1 << 20;
+
+// Registers served past the windows, like an expander behind a regmap would
+pub(crate) const FAKE_EXTRA: usize = FAKE_BASE + A1000B_REGISTERS * FAKE_WINDOW_SIZE;
+const FAKE_END: usize = FAKE_EXTRA + FAKE_WINDOW_SIZE;
+
+// Registers a case may touch, each takes a slot on its first write
+const FAKE_SLOTS: usize = 128;
+
+// A 32-bit register, free while `addr` is zero
+struct FakeReg {
+    addr: AtomicUsize,
+    val: AtomicU32,
+}
+
+const FREE: FakeReg = FakeReg { addr: AtomicUsize::new(0), val: AtomicU32::new(0) };
+
+// Registers of the fake controller. Registers never written read as zero,
+// narrower accesses address bytes of the 32-bit register holding them.
+pub(crate) struct FakeRegs {
+    regs: [FakeReg; FAKE_SLOTS],
+    // Writes seen, dropped ones included
+    writes: AtomicUsize,
+    // Upcoming writes that do not land, as on a flaky bus
+    dropped: AtomicUsize,
+    // Bits of one register released by hardware once read, for self-clearing lines
+    release_addr: AtomicUsize,
+    release_mask: AtomicU32,
+    release_val: AtomicU32,
+}
+
+pub(crate) static FAKE_REGS: FakeRegs = FakeRegs {
+    regs: [FREE; FAKE_SLOTS],
+    writes: AtomicUsize::new(0),
+    dropped: AtomicUsize::new(0),
+    release_addr: AtomicUsize::new(0),
+    release_mask: AtomicU32::new(0),
+    release_val: AtomicU32::new(0),
+};
+
+// Bits of a `width` wide access at `addr` within its 32-bit register
+fn lane(addr: usize, width: Width) -> (u32, u32) {
+    let shift = (addr % 4) as u32 * 8;
+    let mask = match width {
+        Width::W32 => u32::MAX,
+        _ => (1 << width.bits()) - 1,
+    };
+    (shift, mask)
+}
+
+impl FakeRegs {
+    fn find(&self, word: usize) -> Option<&FakeReg> {
+        self.regs.iter().find(|reg| reg.addr.load(Ordering::Acquire) == word)
+    }
+
+    // Slot of a register, taken on first use, `None` once every slot is taken
+    fn slot(&self, word: usize) -> Option<&FakeReg> {
+        if let Some(reg) = self.find(word) {
+            return Some(reg);
+        }
+        let reg = self.regs.iter().find(|reg| {
+            reg.addr.compare_exchange(0, word, Ordering::AcqRel, Ordering::Acquire).is_ok()
+        });
+        if reg.is_none() {
+            pr_err!("selftest: out of fake registers\n");
+        }
+        reg
+    }
+
+    // Read a register without side effects
+    pub(crate) fn peek(&self, addr: usize, width: Width) -> u32 {
+        let (shift, mask) = lane(addr, width);
+        self.find(addr & !3).map_or(0, |reg| reg.val.load(Ordering::Acquire) >> shift & mask)
+    }
+
+    // Set a register without counting the write, e.g. to model a reset default
+    pub(crate) fn poke(&self, val: u32, addr: usize, width: Width) {
+        let (shift, mask) = lane(addr, width);
+        if let Some(reg) = self.slot(addr & !3) {
+            let old = reg.val.load(Ordering::Acquire);
+            reg.val.store(old & !(mask << shift) | (val & mask) << shift, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn writes(&self) -> usize {
+        self.writes.load(Ordering::Acquire)
+    }
+
+    // Make the next `count` writes get lost
+    pub(crate) fn drop_writes(&self, count: usize) {
+        self.dropped.store(count, Ordering::Release);
+    }
+
+    // Have the hardware set the bits of `mask` in the 32-bit register at `addr`
+    // to those of `released` right after each read, like a self-clearing line
+    pub(crate) fn release_on_read(&self, addr: usize, mask: u32, released: u32) {
+        self.release_mask.store(mask, Ordering::Release);
+        self.release_val.store(released, Ordering::Release);
+        self.release_addr.store(addr, Ordering::Release);
+    }
+
+    fn clear(&self) {
+        for reg in self.regs.iter() {
+            reg.val.store(0, Ordering::Release);
+            reg.addr.store(0, Ordering::Release);
+        }
+        self.writes.store(0, Ordering::Release);
+        self.dropped.store(0, Ordering::Release);
+        self.release_addr.store(0, Ordering::Release);
+    }
+}
+
+impl RegAccess for FakeRegs {
+    fn read(&self, addr: usize, width: Width) -> u32 {
+        let val = self.peek(addr, width);
+        // Traced like the MMIO accessors trace theirs
+        #[cfg(feature = "mmio-trace")]
+        mmio_trace::record(mmio_trace::Dir::Read, addr, val, width.bits() as u8);
+        if self.release_addr.load(Ordering::Acquire) == addr & !3 {
+            let mask = self.release_mask.load(Ordering::Acquire);
+            let old = self.peek(addr & !3, Width::W32);
+            self.poke(old & !mask | self.release_val.load(Ordering::Acquire) & mask, addr & !3, Width::W32);
+        }
+        val
+    }
+
+    fn write(&self, val: u32, addr: usize, width: Width) {
+        #[cfg(feature = "mmio-trace")]
+        mmio_trace::record(mmio_trace::Dir::Write, addr, val, width.bits() as u8);
+        self.writes.fetch_add(1, Ordering::AcqRel);
+        let dropped = self.dropped.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        if dropped.is_err() {
+            self.poke(val, addr, width);
+        }
+    }
+
+    fn contains(&self, addr: usize, bytes: usize) -> bool {
+        addr >= FAKE_EXTRA && addr + bytes <= FAKE_END
+    }
+}
+
+// Most sleeps recorded, later ones are only counted
+const MAX_RECORDED: usize = 32;
+
+const NO_SLEEP: AtomicU64 = AtomicU64::new(0);
+
+// Delay provider of the fake, recording the duration of each sleep instead of
+// sleeping. Polling loops still end on their deadlines, in real time.
+pub(crate) struct RecordingDelay {
+    sleeps: [AtomicU64; MAX_RECORDED],
+    count: AtomicUsize,
+}
+
+pub(crate) static RECORDING_DELAY: RecordingDelay =
+    RecordingDelay { sleeps: [NO_SLEEP; MAX_RECORDED], count: AtomicUsize::new(0) };
+
+impl RecordingDelay {
+    pub(crate) fn clear(&self) {
+        self.count.store(0, Ordering::Release);
+    }
+
+    // Sleeps since the last `clear`, in microseconds
+    pub(crate) fn recorded(&self) -> Result<Vec<u64>> {
+        let count = self.count.load(Ordering::Acquire).min(MAX_RECORDED);
+        let mut sleeps = Vec::try_with_capacity(count)?;
+        for sleep in self.sleeps[..count].iter() {
+            sleeps.try_push(sleep.load(Ordering::Acquire))?;
+        }
+        Ok(sleeps)
+    }
+}
+
+impl DelayProvider for RecordingDelay {
+    fn sleep(&self, duration: Duration) {
+        let index = self.count.fetch_add(1, Ordering::AcqRel);
+        if let Some(sleep) = self.sleeps.get(index) {
+            sleep.store(duration.as_micros() as u64, Ordering::Release);
+        }
+    }
+}
+
+// Writes `FAKE_REGS` had seen when a `FakeClock` was last enabled, and whether
+// its enabled clock has been dropped since
+pub(crate) static CLOCK_ENABLED_AT: AtomicUsize = AtomicUsize::new(usize::MAX);
+pub(crate) static CLOCK_DISABLED: AtomicBool = AtomicBool::new(false);
+
+// Clock of a block on the fake, recording when it is enabled and disabled.
+// With `fails` it fails to enable, with EIO.
+pub(crate) struct FakeClock {
+    pub(crate) fails: bool,
+}
+
+pub(crate) struct EnabledFakeClock;
+
+impl BlockClock for FakeClock {
+    type Enabled = EnabledFakeClock;
+
+    fn enable(self) -> Result<EnabledFakeClock> {
+        if self.fails {
+            return Err(error::code::EIO);
+        }
+        CLOCK_ENABLED_AT.store(FAKE_REGS.writes(), Ordering::Release);
+        CLOCK_DISABLED.store(false, Ordering::Release);
+        Ok(EnabledFakeClock)
+    }
+}
+
+impl Drop for EnabledFakeClock {
+    fn drop(&mut self) {
+        CLOCK_DISABLED.store(true, Ordering::Release);
+    }
+}
+
+// Only one fake exists at a time, they share `FAKE_REGS` and `RECORDING_DELAY`
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+// A controller on `FAKE_REGS`, ready for ops as soon as it is built
+pub(crate) struct Fake(Arc<ResetDeviceData>);
+
+impl Fake {
+    // Build a fake controller, `setup` configures it before anything can run on
+    // it: locks are not usable yet, only fields are set
+    pub(crate) fn new(pdev: &platform::Device, setup: impl FnOnce(&mut BstMap) -> Result) -> Result<Self> {
+        if IN_USE.swap(true, Ordering::AcqRel) {
+            return Err(error::code::EBUSY);
+        }
+        FAKE_REGS.clear();
+        RECORDING_DELAY.clear();
+        CLOCK_ENABLED_AT.store(usize::MAX, Ordering::Release);
+        CLOCK_DISABLED.store(false, Ordering::Release);
+        match build(pdev, setup) {
+            Ok(data) => Ok(Fake(data)),
+            Err(e) => {
+                IN_USE.store(false, Ordering::Release);
+                Err(e)
+            }
+        }
+    }
+
+    // A fake controller as probe would build it without any optional property
+    pub(crate) fn plain(pdev: &platform::Device) -> Result<Self> {
+        Self::new(pdev, |_| Ok(()))
+    }
+
+    // The fake as a consumer of the consumer API sees it
+    pub(crate) fn controller(&self, consumer: &'static CStr) -> Controller {
+        Controller::new(self.0.clone(), consumer)
+    }
+
+    // The controller data, as the periodic jobs run on it
+    pub(crate) fn data(&self) -> &ResetDeviceData {
+        &self.0
+    }
+
+    // Raise the completion interrupt the way the IRQ core would, true when the
+    // handler reports it handled
+    pub(crate) fn inject_irq(&self) -> bool {
+        let cookie = &**self as *const BstMap as *mut c_void;
+        // SAFETY: The cookie is the `BstMap` of this fake, which outlives the call.
+        unsafe { irq::handler(0, cookie) == bindings::irqreturn_IRQ_HANDLED }
+    }
+}
+
+fn build(pdev: &platform::Device, setup: impl FnOnce(&mut BstMap) -> Result) -> Result<Arc<ResetDeviceData>> {
+    let mut bases = [None; MAX_RESET_REGISTERS];
+    for (window, base) in bases.iter_mut().enumerate().take(A1000B_REGISTERS) {
+        *base = Some((FAKE_BASE + window * FAKE_WINDOW_SIZE) as *mut u8);
+    }
+    let window_size = [FAKE_WINDOW_SIZE; MAX_RESET_REGISTERS];
+    let manager = build_a1000b(bases);
+    let nr_lines = manager.bsta1000b_map.len();
+    let mut lines = Vec::try_with_capacity(nr_lines)?;
+    let mut state = Vec::try_with_capacity(nr_lines)?;
+    for _ in 0..nr_lines {
+        lines.try_push(LineConfig::default())?;
+        state.try_push(LineState::default())?;
+    }
+    place_lines(&manager, &bases, &window_size, &mut lines)?;
+
+    let mut data = BstMap {
+        dev: device::Device::from_dev(pdev),
+        reg_lock: uninit_lock!(SpinLock, ()),
+        status_lock: uninit_lock!(StatusLock, ()),
+        reset_lock: uninit_lock!(ResetLock, ()),
+        #[cfg(debug_assertions)]
+        reg_lock_owner: AtomicI32::new(-1),
+        #[cfg(debug_assertions)]
+        reg_lock_violations: AtomicU32::new(0),
+        #[cfg(debug_assertions)]
+        status_warned: AtomicBool::new(false),
+        bst_address: bases,
+        phys_base: [None; MAX_RESET_REGISTERS],
+        window_size,
+        set_clr: [None; MAX_RESET_REGISTERS],
+        lazy_windows: 0,
+        read_only_windows: 0,
+        lazy_base: Default::default(),
+        lazy_lock: uninit_lock!(Mutex, ()),
+        manager,
+        maps: [lines, Vec::new()],
+        active_map: AtomicUsize::new(0),
+        write_retries: 0,
+        ready_timeout_ms: 10,
+        reset_timeout_ms: 1000,
+        op_timeout_ms: 0,
+        storm: None,
+        clock_settle_ms: 0,
+        hold_time_ms: 1,
+        flush_posted_writes: false,
+        regs: &FAKE_REGS,
+        delay: &RECORDING_DELAY,
+        shadow_check: false,
+        status_mirror_strict: false,
+        strict: false,
+        release_leaked_holds: false,
+        state,
+        aliases: Vec::new(),
+        safe_state: Vec::new(),
+        level_shifter: LevelShifter { gpio: core::ptr::null_mut(), bit: None, enabled: true },
+        power_domain: None,
+        direct_cells: false,
+        cache_status_suspended: false,
+        holds: uninit_lock!(Mutex, Vec::new()),
+        acquired: uninit_lock!(Mutex, Vec::new()),
+        pins: uninit_lock!(Mutex, Vec::new()),
+        op_log: uninit_lock!(SpinLock, OpLog::new(16)?),
+        post_reset: uninit_lock!(Mutex, Vec::new()),
+        phase_hooks: uninit_lock!(SpinLock, [None; Phase::COUNT]),
+        heartbeat: AtomicU64::new(0),
+        #[cfg(debug_assertions)]
+        last_trace: Default::default(),
+        #[cfg(feature = "replay")]
+        replay: uninit_lock!(Mutex, Vec::new()),
+        async_work: work::Work::new(),
+        completion: irq::Completion::new(),
+        irq_waiting: AtomicUsize::new(0),
+        gated: AtomicBool::new(false),
+        transaction: AtomicU32::new(0),
+        ready: AtomicBool::new(true),
+        reset_source: None,
+        self_reset: None,
+    };
+    setup(&mut data)?;
+    let mut data = kernel::new_device_data!(ResetRegistration::<BstResetDriver>::new(), (), data, "Fake")?;
+    // SAFETY: General part of the data is pinned when `data` is.
+    unsafe { data.as_mut().map_unchecked_mut(|d| &mut **d) }.init_pinned();
+    Ok(Arc::from(data))
+}
+
+impl Deref for Fake {
+    type Target = BstMap;
+
+    fn deref(&self) -> &BstMap {
+        &self.0
+    }
+}
+
+impl Drop for Fake {
+    fn drop(&mut self) {
+        self.0.stop_async_resets();
+        IN_USE.store(false, Ordering::Release);
+    }
+}
+
+// The first `count` lines of the map without driver flags, each in a register
+// of its own so driving one never touches another
+pub(crate) fn plain_lines(data: &BstMap, count: usize) -> Result<Vec<usize>> {
+    let mut ids: Vec<usize> = Vec::new();
+    for (rst_id, _) in data.defined() {
+        if ids.len() == count {
+            break;
+        }
+        let cfg = &data.lines()[rst_id];
+        let shares = ids.iter().any(|id| data.lines()[*id].addr == cfg.addr);
+        if cfg.window.is_some() && cfg.flags == 0 && cfg.partner.is_none() && !shares {
+            ids.try_push(rst_id)?;
+        }
+    }
+    if ids.len() < count {
+        pr_err!("selftest: the map has fewer than {} plain lines\n", count);
+        return Err(error::code::ENODEV);
+    }
+    Ok(ids)
+}
+
+// Two lines of the map sharing a control register, if any
+pub(crate) fn shared_register(data: &BstMap) -> Option<(usize, usize)> {
+    let lines = data.lines();
+    data.defined().find_map(|(a, _)| {
+        let addr = lines[a].addr;
+        data.defined()
+            .find(|(b, _)| *b > a && lines[*b].addr == addr)
+            .map(|(b, _)| (a, b))
+            .filter(|_| lines[a].window.is_some())
+    })
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-2.0
+//! sysfs interface of the reset controller
+
+use kernel::{
+    bindings,
+    device::RawDevice,
+    error,
+    prelude::*,
+    str::CStr,
+    PAGE_SIZE,
+};
+
+use core::{
+    ffi::c_char,
+    fmt::{self, Write},
+    sync::atomic::Ordering,
+};
+
+use crate::{BstMap, OpKind, ResetDeviceData};
+
+// An attribute of the controller device, handled with the controller data
+pub(crate) trait Attribute {
+    // Render the attribute, write-only attributes reject reads
+    fn show(_data: &ResetDeviceData, _out: &mut Buffer) -> Result {
+        Err(error::code::EPERM)
+    }
+
+    // Handle a write, read-only attributes reject it
+    fn store(_data: &ResetDeviceData, _input: &[u8]) -> Result {
+        Err(error::code::EPERM)
+    }
+}
+
+// Page handed to a sysfs read, output past its end is dropped
+pub(crate) struct Buffer {
+    buf: *mut u8,
+    len: usize,
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(PAGE_SIZE - self.len);
+        // SAFETY: `buf` is a page and `len + count` stays within it.
+        unsafe { core::ptr::copy_nonoverlapping(s.as_ptr(), self.buf.add(self.len), count) };
+        self.len += count;
+        Ok(())
+    }
+}
+
+// device_attribute with every field unset
+const ZEROED_ATTR: bindings::device_attribute =
+    // SAFETY: All-zero bytes are a valid `device_attribute`: null pointers and `None` callbacks.
+    unsafe { core::mem::transmute([0u8; core::mem::size_of::<bindings::device_attribute>()]) };
+
+// An attribute together with the data its callbacks need
+struct Node {
+    attr: bindings::device_attribute,
+    data: *const ResetDeviceData,
+}
+
+// Data of the attribute `attr` is embedded in
+//
+// # Safety
+//
+// `attr` must be the attribute of a `Node` created by `File::new`.
+unsafe fn node_data<'a>(attr: *mut bindings::device_attribute) -> &'a ResetDeviceData {
+    // SAFETY: `attr` is embedded in a `Node` that lives as long as the file.
+    let node = unsafe { &*kernel::container_of!(attr, Node, attr) };
+    // SAFETY: `data` outlives the file, see `File::new`.
+    unsafe { &*node.data }
+}
+
+// SAFETY: Called by sysfs with the `attr` registered by `File::new` and a page sized buffer.
+unsafe extern "C" fn show_callback<T: Attribute>(
+    _dev: *mut bindings::device,
+    attr: *mut bindings::device_attribute,
+    buf: *mut c_char,
+) -> isize {
+    // SAFETY: `attr` was registered by `File::new`.
+    let data = unsafe { node_data(attr) };
+    let mut out = Buffer { buf: buf as *mut u8, len: 0 };
+    match T::show(data, &mut out) {
+        Ok(()) => out.len as isize,
+        Err(e) => e.to_kernel_errno() as isize,
+    }
+}
+
+// SAFETY: Called by sysfs with the `attr` registered by `File::new` and a
+// buffer of `count` bytes.
+unsafe extern "C" fn store_callback<T: Attribute>(
+    _dev: *mut bindings::device,
+    attr: *mut bindings::device_attribute,
+    buf: *const c_char,
+    count: usize,
+) -> isize {
+    // SAFETY: `attr` was registered by `File::new`.
+    let data = unsafe { node_data(attr) };
+    // SAFETY: sysfs passes a buffer of `count` bytes.
+    let input = unsafe { core::slice::from_raw_parts(buf as *const u8, count) };
+    match T::store(data, input) {
+        Ok(()) => count as isize,
+        Err(e) => e.to_kernel_errno() as isize,
+    }
+}
+
+// An attribute of a device, removed on drop
+pub(crate) struct File {
+    dev: *mut bindings::device,
+    node: Box<Node>,
+}
+
+impl File {
+    // Create an attribute whose callbacks receive `data`, which must outlive the file.
+    // Only the callbacks the mode allows are installed.
+    pub(crate) fn new<T: Attribute>(
+        dev: &impl RawDevice,
+        name: &'static CStr,
+        mode: u16,
+        data: &ResetDeviceData,
+    ) -> Result<Self> {
+        let mut node = Box::try_new(Node { attr: ZEROED_ATTR, data })?;
+        node.attr.attr.name = name.as_char_ptr();
+        node.attr.attr.mode = mode;
+        if mode & 0o444 != 0 {
+            node.attr.show = Some(show_callback::<T>);
+        }
+        if mode & 0o222 != 0 {
+            node.attr.store = Some(store_callback::<T>);
+        }
+
+        let dev = dev.raw_device();
+        // SAFETY: `dev` is a live device and `node` is boxed, so the attribute does not move.
+        let ret = unsafe { bindings::device_create_file(dev, &node.attr) };
+        if ret < 0 {
+            return Err(error::Error::from_kernel_errno(ret));
+        }
+        Ok(File { dev, node })
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // SAFETY: The attribute was created on `dev` by `new`.
+        unsafe { bindings::device_remove_file(self.dev, &self.node.attr) };
+    }
+}
+
+// SAFETY: The device pointer is only used to remove the file, which sysfs serializes.
+unsafe impl Send for File {}
+// SAFETY: `File` has no interior mutability.
+unsafe impl Sync for File {}
+
+// sysfs `reset`: write a reset id to run `reset()` on that line, for field
+// recovery without a consumer driver. Only created with `bst,reset-trigger`.
+pub(crate) struct ResetTrigger;
+
+impl Attribute for ResetTrigger {
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        let rst_id: u64 = input.trim().parse().map_err(|_| error::code::EINVAL)?;
+        dev_warn!(data.dev, "Reset of ID {} requested through sysfs\n", rst_id);
+        data.op(OpKind::Reset, rst_id, BstMap::reset_and_wait_ready)
+    }
+}
+
+// sysfs `gate`: reads 1 while reset ops are gated, write 1 to gate them and 0
+// to let them through again
+pub(crate) struct Gate;
+
+impl Attribute for Gate {
+    fn show(data: &ResetDeviceData, out: &mut Buffer) -> Result {
+        writeln!(out, "{}", data.gated.load(Ordering::Acquire) as u32)?;
+        Ok(())
+    }
+
+    fn store(data: &ResetDeviceData, input: &[u8]) -> Result {
+        let input = core::str::from_utf8(input).map_err(|_| error::code::EINVAL)?;
+        match input.trim() {
+            "1" => data.set_gate(true),
+            "0" => data.set_gate(false),
+            _ => return Err(error::code::EINVAL),
+        }
+        Ok(())
+    }
+}
+
+// sysfs `heartbeat`: count of successful ops, for health monitors to check that
+// the reset subsystem is responsive
+pub(crate) struct Heartbeat;
+
+impl Attribute for Heartbeat {
+    fn show(data: &ResetDeviceData, out: &mut Buffer) -> Result {
+        writeln!(out, "{}", data.heartbeat.load(Ordering::Relaxed))?;
+        Ok(())
+    }
+}
+
+// sysfs `capabilities`: driver version on the first line, then the features of
+// this instance, from the build and from DT, so tools can adapt to it
+pub(crate) struct Capabilities;
+
+impl Attribute for Capabilities {
+    fn show(data: &ResetDeviceData, out: &mut Buffer) -> Result {
+        writeln!(out, "{}", env!("CARGO_PKG_VERSION"))?;
+        let mut sep = "";
+        for (name, _) in data.features().iter().filter(|(_, enabled)| *enabled) {
+            write!(out, "{}{}", sep, name)?;
+            sep = " ";
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Deferred and periodic work of the reset controller, run on the system workqueue
+
+use kernel::{
+    bindings,
+    prelude::*,
+};
+
+use core::cell::UnsafeCell;
+
+use crate::ResetDeviceData;
+
+// A job run periodically with the controller data, its failures are its own to log
+pub(crate) trait Job {
+    fn run(&mut self, data: &ResetDeviceData);
+}
+
+// Callback of a work item
+pub(crate) type WorkFn = unsafe extern "C" fn(*mut bindings::work_struct);
+
+// Set up a work item to run `func`, what `INIT_WORK` does, which is a macro
+// bindgen cannot see.
+//
+// # Safety
+//
+// `work` must be valid and must not move once initialized.
+unsafe fn init_work(work: *mut bindings::work_struct, func: WorkFn) {
+    // SAFETY: `work` is valid, see the function's safety requirements.
+    unsafe {
+        (*work).data.counter = bindings::WORK_STRUCT_NO_POOL as _;
+        let entry = core::ptr::addr_of_mut!((*work).entry);
+        (*entry).next = entry;
+        (*entry).prev = entry;
+        (*work).func = Some(func);
+    }
+}
+
+// A work item embedded in the controller data. Its callback finds the data
+// back with `container_of!`.
+pub(crate) struct Work(UnsafeCell<bindings::work_struct>);
+
+impl Work {
+    // A work item that must be initialized with `init` before it is queued
+    pub(crate) fn new() -> Self {
+        // SAFETY: All-zero bytes are a valid `work_struct`, it is initialized by `init`.
+        Work(UnsafeCell::new(unsafe { core::mem::zeroed() }))
+    }
+
+    // Set the callback of the work item
+    //
+    // # Safety
+    //
+    // The work item must not move anymore, e.g. because it is pinned.
+    pub(crate) unsafe fn init(&self, func: WorkFn) {
+        // SAFETY: The work item does not move, see the function's safety requirements.
+        unsafe { init_work(self.0.get(), func) };
+    }
+
+    // Queue the work item, false when it is already queued and not running yet
+    pub(crate) fn queue(&self) -> bool {
+        // SAFETY: The work item was initialized by `init` and `system_wq` lives forever.
+        unsafe {
+            bindings::queue_work_on(bindings::WORK_CPU_UNBOUND as _, bindings::system_wq, self.0.get())
+        }
+    }
+
+    // Cancel the work item and wait for a run in progress
+    pub(crate) fn cancel_sync(&self) {
+        // SAFETY: The work item was initialized by `init`.
+        unsafe { bindings::cancel_work_sync(self.0.get()) };
+    }
+}
+
+// SAFETY: Queueing and cancelling are serialized by the workqueue.
+unsafe impl Send for Work {}
+// SAFETY: As above.
+unsafe impl Sync for Work {}
+
+// delayed_work with every field unset
+const ZEROED_WORK: bindings::delayed_work =
+    // SAFETY: All-zero bytes are a valid `delayed_work`, it is initialized by `Periodic::start`.
+    unsafe { core::mem::transmute([0u8; core::mem::size_of::<bindings::delayed_work>()]) };
+
+// A delayed work together with the job it runs and the data the job needs
+struct Node<T> {
+    work: UnsafeCell<bindings::delayed_work>,
+    data: *const ResetDeviceData,
+    interval: core::ffi::c_ulong,
+    job: UnsafeCell<T>,
+}
+
+impl<T: Job> Node<T> {
+    fn queue(&self) {
+        // SAFETY: `work` was initialized by `Periodic::start` and `system_wq` lives forever.
+        unsafe {
+            bindings::queue_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                bindings::system_wq,
+                self.work.get(),
+                self.interval,
+            )
+        };
+    }
+}
+
+// SAFETY: Called by the workqueue with the work queued by `Node::queue`.
+unsafe extern "C" fn work_callback<T: Job>(work: *mut bindings::work_struct) {
+    // SAFETY: `work` is embedded in the `delayed_work` of a `Node<T>`, which lives
+    // until the work is cancelled.
+    let node = unsafe {
+        let dwork = kernel::container_of!(work, bindings::delayed_work, work);
+        &*kernel::container_of!(dwork, Node<T>, work)
+    };
+    // SAFETY: A work item never runs concurrently with itself, so the job has a
+    // single user, and `data` outlives the work, see `Periodic::start`.
+    unsafe { (*node.job.get()).run(&*node.data) };
+    node.queue();
+}
+
+// A job run every interval until stopped or dropped
+pub(crate) struct Periodic<T: Job>(Box<Node<T>>);
+
+impl<T: Job> Periodic<T> {
+    // Run `job` every `interval_ms`, first after one interval. `data` must outlive
+    // the returned value.
+    pub(crate) fn start(job: T, interval_ms: u32, data: &ResetDeviceData) -> Result<Self> {
+        let node = Box::try_new(Node {
+            work: UnsafeCell::new(ZEROED_WORK),
+            data,
+            // SAFETY: Pure conversion without preconditions.
+            interval: unsafe { bindings::__msecs_to_jiffies(interval_ms) },
+            job: UnsafeCell::new(job),
+        })?;
+        let dwork = node.work.get();
+        // SAFETY: `dwork` is boxed so it does not move. This is `INIT_DELAYED_WORK`,
+        // which is a macro bindgen cannot see.
+        unsafe {
+            init_work(core::ptr::addr_of_mut!((*dwork).work), work_callback::<T>);
+            bindings::init_timer_key(
+                &mut (*dwork).timer,
+                Some(bindings::delayed_work_timer_fn),
+                bindings::TIMER_IRQSAFE,
+                core::ptr::null(),
+                core::ptr::null_mut(),
+            );
+        }
+        node.queue();
+        Ok(Periodic(node))
+    }
+
+    // Cancel the job and wait for a pass in progress, it is not run again
+    pub(crate) fn stop(&self) {
+        // SAFETY: The work was initialized by `start`, cancelling also stops it
+        // from requeueing itself.
+        unsafe { bindings::cancel_delayed_work_sync(self.0.work.get()) };
+    }
+}
+
+impl<T: Job> Drop for Periodic<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// SAFETY: The job only runs on the workqueue, one pass at a time.
+unsafe impl<T: Job + Send> Send for Periodic<T> {}
+// SAFETY: The only shared access is `stop`, which the workqueue serializes.
+unsafe impl<T: Job + Send> Sync for Periodic<T> {}